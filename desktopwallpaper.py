@@ -31,11 +31,18 @@
 #
 
 import os
+import json
+import logging
 import math
 import random
 import struct
+import time
 import zlib
 
+# Warnings about fallbacks, such as a cache that can't be written, and
+# the preview server's requests are reported to this logger.
+_logger = logging.getLogger("desktopwallpaper")
+
 def _listdir(p):
     return [os.path.abspath(p + "/" + x) for x in os.listdir(p)]
 
@@ -768,31 +775,340 @@ def writeppm(f, image, width, height, raiseIfExists=False):
     fd.write(bytes(image))
     fd.close()
 
-def writepng(f, image, width, height, raiseIfExists=False, alpha=False):
+def _pngchunk(tag, data):
+    chunk = tag + data
+    return struct.pack(">L", len(data)) + chunk + struct.pack(">L", zlib.crc32(chunk))
+
+def _pngheader(width, height, alpha, pixelaspect):
+    ret = b"\x89PNG\x0d\n\x1a\n" + _pngchunk(
+        b"IHDR", struct.pack(">LLbbbbb", width, height, 8, 6 if alpha else 2, 0, 0, 0)
+    )
+    if pixelaspect:
+        # Pixels per unit horizontally and vertically, with an unknown unit
+        phys = struct.pack(">LLb", pixelaspect[1], pixelaspect[0], 0)
+        ret += _pngchunk(b"pHYs", phys)
+    return ret
+
+def pngbytes(image, width, height, alpha=False, pixelaspect=None):
+    # Encodes an image as a PNG file and returns the file's contents as
+    # a byte string; see writepng().
     if not image:
         raise ValueError
     if len(image) != width * height * (4 if alpha else 3):
         raise ValueError("len=%d width=%d height=%d" % (len(image), width, height))
+    # Compress one row at a time, rather than building a filtered copy
+    # of the whole image first
+    comp = zlib.compressobj()
+    data = []
+    stride = width * (4 if alpha else 3)
+    for y in range(height):
+        data.append(comp.compress(b"\0" + bytes(image[y * stride : (y + 1) * stride])))
+    data.append(comp.flush())
+    return (
+        _pngheader(width, height, alpha, pixelaspect)
+        + _pngchunk(b"IDAT", b"".join(data))
+        + _pngchunk(b"IEND", b"")
+    )
+
+def writepng(
+    f, image, width, height, raiseIfExists=False, alpha=False, pixelaspect=None
+):
+    # 'pixelaspect' is the pixel aspect ratio (see correctaspect()) to
+    # record in the file, or None to record none.
+    data = pngbytes(image, width, height, alpha, pixelaspect)
     fd = open(f, "xb" if raiseIfExists else "wb")
-    fd.write(b"\x89PNG\x0d\n\x1a\n")
-    chunk = b"IHDR" + struct.pack(
-        ">LLbbbbb", width, height, 8, 6 if alpha else 2, 0, 0, 0
+    fd.write(data)
+    fd.close()
+
+# Streaming writers
+
+# These classes write an image to a file a few rows at a time, so that
+# a very large image (such as a wallpaper spanning several monitors)
+# can be rendered and written strip by strip without holding the whole
+# image in memory.  Call writerows() with the rows in order, from the top,
+# then close(); both classes can also be used in a 'with' statement.
+# 'rows' is a list of the components of one or more whole rows, in the
+# same order as in an image.
+
+class PngStreamWriter:
+    def __init__(
+        self, f, width, height, raiseIfExists=False, alpha=False, pixelaspect=None
+    ):
+        if width <= 0 or height <= 0:
+            raise ValueError
+        self.width = width
+        self.height = height
+        self.stride = width * (4 if alpha else 3)
+        self.rowswritten = 0
+        self.comp = zlib.compressobj()
+        self.pending = []
+        self.pendingsize = 0
+        self.fd = open(f, "xb" if raiseIfExists else "wb")
+        self.fd.write(_pngheader(width, height, alpha, pixelaspect))
+
+    def _emit(self, data, force=False):
+        if data:
+            self.pending.append(data)
+            self.pendingsize += len(data)
+        # Write compressed data in IDAT chunks of about 64 KiB
+        if self.pendingsize >= 65536 or (force and self.pendingsize > 0):
+            self.fd.write(_pngchunk(b"IDAT", b"".join(self.pending)))
+            self.pending = []
+            self.pendingsize = 0
+
+    def writerows(self, rows):
+        if len(rows) % self.stride != 0:
+            raise ValueError("not a whole number of rows")
+        n = len(rows) // self.stride
+        if self.rowswritten + n > self.height:
+            raise ValueError("too many rows")
+        for y in range(n):
+            row = rows[y * self.stride : (y + 1) * self.stride]
+            self._emit(self.comp.compress(b"\0" + bytes(row)))
+        self.rowswritten += n
+
+    def close(self):
+        if self.fd is None:
+            return
+        complete = self.rowswritten == self.height
+        if complete:
+            self._emit(self.comp.flush(), True)
+            self.fd.write(_pngchunk(b"IEND", b""))
+        self.fd.close()
+        self.fd = None
+        if not complete:
+            raise ValueError(
+                "only %d of %d rows written" % (self.rowswritten, self.height)
+            )
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exctype, exc, tb):
+        if exctype is not None:
+            self.fd.close()
+            self.fd = None
+            return False
+        self.close()
+        return False
+
+def _pcxrle(data):
+    # Run-length encodes one scan line of one color plane of a PCX file
+    ret = bytearray()
+    i = 0
+    while i < len(data):
+        v = data[i]
+        n = 1
+        while n < 63 and i + n < len(data) and data[i + n] == v:
+            n += 1
+        if n > 1 or v >= 0xC0:
+            ret.append(0xC0 | n)
+        ret.append(v)
+        i += n
+    return ret
+
+class PcxStreamWriter:
+    # Writes a 24-bit ZSoft PCX file, with three color planes
+    def __init__(self, f, width, height, raiseIfExists=False):
+        # The width is limited so that the bytes per scan line, rounded
+        # up to an even number, fit in 16 bits
+        if width <= 0 or height <= 0 or width > 65534 or height > 65536:
+            raise ValueError
+        self.width = width
+        self.height = height
+        self.rowswritten = 0
+        # Bytes per plane per scan line, which must be even
+        self.bytesperline = width + (width & 1)
+        header = struct.pack(
+            "<BBBBHHHHHH",
+            10,  # ZSoft
+            5,  # version 3.0 and later
+            1,  # run-length encoding
+            8,  # bits per pixel per plane
+            0,
+            0,
+            width - 1,
+            height - 1,
+            72,
+            72,
+        )
+        header += bytes(48) + struct.pack("<BBHHHH", 0, 3, self.bytesperline, 1, 0, 0)
+        header += bytes(128 - len(header))
+        self.fd = open(f, "xb" if raiseIfExists else "wb")
+        self.fd.write(header)
+
+    def writerows(self, rows):
+        stride = self.width * 3
+        if len(rows) % stride != 0:
+            raise ValueError("not a whole number of rows")
+        n = len(rows) // stride
+        if self.rowswritten + n > self.height:
+            raise ValueError("too many rows")
+        pad = [0] * (self.bytesperline - self.width)
+        out = bytearray()
+        for y in range(n):
+            row = rows[y * stride : (y + 1) * stride]
+            for plane in range(3):
+                out += _pcxrle(list(row[plane::3]) + pad)
+        self.fd.write(out)
+        self.rowswritten += n
+
+    def close(self):
+        if self.fd is None:
+            return
+        self.fd.close()
+        self.fd = None
+        if self.rowswritten != self.height:
+            raise ValueError(
+                "only %d of %d rows written" % (self.rowswritten, self.height)
+            )
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exctype, exc, tb):
+        if exctype is not None:
+            self.fd.close()
+            self.fd = None
+            return False
+        self.close()
+        return False
+
+def writemonopcx(f, image, width, height, raiseIfExists=False, method="ordered"):
+    # Writes an image to a black-and-white ZSoft PCX file with one bit per
+    # pixel, like the monochrome patterns of classic Paintbrush.  The image
+    # is reduced to black and white with imageformat.monochromebits();
+    # 'method' is as given there.
+    import imageformat
+
+    if width <= 0 or height <= 0 or width > 65536 or height > 65536:
+        raise ValueError
+    bits = imageformat.monochromebits(image, width, height, method)
+    # Bytes per scan line, which must be even
+    bytesperline = (width + 7) // 8
+    bytesperline += bytesperline & 1
+    header = struct.pack(
+        "<BBBBHHHHHH",
+        10,  # ZSoft
+        5,  # version 3.0 and later
+        1,  # run-length encoding
+        1,  # bits per pixel per plane
+        0,
+        0,
+        width - 1,
+        height - 1,
+        72,
+        72,
     )
-    fd.write(struct.pack(">L", 0x0D))
-    fd.write(chunk)
-    fd.write(struct.pack(">L", zlib.crc32(chunk)))
-    newimage = []
-    pos = 0
+    # Palette of black (color 0) and white (color 1)
+    header += bytes(3) + bytes([255, 255, 255]) + bytes(42)
+    header += struct.pack("<BBHHHH", 0, 1, bytesperline, 1, 0, 0)
+    header += bytes(128 - len(header))
+    out = bytearray(header)
     for y in range(height):
-        newimage.append(0)
-        newimage += [image[x] for x in range(pos, pos + width * (4 if alpha else 3))]
-        pos += width * (4 if alpha else 3)
-    chunk = b"IDAT" + zlib.compress(bytes(newimage))
-    fd.write(struct.pack(">L", len(chunk) - 4))
-    fd.write(chunk)
-    fd.write(struct.pack(">L", zlib.crc32(chunk)))
-    fd.write(b"\0\0\0\0IEND\xae\x42\x60\x82")
-    fd.close()
+        row = bytearray(bytesperline)
+        for x in range(width):
+            # Set bits are white
+            if not bits[y * width + x]:
+                row[x >> 3] |= 0x80 >> (x & 7)
+        out += _pcxrle(row)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(out)
+    ff.close()
+
+def _renderstrip(job):
+    recipe, width, y0, stripheight = job
+    if isinstance(recipe, dict):
+        image = recipe["image"]
+        return wraparoundcrop(
+            image, recipe["width"], recipe["height"], 0, y0, width, stripheight
+        )
+    strip = recipe(0, y0, width, stripheight)
+    if len(strip) != width * stripheight * 3:
+        raise ValueError
+    return strip
+
+def rendertiled(recipe, width, height, tilesize, sink, processes=None):
+    # Renders an image of the given size in strips 'tilesize' rows high
+    # and passes each strip in turn to the writerows() method of 'sink'
+    # (such as a PngStreamWriter or PcxStreamWriter), so that the whole
+    # image is never held in memory at once.  The caller closes 'sink'
+    # afterwards.  'recipe' is either a wallpaper (a dictionary with the
+    # keys "image", "width", and "height"), which is tiled over the output,
+    # or a function taking four parameters, x0, y0, w, and h, that returns
+    # the part of the output image with the given position and size.
+    # If 'processes' is greater than 1, that many strips are rendered at a
+    # time in parallel using separate processes; in that case 'recipe', if
+    # a function, must be defined at the top level of a module.
+    if width <= 0 or height <= 0 or tilesize <= 0:
+        raise ValueError
+    jobs = [
+        [recipe, width, y, min(tilesize, height - y)]
+        for y in range(0, height, tilesize)
+    ]
+    if processes is None or processes <= 1:
+        for job in jobs:
+            sink.writerows(_renderstrip(job))
+        return
+    import multiprocessing
+
+    with multiprocessing.Pool(processes) as pool:
+        for i in range(0, len(jobs), processes):
+            for strip in pool.map(_renderstrip, jobs[i : i + processes]):
+                sink.writerows(strip)
+
+class MmapImage:
+    # An image whose red, green, and blue components are stored in a
+    # memory-mapped temporary file rather than a list, for intermediate
+    # images too big to hold in memory.  It can be used in place of an
+    # image list in most methods in this script: it supports len(),
+    # iteration, and getting and setting components by index or slice
+    # (slices are returned as lists).  The image starts out black.
+    # Call close() (or use a 'with' statement) to delete the temporary file.
+    def __init__(self, width, height):
+        import mmap
+        import tempfile
+
+        if width <= 0 or height <= 0:
+            raise ValueError
+        self.width = width
+        self.height = height
+        self.size = width * height * 3
+        self.file = tempfile.TemporaryFile()
+        self.file.truncate(self.size)
+        self.map = mmap.mmap(self.file.fileno(), self.size)
+
+    def __len__(self):
+        return self.size
+
+    def __getitem__(self, index):
+        if isinstance(index, slice):
+            return list(self.map[index])
+        return self.map[index]
+
+    def __setitem__(self, index, value):
+        if isinstance(index, slice):
+            self.map[index] = bytes(value)
+        else:
+            self.map[index] = value
+
+    def __iter__(self):
+        for pos in range(0, self.size, 65536):
+            for v in self.map[pos : pos + 65536]:
+                yield v
+
+    def close(self):
+        if self.map is not None:
+            self.map.close()
+            self.file.close()
+            self.map = None
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exctype, exc, tb):
+        self.close()
+        return False
 
 def simplebox(image, width, height, color, x0, y0, x1, y1):
     borderedbox(image, width, height, None, color, color, x0, y0, x1, y1)
@@ -825,34 +1141,18 @@ def hatchedbox(
     # the Windows convention is used; if False, the X pixmap convention is used.
     # 'drawborder' means to draw the box's border with the hatch color;
     # default is False.
-    if x0 < 0 or y0 < 0 or x1 < x0 or y1 < y0:
-        raise ValueError
+    # The box is drawn with rectangle(), so the corners can be given in
+    # either order.
     if width <= 0 or height <= 0:
         raise ValueError
     if (not color) or len(color) != 3:
         raise ValueError
-    if x0 == x1 or y0 == y1:
-        return
-    cr = color[0] & 0xFF
-    cg = color[1] & 0xFF
-    cb = color[2] & 0xFF
-    for y in range(y0, y1):
-        ypp = y % height
-        yp = ypp * width * 3
-        for x in range(x0, x1):
-            xp = x % width
-            c = (
-                ((pattern[ypp & 7] >> (7 - (xp & 7))) & 1)
-                if msbfirst
-                else ((pattern[ypp & 7] >> (xp & 7)) & 1)
-            )
-            if (
-                drawborder and (y == y0 or y == y1 - 1 or x == x0 or x == x1 - 1)
-            ) or c == 1:
-                # Draw hatch color
-                image[yp + xp * 3] = cr
-                image[yp + xp * 3 + 1] = cg
-                image[yp + xp * 3 + 2] = cb
+    c = [color[0] & 0xFF, color[1] & 0xFF, color[2] & 0xFF]
+    rectangle(
+        image, width, height, x0, y0, x1, y1, Brush.pattern(pattern, c, None, msbfirst)
+    )
+    if drawborder:
+        _rectangleborder(image, width, height, x0, y0, x1, y1, c)
 
 def imageblit(
     dstimage,
@@ -909,7 +1209,18 @@ def randomtiles(columns, rows, sourceImages, srcwidth, srcheight):
     return {"image": image, "width": width, "height": height}
 
 def shadowedborderedbox(
-    image, width, height, border, shadow, color1, color2, x0, y0, x1, y1
+    image,
+    width,
+    height,
+    border,
+    shadow,
+    color1,
+    color2,
+    x0,
+    y0,
+    x1,
+    y1,
+    borderthickness=1,
 ):
     if shadow:
         # Draw box's shadow
@@ -917,42 +1228,159 @@ def shadowedborderedbox(
         hatchedbox(
             image, width, height, shadow, pattern, x0 + 4, y0 + 4, x1 + 4, y1 + 4
         )
-    borderedbox(image, width, height, border, color1, color2, x0, y0, x1, y1)
+    borderedbox(
+        image, width, height, border, color1, color2, x0, y0, x1, y1, borderthickness
+    )
+
+class Brush:
+    # A brush gives the color of each pixel in a filled area, such as
+    # a box drawn with rectangle() or borderedbox().  A brush is called with
+    # the x- and y-coordinates of a pixel in the image and returns its color,
+    # or None to leave that pixel unchanged.  Brushes are created with
+    # the following methods.
+
+    def __init__(self, func):
+        self.func = func
+
+    def __call__(self, x, y):
+        return self.func(x, y)
+
+    @staticmethod
+    def solid(color):
+        # Brush that fills with a single color
+        return Brush(lambda x, y: color)
+
+    @staticmethod
+    def checker(color1, color2, cellsize=1):
+        # Brush that fills with a checkerboard of square cells of the given
+        # size, in pixels; the cell at the image's top left corner is 'color1'.
+        # 'color1' or 'color2' can be None to leave those cells unchanged.
+        # With a cell size of 1, the two colors are dithered.
+        if cellsize <= 0 or int(cellsize) != cellsize:
+            raise ValueError
+        return Brush(
+            lambda x, y: color1 if (x // cellsize + y // cellsize) % 2 == 0 else color2
+        )
 
-def borderedbox(image, width, height, border, color1, color2, x0, y0, x1, y1):
-    # Draw a wraparound dither-colored box on an image.
-    # 'border' is the color of the 1-pixel-thick border. Can be None (so
-    # that no border is drawn)
-    # 'color1' and 'color2' are the dithered
-    # versions of the inner color. 'color1' and 'color2' can't be None.
-    if x0 < 0 or y0 < 0 or x1 < x0 or y1 < y0:
-        raise ValueError
+    @staticmethod
+    def pattern(pattern, color, bgcolor=None, msbfirst=True):
+        # Brush that fills with an 8x8 pattern (see hatchedbox()), where
+        # set bits are drawn in 'color' and clear bits are drawn in 'bgcolor'
+        # (or left unchanged if 'bgcolor' is None).
+        if len(pattern) != 8:
+            raise ValueError
+
+        def func(x, y):
+            sh = (7 - (x & 7)) if msbfirst else (x & 7)
+            return color if ((pattern[y & 7] >> sh) & 1) == 1 else bgcolor
+
+        return Brush(func)
+
+    @staticmethod
+    def hatch(style, color, bgcolor=None):
+        # Brush that fills with one of the hatch styles of Windows brushes,
+        # drawn in 'color' over 'bgcolor' (or over the existing pixels if
+        # 'bgcolor' is None).  'style' is "horizontal", "vertical", "fdiagonal"
+        # (top left to bottom right), "bdiagonal" (bottom left to top right),
+        # "cross", or "diagcross".
+        styles = {
+            "horizontal": [0xFF, 0, 0, 0, 0, 0, 0, 0],
+            "vertical": [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80],
+            "fdiagonal": [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01],
+            "bdiagonal": [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80],
+            "cross": [0xFF, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80],
+            "diagcross": [0x81, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x81],
+        }
+        if style not in styles:
+            raise ValueError
+        return Brush.pattern(styles[style], color, bgcolor)
+
+def rectangle(image, width, height, x0, y0, x1, y1, brush, wraparound=True):
+    # Fills a rectangle on an image.  The rectangle's corners are (x0, y0)
+    # and (x1, y1), where the second corner is just outside the rectangle;
+    # the corners can be given in either order, and a rectangle with no
+    # area draws nothing.
+    # 'brush' is either a color (a 3-item array of the red, green, and blue
+    # components in that order), a Brush, or a function that, like a Brush,
+    # takes the x- and y-coordinates of a pixel in the image and returns
+    # its color, or None to leave that pixel unchanged.
+    # 'wraparound' means that parts of the rectangle beyond the image's edges
+    # wrap around to the other side (the default); if False, those parts
+    # are not drawn.
     if width <= 0 or height <= 0:
         raise ValueError
-    if (not color1) or (not image) or (not color2):
+    if not image:
         raise ValueError
-    if x0 == x1 or y0 == y1:
+    if x1 < x0:
+        x0, x1 = x1, x0
+    if y1 < y0:
+        y0, y1 = y1, y0
+    if wraparound:
+        # No need to draw any pixel more than once
+        x1 = min(x1, x0 + width)
+        y1 = min(y1, y0 + height)
+    else:
+        x0 = max(x0, 0)
+        y0 = max(y0, 0)
+        x1 = min(x1, width)
+        y1 = min(y1, height)
+    if x0 >= x1 or y0 >= y1:
         return
+    solid = not callable(brush)
     for y in range(y0, y1):
         ypp = y % height
         yp = ypp * width * 3
         for x in range(x0, x1):
             xp = x % width
-            if border and (y == y0 or y == y1 - 1 or x == x0 or x == x1 - 1):
-                # Draw border color
-                image[yp + xp * 3] = border[0]
-                image[yp + xp * 3 + 1] = border[1]
-                image[yp + xp * 3 + 2] = border[2]
-            elif ypp % 2 == xp % 2:
-                # Draw first color
-                image[yp + xp * 3] = color1[0]
-                image[yp + xp * 3 + 1] = color1[1]
-                image[yp + xp * 3 + 2] = color1[2]
-            else:
-                # Draw second color
-                image[yp + xp * 3] = color2[0]
-                image[yp + xp * 3 + 1] = color2[1]
-                image[yp + xp * 3 + 2] = color2[2]
+            c = brush if solid else brush(xp, ypp)
+            if c:
+                image[yp + xp * 3] = c[0]
+                image[yp + xp * 3 + 1] = c[1]
+                image[yp + xp * 3 + 2] = c[2]
+
+def _rectangleborder(image, width, height, x0, y0, x1, y1, brush, thickness=1):
+    # Draws the border of a rectangle given as in rectangle()
+    x0, x1 = min(x0, x1), max(x0, x1)
+    y0, y1 = min(y0, y1), max(y0, y1)
+    if x0 == x1 or y0 == y1:
+        return
+    # Keep the border within the rectangle
+    t = min(thickness, (x1 - x0 + 1) // 2, (y1 - y0 + 1) // 2)
+    rectangle(image, width, height, x0, y0, x1, y0 + t, brush)
+    rectangle(image, width, height, x0, y1 - t, x1, y1, brush)
+    rectangle(image, width, height, x0, y0, x0 + t, y1, brush)
+    rectangle(image, width, height, x1 - t, y0, x1, y1, brush)
+
+def borderedbox(
+    image, width, height, border, color1, color2, x0, y0, x1, y1, borderthickness=1
+):
+    # Draw a wraparound dither-colored box on an image.
+    # 'border' is the color of the border. Can be None (so
+    # that no border is drawn)
+    # 'color1' and 'color2' are the dithered
+    # versions of the inner color. 'color1' and 'color2' can't be None.
+    # Alternatively, 'color1' can be a Brush to fill the box's inside with,
+    # in which case 'color2' is ignored.
+    # 'borderthickness' is the border's thickness in pixels; default is 1.
+    # The box is drawn with rectangle(), so the corners can be given in
+    # either order.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if borderthickness <= 0 or int(borderthickness) != borderthickness:
+        raise ValueError
+    if (not color1) or (not image):
+        raise ValueError
+    if isinstance(color1, Brush):
+        brush = color1
+    elif not color2:
+        raise ValueError
+    elif color1 == color2:
+        brush = color1
+    else:
+        brush = Brush.checker(color1, color2)
+    rectangle(image, width, height, x0, y0, x1, y1, brush)
+    if border:
+        _rectangleborder(image, width, height, x0, y0, x1, y1, border, borderthickness)
 
 def blankimage(width, height, color=None):
     if color and len(color) < 3:
@@ -1001,10 +1429,119 @@ def _nearest_rgb3(pal, r, g, b):
 def _nearest_rgb(pal, rgb):
     return _nearest_rgb3(pal, rgb[0], rgb[1], rgb[2])
 
-def randomboxeslightdark(width, height, palette):
+def poissondisk(width, height, mindist, seed=None, maxpoints=None):
+    # Generates random points spread evenly over a wraparound image, so that
+    # no two points are closer than 'mindist' pixels apart (measuring
+    # distances across the image's edges as well, so that the points tile
+    # seamlessly), using Bridson's algorithm for Poisson-disk sampling.
+    # 'seed' is a seed for the random number generator; if None, different
+    # points are generated each time.
+    # 'maxpoints' is the maximum number of points to generate, or None
+    # for no maximum.
+    # Returns a list of [x, y] integer positions.
+    if width <= 0 or height <= 0 or mindist <= 0:
+        raise ValueError
+    rnd = random.Random(seed)
+    cellsize = mindist / math.sqrt(2)
+    # Cells are at most 'cellsize' wide and tall, so each holds at most
+    # one point
+    gw = max(1, int(math.ceil(width / cellsize)))
+    gh = max(1, int(math.ceil(height / cellsize)))
+    grid = [None for i in range(gw * gh)]
+    points = []
+    active = []
+
+    def cell(p):
+        return (int(p[1] * gh / height) % gh) * gw + int(p[0] * gw / width) % gw
+
+    def fits(p):
+        cx = int(p[0] * gw / width)
+        cy = int(p[1] * gh / height)
+        # Points up to 'mindist' away can be several cells away, since cells
+        # can be smaller than 'cellsize', so enough cells are checked
+        reach = int(math.ceil(mindist * max(gw / width, gh / height))) + 1
+        for yy in range(cy - reach, cy + reach + 1):
+            for xx in range(cx - reach, cx + reach + 1):
+                q = grid[(yy % gh) * gw + (xx % gw)]
+                if q:
+                    dx = abs(p[0] - q[0])
+                    dy = abs(p[1] - q[1])
+                    dx = min(dx, width - dx)
+                    dy = min(dy, height - dy)
+                    if dx * dx + dy * dy < mindist * mindist:
+                        return False
+        return True
+
+    def add(p):
+        grid[cell(p)] = p
+        points.append(p)
+        active.append(p)
+
+    add([rnd.randint(0, width - 1), rnd.randint(0, height - 1)])
+    while len(active) > 0 and (maxpoints is None or len(points) < maxpoints):
+        i = rnd.randint(0, len(active) - 1)
+        found = False
+        for k in range(30):
+            angle = rnd.random() * math.pi * 2
+            r = mindist * (1 + rnd.random())
+            p = [
+                int(active[i][0] + r * math.cos(angle)) % width,
+                int(active[i][1] + r * math.sin(angle)) % height,
+            ]
+            if grid[cell(p)] is None and fits(p):
+                add(p)
+                found = True
+                break
+        if not found:
+            active.pop(i)
+    return points
+
+def _gradientbrush(color1, color2, y0, y1, height):
+    # Brush that fills a box running from y0 to y1 with an ordered-dithered
+    # vertical gradient from 'color1' at the top to 'color2' at the bottom
+    bayer = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+    span = max(1, y1 - y0 - 1)
+
+    def func(x, y):
+        level = min(((y - y0) % height) * 16 // span, 16)
+        return color2 if level > bayer[(y & 3) * 4 + (x & 3)] else color1
+
+    return Brush(func)
+
+def _shadedbox(
+    image, width, height, style, border, fill, light, dark, x0, y0, x1, y1, thick
+):
+    # Draws a box for randomboxeslightdark() in the given shading style
+    if style == "shadow":
+        shadowedborderedbox(
+            image, width, height, border, border, fill, fill, x0, y0, x1, y1, thick
+        )
+        return
+    borderedbox(image, width, height, border, fill, fill, x0, y0, x1, y1, thick)
+    if style == "raised" or style == "sunken":
+        # Bevel just inside the border
+        hilt = light if style == "raised" else dark
+        dksh = dark if style == "raised" else light
+        bx0 = x0 + thick
+        by0 = y0 + thick
+        bx1 = x1 - thick
+        by1 = y1 - thick
+        if bx1 - bx0 >= 2 and by1 - by0 >= 2:
+            rectangle(image, width, height, bx0, by0, bx1, by0 + 1, hilt)
+            rectangle(image, width, height, bx0, by0, bx0 + 1, by1, hilt)
+            rectangle(image, width, height, bx0 + 1, by1 - 1, bx1, by1, dksh)
+            rectangle(image, width, height, bx1 - 1, by0 + 1, bx1, by1, dksh)
+
+def randomboxeslightdark(width, height, palette, shading=None, spread=False):
     # Generate two portable pixelmaps (PPM) of a tileable pattern
     # with random boxes, namely a light version and a dark version,
     # using only the colors in the given palette.
+    # 'shading' is a list of shading styles to choose from at random for
+    # each box: "flat" (no shading), "raised" or "sunken" (a 3-D bevel),
+    # "shadow" (a drop shadow), or "gradient" (a dithered vertical gradient
+    # fill).  If None, the default, all boxes are flat.
+    # 'spread' means to place the boxes evenly over the image (see
+    # poissondisk()), rather than purely at random, so that they overlap less.
     if width <= 0 or int(width) != width:
         raise ValueError
     if height <= 0 or int(height) != height:
@@ -1012,34 +1549,97 @@ def randomboxeslightdark(width, height, palette):
     if (not palette) or len(palette) <= 0 or len(palette) > 2000:
         # too long palette not supported
         raise ValueError
+    for style in shading if shading else []:
+        if style not in ["flat", "raised", "sunken", "shadow", "gradient"]:
+            raise ValueError
     darkest = palette[_nearest_rgb3(palette, 0, 0, 0)]
     lightimage = blankimage(width, height, darkest)
     darkimage = blankimage(width, height, darkest)
     paletteSize = len(palette)
     darkkeys = [palette[_nearest_rgb(palette, [x // 2 for x in c])] for c in palette]
-    for i in range(45):
-        x0 = random.randint(0, width - 1)
-        x1 = x0 + random.randint(3, max(3, width * 3 // 4))
-        y0 = random.randint(0, height - 1)
-        y1 = y0 + random.randint(3, max(3, height * 3 // 4))
+    lightkeys = [
+        palette[_nearest_rgb(palette, [(x + 255) // 2 for x in c])] for c in palette
+    ]
+    boxcount = 45
+    if spread:
+        mindist = max(4, min(width, height) // 6)
+        points = poissondisk(width, height, mindist, random.random(), boxcount)
+        boxcount = len(points)
+    for i in range(boxcount):
+        if spread:
+            x0 = points[i][0]
+            x1 = x0 + random.randint(3, max(3, mindist * 3 // 2))
+            y0 = points[i][1]
+            y1 = y0 + random.randint(3, max(3, mindist * 3 // 2))
+        else:
+            x0 = random.randint(0, width - 1)
+            x1 = x0 + random.randint(3, max(3, width * 3 // 4))
+            y0 = random.randint(0, height - 1)
+            y1 = y0 + random.randint(3, max(3, height * 3 // 4))
         border1 = random.randint(0, 5)
         border2 = random.randint(0, paletteSize - 1)
         color = random.randint(0, paletteSize - 1)
         border = border2 if border1 == 0 else 0
         c1 = palette[color]
-        shadowedborderedbox(
-            lightimage, width, height, darkest, None, c1, c1, x0, y0, x1, y1
+        d1 = darkkeys[color]
+        style = random.choice(shading) if shading else "flat"
+        fill = random.randint(0, 5)
+        if style == "gradient":
+            c1 = _gradientbrush(c1, d1, y0, y1, height)
+            d1 = _gradientbrush(d1, darkkeys[_nearest_rgb(palette, d1)], y0, y1, height)
+        elif fill == 0:
+            # Hatched box
+            hstyle = random.choice(
+                ["horizontal", "vertical", "fdiagonal"]
+                + ["bdiagonal", "cross", "diagcross"]
+            )
+            c2 = random.randint(0, paletteSize - 1)
+            c1 = Brush.hatch(hstyle, c1, palette[c2])
+            d1 = Brush.hatch(hstyle, d1, darkkeys[c2])
+        elif fill == 1:
+            # Checkered box
+            c2 = random.randint(0, paletteSize - 1)
+            size = random.choice([2, 4, 8])
+            c1 = Brush.checker(c1, palette[c2], size)
+            d1 = Brush.checker(d1, darkkeys[c2], size)
+        thick = 1 if random.randint(0, 3) > 0 else 2
+        _shadedbox(
+            lightimage,
+            width,
+            height,
+            style,
+            darkest,
+            c1,
+            lightkeys[color],
+            darkkeys[color],
+            x0,
+            y0,
+            x1,
+            y1,
+            thick,
         )
-        c1 = darkkeys[color]
-        shadowedborderedbox(
-            darkimage, width, height, darkest, None, c1, c1, x0, y0, x1, y1
+        _shadedbox(
+            darkimage,
+            width,
+            height,
+            style,
+            darkest,
+            d1,
+            palette[color],
+            darkkeys[_nearest_rgb(palette, darkkeys[color])],
+            x0,
+            y0,
+            x1,
+            y1,
+            thick,
         )
     return {"light": lightimage, "dark": darkimage}
 
-def randomboxes(width, height, palette):
+def randomboxes(width, height, palette, shading=None, spread=False):
     # Generate a portable pixelmap (PPM) of a tileable pattern with random boxes,
-    # using only the colors in the given palette
-    return randomboxeslightdark(width, height, palette)["light"]
+    # using only the colors in the given palette.
+    # 'shading' and 'spread' are as in randomboxeslightdark().
+    return randomboxeslightdark(width, height, palette, shading, spread)["light"]
 
 def crosshatch(
     hhatchdist=8, vhatchdist=8, hhatchthick=1, vhatchthick=1, fgcolor=None, bgcolor=None
@@ -1161,6 +1761,184 @@ def diaghatch(wpsize=64, stripesize=32, fgcolor=None, bgcolor=None):
 def diagrevhatch(wpsize=64, stripesize=32, fgcolor=None, bgcolor=None):
     return diagcrosshatch(wpsize, 0, stripesize, fgcolor, bgcolor)
 
+def checker(size=8, color1=None, color2=None):
+    # Generate a portable pixelmap (PPM) of a checkerboard pattern.
+    # 'size': width and height of each square, in pixels.
+    # 'color1': color of the top left square.  If None, default is black.
+    # 'color2': color of the other squares.  If None, default is white.
+    if size <= 0 or int(size) != size:
+        raise ValueError
+    width = size * 2
+    height = size * 2
+    image = blankimage(width, height, color2)
+    c1 = color1 if color1 else [0, 0, 0]
+    simplebox(image, width, height, c1, 0, 0, size, size)
+    simplebox(image, width, height, c1, size, size, width, height)
+    return {"image": image, "width": width, "height": height}
+
+def gingham(size=8, fgcolor=None, bgcolor=None):
+    # Generate a portable pixelmap (PPM) of a gingham pattern, in which
+    # horizontal and vertical bands of a color cross over a background.
+    # Squares where one band crosses another are drawn in the band color, and
+    # squares covered by one band only are drawn in a color halfway between
+    # the band color and the background color.
+    # 'size': width and height of each square, in pixels.
+    # 'fgcolor': band color.  If None, default is black.
+    # 'bgcolor': background color.  If None, default is white.
+    if size <= 0 or int(size) != size:
+        raise ValueError
+    fg = fgcolor if fgcolor else [0, 0, 0]
+    bg = bgcolor if bgcolor else [255, 255, 255]
+    half = [(fg[i] + bg[i]) // 2 for i in range(3)]
+    width = size * 2
+    height = size * 2
+    image = blankimage(width, height, bg)
+    simplebox(image, width, height, fg, 0, 0, size, size)
+    simplebox(image, width, height, half, size, 0, width, size)
+    simplebox(image, width, height, half, 0, size, size, height)
+    return {"image": image, "width": width, "height": height}
+
+def diagonalstripes(thickness, rise, run, colors):
+    # Generate a portable pixelmap (PPM) of a pattern of parallel stripes
+    # that tiles seamlessly.  Unlike diagcrosshatch(), whose stripes always run
+    # at 45 degrees, the stripes can run at any rational slope: they go
+    # 'run' pixels to the right for every 'rise' pixels down (either of which
+    # can be 0 or negative, but not both 0).  The image's size is chosen
+    # so that the stripes line up exactly where the image wraps around.
+    # 'thickness': thickness of each stripe, in pixels, measured horizontally
+    # for steep stripes and vertically for shallow stripes.
+    # 'colors': list of stripe colors, drawn in turn.
+    if thickness <= 0 or int(thickness) != thickness:
+        raise ValueError
+    if int(rise) != rise or int(run) != run or (rise == 0 and run == 0):
+        raise ValueError
+    if (not colors) or len(colors) == 0:
+        raise ValueError
+    # Each stripe is where x*rise - y*run falls within a range of 'unit'
+    # values, and that quantity repeats every 'period' values
+    unit = thickness * max(abs(rise), abs(run))
+    period = unit * len(colors)
+    width = period // math.gcd(abs(rise), period)
+    height = period // math.gcd(abs(run), period)
+    image = blankimage(width, height)
+    for y in range(height):
+        for x in range(width):
+            c = colors[((x * rise - y * run) % period) // unit]
+            pos = (y * width + x) * 3
+            image[pos] = c[0]
+            image[pos + 1] = c[1]
+            image[pos + 2] = c[2]
+    return {"image": image, "width": width, "height": height}
+
+# Scalable vector graphics (SVG) versions of some of the patterns above.
+# Each method takes the same parameters as the method it's named after and
+# returns an SVG document (as a string) with the same shapes, which can
+# be scaled to any size.
+
+def _svgcolor(c, default):
+    c = c if c else default
+    return "rgb(%d,%d,%d)" % (c[0], c[1], c[2])
+
+def _svgdocument(width, height, bgcolor, paths):
+    return (
+        "<svg width='%dpx' height='%dpx' viewBox='0 0 %d %d'"
+        % (width, height, width, height)
+        + " xmlns='http://www.w3.org/2000/svg'>"
+        + "<path style='stroke:none;fill:%s' d='M0 0H%dV%dH0Z'/>"
+        % (_svgcolor(bgcolor, [255, 255, 255]), width, height)
+        + "".join(paths)
+        + "</svg>"
+    )
+
+def crosshatchsvg(
+    hhatchdist=8, vhatchdist=8, hhatchthick=1, vhatchthick=1, fgcolor=None, bgcolor=None
+):
+    # SVG version of crosshatch()
+    if hhatchdist <= 0 or hhatchthick < 0 or hhatchthick > hhatchdist:
+        raise ValueError
+    if vhatchdist <= 0 or vhatchthick < 0 or vhatchthick > vhatchdist:
+        raise ValueError
+    if fgcolor and len(fgcolor) != 3:
+        raise ValueError
+    if bgcolor and len(bgcolor) != 3:
+        raise ValueError
+    width = vhatchdist * 4
+    height = hhatchdist * 4
+    fill = _svgcolor(fgcolor, [0, 0, 0])
+    d = ""
+    for i in range(4):
+        if hhatchthick > 0:
+            d += "M0 %dH%dV%dH0Z" % (
+                hhatchdist * i,
+                width,
+                hhatchdist * i + hhatchthick,
+            )
+        if vhatchthick > 0:
+            d += "M%d 0H%dV%dH%dZ" % (
+                vhatchdist * i,
+                vhatchdist * i + vhatchthick,
+                height,
+                vhatchdist * i,
+            )
+    paths = ["<path style='stroke:none;fill:%s' d='%s'/>" % (fill, d)] if d else []
+    return _svgdocument(width, height, bgcolor, paths)
+
+def verthatchsvg(hatchdist=8, hatchthick=1, fgcolor=None, bgcolor=None):
+    # SVG version of verthatch()
+    return crosshatchsvg(1, hatchdist, 0, hatchthick, fgcolor=fgcolor, bgcolor=bgcolor)
+
+def horizhatchsvg(hatchdist=8, hatchthick=1, fgcolor=None, bgcolor=None):
+    # SVG version of horizhatch()
+    return crosshatchsvg(hatchdist, 1, hatchthick, 0, fgcolor=fgcolor, bgcolor=bgcolor)
+
+def _diagstripesvg(width, height, stripesize, reverse):
+    # Path data for a wraparound diagonal stripe as drawn by _drawdiagstripe()
+    if stripesize == 0:
+        return ""
+    d = ""
+    x0 = -(stripesize // 2)
+    # Draw copies of the stripe shifted by the image width, so that the
+    # parts of the stripe beyond the image's edges wrap around
+    for shift in range(-1, (height + width - 1) // width + 1):
+        xs = [
+            x0 + shift * width,
+            x0 + shift * width + stripesize,
+            x0 + shift * width + stripesize + height,
+            x0 + shift * width + height,
+        ]
+        if reverse:
+            # Mirror the stripe, keeping the points in clockwise order
+            xs = [width - x for x in [xs[1], xs[0], xs[3], xs[2]]]
+        d += "M%d 0L%d 0L%d %dL%d %dZ" % (xs[0], xs[1], xs[2], height, xs[3], height)
+    return d
+
+def diagcrosshatchsvg(
+    wpsize=64, stripesize=32, revstripesize=32, fgcolor=None, bgcolor=None
+):
+    # SVG version of diagcrosshatch()
+    if stripesize > wpsize or stripesize < 0:
+        raise ValueError
+    if revstripesize > wpsize or revstripesize < 0:
+        raise ValueError
+    if wpsize <= 0 or int(wpsize) != wpsize:
+        raise ValueError
+    if fgcolor and len(fgcolor) != 3:
+        raise ValueError
+    d = _diagstripesvg(wpsize, wpsize, stripesize, False) + _diagstripesvg(
+        wpsize, wpsize, revstripesize, True
+    )
+    fill = _svgcolor(fgcolor, [0, 0, 0])
+    paths = ["<path style='stroke:none;fill:%s' d='%s'/>" % (fill, d)] if d else []
+    return _svgdocument(wpsize, wpsize, bgcolor, paths)
+
+def diaghatchsvg(wpsize=64, stripesize=32, fgcolor=None, bgcolor=None):
+    # SVG version of diaghatch()
+    return diagcrosshatchsvg(wpsize, stripesize, 0, fgcolor, bgcolor)
+
+def diagrevhatchsvg(wpsize=64, stripesize=32, fgcolor=None, bgcolor=None):
+    # SVG version of diagrevhatch()
+    return diagcrosshatchsvg(wpsize, 0, stripesize, fgcolor, bgcolor)
+
 def getgrays(palette):
     grays = 0
     for p in palette:
@@ -1310,6 +2088,40 @@ def patternDither(image, width, height, palette):
             image[xp + 1] = fcan[1]
             image[xp + 2] = fcan[2]
 
+def floydSteinbergDither(image, width, height, palette):
+    # Dithering for arbitrary color palettes using Floyd-Steinberg error
+    # diffusion, which spreads each pixel's difference from the nearest
+    # palette color to the pixels to its right and below.  Unlike
+    # patternDither(), the result doesn't tile seamlessly (error isn't carried
+    # across the image's edges), but gradients are reproduced more smoothly.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if (not palette) or len(palette) == 0:
+        raise ValueError
+    nearest = {}
+    err = [0 for i in range((width + 2) * 3)]
+    for y in range(height):
+        yp = y * width * 3
+        nexterr = [0 for i in range((width + 2) * 3)]
+        for x in range(width):
+            xp = yp + x * 3
+            # Error buffers are offset by one pixel so that x-1 is never negative
+            ep = (x + 1) * 3
+            t = [min(255, max(0, image[xp + i] + err[ep + i] // 16)) for i in range(3)]
+            key = t[0] | (t[1] << 8) | (t[2] << 16)
+            if key not in nearest:
+                nearest[key] = palette[_nearest_rgb3(palette, t[0], t[1], t[2])]
+            c = nearest[key]
+            for i in range(3):
+                e = t[i] - c[i]
+                image[xp + i] = c[i]
+                err[ep + 3 + i] += e * 7
+                nexterr[ep - 3 + i] += e * 3
+                nexterr[ep + i] += e * 5
+                nexterr[ep + 3 + i] += e
+        err = nexterr
+    return image
+
 def diaggradient(size=32):
     # Generate a portable pixelmap (PPM) of a diagonal linear gradient
     if size <= 0 or int(size) != size:
@@ -1333,10 +2145,168 @@ def colorgradient(blackColor, whiteColor):
         or len(whiteColor) != 3
     ):
         raise ValueError
-    return [
-        [blackColor[i] + (whiteColor[i] - blackColor[i]) * j // 255 for i in range(3)]
-        for j in range(256)
-    ]
+    return ColorRamp([[0, blackColor], [1, whiteColor]]).colors()
+
+class ColorRamp:
+    # A color ramp (or color gradient) made of color stops.  'stops' is a list
+    # of two-item lists, each giving a position from 0 through 1 and the
+    # color at that position (a 3-item array of the red, green, and blue
+    # components in that order).  Colors between two stops are
+    # interpolated linearly, and colors before the first stop or after the
+    # last are the same as that stop's color.
+    def __init__(self, stops):
+        if (not stops) or len(stops) == 0:
+            raise ValueError
+        for pos, c in stops:
+            if pos < 0 or pos > 1 or (not c) or len(c) != 3:
+                raise ValueError
+        self.stops = sorted(
+            [[pos, [x for x in c]] for pos, c in stops], key=lambda s: s[0]
+        )
+
+    def colorat(self, t):
+        # Gets the color at the given position (from 0 through 1)
+        return self._colorat(t * 255, 255)
+
+    def _colorat(self, v, scale):
+        # Gets the color at position v/scale
+        stops = self.stops
+        if v <= stops[0][0] * scale:
+            return [x for x in stops[0][1]]
+        for i in range(1, len(stops)):
+            p1 = stops[i][0] * scale
+            if v <= p1:
+                p0 = stops[i - 1][0] * scale
+                c0 = stops[i - 1][1]
+                c1 = stops[i][1]
+                if p1 == p0:
+                    return [x for x in c1]
+                return [
+                    int(c0[k] + (c1[k] - c0[k]) * (v - p0) // (p1 - p0))
+                    for k in range(3)
+                ]
+        return [x for x in stops[-1][1]]
+
+    def colors(self):
+        # Gets a list of 256 colors sampled evenly from the ramp, in the form
+        # taken by graymap(), so that the ramp can be used to color an image
+        # of gray tones
+        return [self._colorat(j, 255) for j in range(256)]
+
+    def _colorsgray(self):
+        # Gets the red components of colors(), for ramps of gray tones
+        return [c[0] for c in self.colors()]
+
+    def apply(self, image, width, height, palette=None):
+        # Converts an image to gray tones, then colors them using the ramp
+        # (see graymap()).  If 'palette' is given, the result is then
+        # dithered to the colors in that palette (see patternDither()).
+        # Returns 'image'.
+        graymap(image, width, height, self.colors())
+        if palette:
+            patternDither(image, width, height, palette)
+        return image
+
+def colorramp(name):
+    # Gets a color ramp with classic colors for fractals and plasma
+    # effects.  'name' is one of the following: "fire", "ice", "rainbow",
+    # "copper", "gray".
+    ramps = {
+        "fire": [
+            [0, [0, 0, 0]],
+            [0.33, [192, 0, 0]],
+            [0.67, [255, 192, 0]],
+            [1, [255, 255, 255]],
+        ],
+        "ice": [
+            [0, [0, 0, 64]],
+            [0.5, [0, 128, 255]],
+            [1, [255, 255, 255]],
+        ],
+        "rainbow": [
+            [0, [255, 0, 0]],
+            [1 / 6, [255, 255, 0]],
+            [2 / 6, [0, 255, 0]],
+            [3 / 6, [0, 255, 255]],
+            [4 / 6, [0, 0, 255]],
+            [5 / 6, [255, 0, 255]],
+            [1, [255, 0, 0]],
+        ],
+        "copper": [
+            [0, [0, 0, 0]],
+            [0.8, [255, 160, 101]],
+            [1, [255, 199, 127]],
+        ],
+        "gray": [[0, [0, 0, 0]], [1, [255, 255, 255]]],
+    }
+    if name not in ramps:
+        raise ValueError("unknown color ramp: %s" % (name))
+    return ColorRamp(ramps[name])
+
+def curvelut(points):
+    # Builds a lookup table (a list of 256 integers) from a curve through the
+    # given points, with straight lines between points.  'points' is a list of
+    # [x, y] pairs, where x is an input value and y its output value, each
+    # from 0 through 255.  Inputs before the first point or after the last
+    # are mapped to that point's output.
+    if (not points) or len(points) == 0:
+        raise ValueError
+    for x, y in points:
+        if x < 0 or x > 255 or y < 0 or y > 255:
+            raise ValueError
+    return ColorRamp([[x / 255, [y, y, y]] for x, y in points])._colorsgray()
+
+def applylut(image, width, height, lut):
+    # Maps each color component of each pixel in an image through
+    # a lookup table.  'lut' is either a list of 256 integers, used for all
+    # three components, or a list of three such lists, used for the red,
+    # green, and blue components, respectively.  Returns 'image'.
+    if len(lut) == 3:
+        luts = lut
+    elif len(lut) == 256:
+        luts = [lut, lut, lut]
+    else:
+        raise ValueError
+    for t in luts:
+        if len(t) != 256:
+            raise ValueError
+    for i in range(0, width * height * 3, 3):
+        image[i] = luts[0][image[i]]
+        image[i + 1] = luts[1][image[i + 1]]
+        image[i + 2] = luts[2][image[i + 2]]
+    return image
+
+def lutpreset(name):
+    # Gets lookup tables (see applylut()) for a classic look.
+    # 'name' is one of the following:
+    # "faded": Lighter blacks and darker whites, as in a faded print.
+    # "sepia": Brownish tones; best applied to an image of gray tones.
+    # "amber", "green": Tones of an amber or green monochrome monitor's
+    # phosphor; best applied to an image of gray tones.
+    # "contrast": Darker shadows and lighter highlights.
+    if name == "faded":
+        return curvelut([[0, 40], [128, 132], [255, 224]])
+    if name == "sepia":
+        return [
+            curvelut([[0, 20], [128, 162], [255, 255]]),
+            curvelut([[0, 10], [128, 128], [255, 240]]),
+            curvelut([[0, 0], [128, 90], [255, 200]]),
+        ]
+    if name == "amber":
+        return [
+            curvelut([[0, 0], [255, 255]]),
+            curvelut([[0, 0], [255, 176]]),
+            curvelut([[0, 0], [255, 0]]),
+        ]
+    if name == "green":
+        return [
+            curvelut([[0, 0], [255, 51]]),
+            curvelut([[0, 0], [255, 255]]),
+            curvelut([[0, 0], [255, 51]]),
+        ]
+    if name == "contrast":
+        return curvelut([[0, 0], [64, 40], [192, 216], [255, 255]])
+    raise ValueError("unknown lookup table: %s" % (name))
 
 def noiseimage(width=64, height=64):
     # Generate an image of noise
@@ -1550,6 +2520,99 @@ def brushednoise3(width, height):
             linedraw(image, width, height, [c, c, c], x, y, x1, y1)
     return image
 
+def reactiondiffusion(
+    width=64, height=64, params="coral", steps=2000, seed=None, ramp=None
+):
+    # Generates a tileable organic texture by simulating the Gray-Scott
+    # reaction-diffusion model, in which two chemicals spread across the
+    # image and react, on a grid whose edges wrap around.
+    # 'params' is the feed and kill rates of the model as a 2-item list,
+    # or one of the following named settings: "coral", "mitosis", "spots",
+    # "maze", or "worms".  'steps' is the number of simulation steps; more
+    # steps give the pattern more time to develop.  'seed' seeds the random
+    # number generator that places the starting spots.  'ramp' is a
+    # ColorRamp or the name of a color ramp (see colorramp()) used to color
+    # the result; default is "gray".
+    # Returns a dictionary with "image", "width", and "height" keys.
+    presets = {
+        "coral": [0.0545, 0.062],
+        "mitosis": [0.0367, 0.0649],
+        "spots": [0.035, 0.065],
+        "maze": [0.029, 0.057],
+        "worms": [0.078, 0.061],
+    }
+    if isinstance(params, str):
+        if params not in presets:
+            raise ValueError
+        params = presets[params]
+    feed, kill = params[0], params[1]
+    if width <= 0 or height <= 0 or steps < 0:
+        raise ValueError
+    if ramp is None:
+        ramp = "gray"
+    if isinstance(ramp, str):
+        ramp = colorramp(ramp)
+    rnd = random.Random(seed)
+    size = width * height
+    a = [1.0 for i in range(size)]
+    b = [0.0 for i in range(size)]
+    # Start with a few squares of the second chemical
+    for i in range(max(1, size // 400)):
+        cx = rnd.randrange(width)
+        cy = rnd.randrange(height)
+        for y in range(cy - 3, cy + 4):
+            for x in range(cx - 3, cx + 4):
+                b[(y % height) * width + (x % width)] = 1.0
+    # Indices of each cell's neighbors, with wraparound
+    orth = []
+    diag = []
+    for y in range(height):
+        for x in range(width):
+            xl = (x - 1) % width
+            xr = (x + 1) % width
+            yu = ((y - 1) % height) * width
+            yd = ((y + 1) % height) * width
+            yy = y * width
+            orth.append([yu + x, yd + x, yy + xl, yy + xr])
+            diag.append([yu + xl, yu + xr, yd + xl, yd + xr])
+    for step in range(steps):
+        na = [0.0 for i in range(size)]
+        nb = [0.0 for i in range(size)]
+        for i in range(size):
+            o = orth[i]
+            d = diag[i]
+            ai = a[i]
+            bi = b[i]
+            # Laplacian with weights 0.2 for orthogonal neighbors
+            # and 0.05 for diagonal ones
+            lapa = (
+                0.2 * (a[o[0]] + a[o[1]] + a[o[2]] + a[o[3]])
+                + 0.05 * (a[d[0]] + a[d[1]] + a[d[2]] + a[d[3]])
+                - ai
+            )
+            lapb = (
+                0.2 * (b[o[0]] + b[o[1]] + b[o[2]] + b[o[3]])
+                + 0.05 * (b[d[0]] + b[d[1]] + b[d[2]] + b[d[3]])
+                - bi
+            )
+            abb = ai * bi * bi
+            na[i] = min(1.0, max(0.0, ai + lapa - abb + feed * (1 - ai)))
+            nb[i] = min(1.0, max(0.0, bi + 0.5 * lapb + abb - (kill + feed) * bi))
+        a = na
+        b = nb
+    # Color by the amount of the first chemical, stretched to the full ramp
+    lo = min(a)
+    hi = max(a)
+    colors = ramp.colors()
+    image = [0 for i in range(size * 3)]
+    for i in range(size):
+        v = int((a[i] - lo) * 255 / (hi - lo)) if hi > lo else 0
+        c = colors[v]
+        image[i * 3] = c[0]
+        image[i * 3 + 1] = c[1]
+        image[i * 3 + 2] = c[2]
+    return {"image": image, "width": width, "height": height}
+
 # What follows are methods for generating scalable vector graphics (SVGs)
 # and raster graphics of classic OS style borders and button controls.
 # Although the SVGs are scalable
@@ -1591,7 +2654,9 @@ class ImageWraparoundDraw:
 
     def rect(self, x0, y0, x1, y1, c):
         if len(c) == 2:
-            borderedbox(image, width, height, None, c[0], c[1], x0, y0, x1, y1)
+            borderedbox(
+                self.image, self.width, self.height, None, c[0], c[1], x0, y0, x1, y1
+            )
         else:
             simplebox(self.image, self.width, self.height, c, x0, y0, x1, y1)
 
@@ -1951,6 +3016,7 @@ def windowborder(
         _drawinnerface(helper, x0, y0, x1, y1, face)
 
 def buttonup(
+    helper,
     x0,
     y0,
     x1,
@@ -2231,31 +3297,2106 @@ def makesvg():
     )
     return image
 
-# random wallpaper generation
+# window chrome
 
-def _randomdither(image, palette):
-    grays = getgrays(palette)
-    if len(grays) >= 2 and random.randint(0, 99) < 10:
-        # Convert to the grays in the palette
-        dithertograyimage(image["image"], image["width"], image["height"], grays)
+def _bevelcolors(scheme):
+    return [
+        scheme["ButtonHilight"],
+        scheme["ButtonLight"],
+        scheme["ButtonShadow"],
+        scheme["ButtonDkShadow"],
+    ]
+
+def _captionglyph(helper, x0, y0, x1, y1, kind, c):
+    # Draws the symbol of a caption button centered in the given box
+    cx = (x0 + x1) // 2
+    cy = (y0 + y1) // 2
+    if kind == "minimize":
+        helper.rect(cx - 4, cy + 2, cx + 2, cy + 4, c)
+    elif kind == "maximize":
+        helper.rect(cx - 5, cy - 4, cx + 4, cy - 2, c)
+        helper.rect(cx - 5, cy - 2, cx - 4, cy + 4, c)
+        helper.rect(cx + 3, cy - 2, cx + 4, cy + 4, c)
+        helper.rect(cx - 4, cy + 3, cx + 3, cy + 4, c)
+    elif kind == "restore":
+        helper.rect(cx - 3, cy - 5, cx + 3, cy - 3, c)
+        helper.rect(cx + 2, cy - 3, cx + 3, cy + 1, c)
+        helper.rect(cx - 5, cy - 2, cx + 1, cy, c)
+        helper.rect(cx - 5, cy, cx - 4, cy + 4, c)
+        helper.rect(cx, cy, cx + 1, cy + 4, c)
+        helper.rect(cx - 4, cy + 3, cx, cy + 4, c)
+    elif kind == "close":
+        for i in range(7):
+            helper.rect(cx - 4 + i, cy - 4 + i, cx - 2 + i, cy - 3 + i, c)
+            helper.rect(cx + 2 - i, cy - 4 + i, cx + 4 - i, cy - 3 + i, c)
     else:
-        # Dither away from half-and-half colors
-        halfhalfditherimage(
-            image["image"],
-            image["width"],
-            image["height"],
-            palette,
-        )
-    return image
+        raise ValueError
 
-def randomhatchimage(palette=None):
-    # Generates a random hatch image using the given palette
-    # (default is the palette in classiccolors)
-    pal = palette if palette else classiccolors()
-    expandedpal = paletteandhalfhalf(pal)
-    if random.randint(0, 99) < 50:
-        w = random.randint(40, 96)
-        w -= w % 2  # make even
+def titlebar(
+    image,
+    width,
+    height,
+    x0,
+    y0,
+    x1,
+    y1,
+    caption,
+    active=True,
+    buttons=("minimize", "maximize", "close"),
+    scheme=None,
+):
+    # Draws a wraparound Windows 95 style title bar on an image, with a
+    # horizontal gradient from the title color to the gradient title color,
+    # the caption text and the given caption buttons.
+    # 'buttons' is a sequence of "minimize", "maximize", "restore" or "close";
+    # the buttons are drawn in that order at the right end of the title bar,
+    # with a gap before "close".
+    # 'scheme' is a color scheme in the form returned by classiccolorscheme(),
+    # or None to use that color scheme.
+    scheme = scheme if scheme else classiccolorscheme()
+    helper = ImageWraparoundDraw(image, width, height)
+    c0 = scheme["ActiveTitle"] if active else scheme["InactiveTitle"]
+    c1 = scheme["GradientActiveTitle" if active else "GradientInactiveTitle"]
+    span = max(1, x1 - x0 - 1)
+    for x in range(x0, x1):
+        helper.rect(x, y0, x + 1, y1, _mixcolor(c0, c1, (x - x0) / span))
+    textcolor = scheme["TitleText"] if active else scheme["InactiveTitleText"]
+    ty = y0 + (y1 - y0 - textheight()) // 2
+    textdraw(image, width, height, textcolor, x0 + 3, ty, caption)
+    # Caption buttons, from right to left
+    bh = y1 - y0 - 4
+    bw = bh + 2
+    bx = x1 - 2
+    for kind in reversed(buttons):
+        buttonup(helper, bx - bw, y0 + 2, bx, y0 + 2 + bh, *_bevelcolors(scheme))
+        _captionglyph(
+            helper, bx - bw, y0 + 2, bx, y0 + 2 + bh, kind, scheme["ButtonText"]
+        )
+        bx -= bw + (2 if kind == "close" else 0)
+
+def _drawlabel(image, width, height, x0, y0, text, c, underline):
+    textdraw(image, width, height, c, x0, y0, text)
+    if underline and len(text) > 0:
+        simplebox(image, width, height, c, x0, y0 + 8, x0 + 5, y0 + 9)
+
+def menubar(image, width, height, x0, y0, x1, items, scheme=None):
+    # Draws a wraparound Windows 95 style menu bar on an image, with the
+    # first letter of each item underlined as its access key.
+    # 'items' is a list of menu item names.
+    # Returns the Y coordinate of the menu bar's bottom edge.
+    scheme = scheme if scheme else classiccolorscheme()
+    y1 = y0 + textheight() + 11
+    simplebox(image, width, height, scheme["MenuBar"], x0, y0, x1, y1)
+    x = x0 + 7
+    for item in items:
+        _drawlabel(image, width, height, x, y0 + 5, item, scheme["MenuText"], True)
+        x += textwidth(item) + 13
+    return y1
+
+def statusbar(image, width, height, x0, y0, x1, y1, fields, scheme=None):
+    # Draws a wraparound Windows 95 style status bar on an image, made of
+    # sunken fields each holding a line of text.  The last field takes
+    # up the width remaining after the other fields.
+    # 'fields' is a list of field texts.
+    scheme = scheme if scheme else classiccolorscheme()
+    helper = ImageWraparoundDraw(image, width, height)
+    helper.rect(x0, y0, x1, y1, scheme["ButtonFace"])
+    ty = y0 + (y1 - y0 - textheight()) // 2
+    x = x0
+    for i in range(len(fields)):
+        fx1 = x1 if i == len(fields) - 1 else x + textwidth(fields[i]) + 8
+        statusfieldbox(helper, x, y0 + 2, fx1, y1, *_bevelcolors(scheme))
+        textdraw(image, width, height, scheme["ButtonText"], x + 4, ty + 1, fields[i])
+        x = fx1 + 2
+
+def groupbox(image, width, height, x0, y0, x1, y1, label, scheme=None):
+    # Draws a wraparound Windows 95 style group box on an image: an etched
+    # frame with its label set into the top edge.  The inside of the group box
+    # is left unchanged.
+    scheme = scheme if scheme else classiccolorscheme()
+    helper = ImageWraparoundDraw(image, width, height)
+    fy0 = y0 + textheight() // 2
+    groupingbox(helper, x0, fy0, x1, y1, *_bevelcolors(scheme), drawFace=False)
+    if len(label) > 0:
+        textdraw(
+            image,
+            width,
+            height,
+            scheme["WindowText"],
+            x0 + 8,
+            y0,
+            " " + label + " ",
+            bgcolor=scheme["ButtonFace"],
+        )
+
+def drawwindow(
+    image,
+    width,
+    height,
+    x0,
+    y0,
+    x1,
+    y1,
+    caption,
+    menu=None,
+    status=None,
+    active=True,
+    client=None,
+    buttons=("minimize", "maximize", "close"),
+    scheme=None,
+):
+    # Draws a wraparound Windows 95 style window on an image, composed of
+    # a window frame, title bar, and optionally a menu bar, sunken client
+    # area and status bar.
+    # 'menu' is a list of menu item names, or None for no menu bar.
+    # 'status' is a list of status bar field texts, or None for no status bar.
+    # 'client' is the client area's color, or None to leave the client area
+    # as a plain dialog face without a sunken border.
+    # 'buttons' is the caption buttons to draw, as in titlebar().
+    # Returns the client area's rectangle as [x0, y0, x1, y1].
+    scheme = scheme if scheme else classiccolorscheme()
+    helper = ImageWraparoundDraw(image, width, height)
+    windowborder(
+        helper, x0, y0, x1, y1, *_bevelcolors(scheme), face=scheme["ButtonFace"]
+    )
+    # Window frame (2 pixels), border padding (1 pixel) and 18-pixel title bar
+    x0 += 3
+    x1 -= 3
+    y1 -= 3
+    titlebar(
+        image, width, height, x0, y0 + 3, x1, y0 + 21, caption, active, buttons, scheme
+    )
+    y0 += 22
+    if menu is not None:
+        y0 = menubar(image, width, height, x0, y0, x1, menu, scheme)
+    if status is not None:
+        statusbar(image, width, height, x0, y1 - 20, x1, y1, status, scheme)
+        y1 -= 22
+    if client is not None:
+        fieldbox(helper, x0, y0, x1, y1, *_bevelcolors(scheme), face=client)
+        return [x0 + 2, y0 + 2, x1 - 2, y1 - 2]
+    return [x0, y0, x1, y1]
+
+# image comparison
+
+def _ssimblock(image1, image2, width, height, x0, y0, x1, y1):
+    # Structural similarity of the luma of a block of two images
+    c1 = (0.01 * 255) ** 2
+    c2 = (0.03 * 255) ** 2
+    a = []
+    b = []
+    for y in range(y0, y1):
+        yp = y * width * 3
+        for x in range(x0, x1):
+            xp = yp + x * 3
+            a.append(
+                (image1[xp] * 2126 + image1[xp + 1] * 7152 + image1[xp + 2] * 722)
+                / 10000
+            )
+            b.append(
+                (image2[xp] * 2126 + image2[xp + 1] * 7152 + image2[xp + 2] * 722)
+                / 10000
+            )
+    n = len(a)
+    meana = sum(a) / n
+    meanb = sum(b) / n
+    vara = sum((v - meana) ** 2 for v in a) / n
+    varb = sum((v - meanb) ** 2 for v in b) / n
+    cov = sum((va - meana) * (vb - meanb) for va, vb in zip(a, b)) / n
+    return ((2 * meana * meanb + c1) * (2 * cov + c2)) / (
+        (meana**2 + meanb**2 + c1) * (vara + varb + c2)
+    )
+
+def imagediff(image1, image2, width, height, ssim=False):
+    # Compares two images of the same size and returns a dictionary with
+    # the following keys:
+    # "maxerror": greatest absolute difference between two color components.
+    # "meanerror": average absolute difference between two color components.
+    # "psnr": peak signal-to-noise ratio in decibels; infinity if the
+    # images are the same.
+    # "ssim": if 'ssim' is True, the average structural similarity (from -1
+    # through 1) of the images' gray tones over 8x8 blocks; otherwise, None.
+    # Useful for checking how close a dithered or color-reduced image
+    # is to the original.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(image1) != width * height * 3 or len(image2) != width * height * 3:
+        raise ValueError
+    maxerror = 0
+    sumerror = 0
+    sumsq = 0
+    for i in range(width * height * 3):
+        d = abs(image1[i] - image2[i])
+        if d > maxerror:
+            maxerror = d
+        sumerror += d
+        sumsq += d * d
+    mse = sumsq / (width * height * 3)
+    ret = {
+        "maxerror": maxerror,
+        "meanerror": sumerror / (width * height * 3),
+        "psnr": math.inf if mse == 0 else 10 * math.log10(255 * 255 / mse),
+        "ssim": None,
+    }
+    if ssim:
+        total = 0
+        blocks = 0
+        for y in range(0, height, 8):
+            for x in range(0, width, 8):
+                total += _ssimblock(
+                    image1,
+                    image2,
+                    width,
+                    height,
+                    x,
+                    y,
+                    min(width, x + 8),
+                    min(height, y + 8),
+                )
+                blocks += 1
+        ret["ssim"] = total / blocks
+    return ret
+
+def diffimage(image1, image2, width, height):
+    # Generates a heat map of the differences between two images of the same
+    # size.  Pixels that are the same in both images are black; the greater
+    # the difference, the more the pixel goes from red to yellow to white.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(image1) != width * height * 3 or len(image2) != width * height * 3:
+        raise ValueError
+    image = [0 for i in range(width * height * 3)]
+    for i in range(0, width * height * 3, 3):
+        d = max(
+            abs(image1[i] - image2[i]),
+            abs(image1[i + 1] - image2[i + 1]),
+            abs(image1[i + 2] - image2[i + 2]),
+        )
+        # Map the difference (0 through 255) to a black-red-yellow-white ramp
+        d = d * 3
+        image[i] = min(255, d)
+        image[i + 1] = min(255, max(0, d - 255))
+        image[i + 2] = min(255, max(0, d - 510))
+    return image
+
+# image resizing
+
+def areaaverage(image, width, height, dstwidth, dstheight):
+    # Resizes an image to the given size by averaging the colors of
+    # the source pixels that each destination pixel covers (box filter).
+    # Intended for shrinking images; enlarging an image with this
+    # method simply repeats its pixels.
+    if width <= 0 or height <= 0 or dstwidth <= 0 or dstheight <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    ret = [0 for i in range(dstwidth * dstheight * 3)]
+    for dy in range(dstheight):
+        # Source rows covered by this destination row, in units
+        # of 1/dstheight of a source pixel
+        sy0 = dy * height
+        sy1 = sy0 + height
+        for dx in range(dstwidth):
+            sx0 = dx * width
+            sx1 = sx0 + width
+            r = g = b = 0
+            for y in range(sy0 // dstheight, (sy1 + dstheight - 1) // dstheight):
+                wy = min(sy1, (y + 1) * dstheight) - max(sy0, y * dstheight)
+                yp = y * width * 3
+                for x in range(sx0 // dstwidth, (sx1 + dstwidth - 1) // dstwidth):
+                    wx = min(sx1, (x + 1) * dstwidth) - max(sx0, x * dstwidth)
+                    w = wx * wy
+                    xp = yp + x * 3
+                    r += image[xp] * w
+                    g += image[xp + 1] * w
+                    b += image[xp + 2] * w
+            area = width * height
+            dp = (dy * dstwidth + dx) * 3
+            ret[dp] = (r + area // 2) // area
+            ret[dp + 1] = (g + area // 2) // area
+            ret[dp + 2] = (b + area // 2) // area
+    return ret
+
+def thumbnail(image, width, height, maxdim=64):
+    # Generates a thumbnail of an image whose width and height are no
+    # greater than 'maxdim' pixels, keeping the image's aspect ratio.
+    # Returns a dictionary with the keys "image", "width", and "height".
+    # Images already small enough are copied unchanged.
+    if maxdim <= 0 or width <= 0 or height <= 0:
+        raise ValueError
+    if width <= maxdim and height <= maxdim:
+        return {"image": [x for x in image], "width": width, "height": height}
+    if width >= height:
+        tw = maxdim
+        th = max(1, (height * maxdim + width // 2) // width)
+    else:
+        th = maxdim
+        tw = max(1, (width * maxdim + height // 2) // height)
+    return {
+        "image": areaaverage(image, width, height, tw, th),
+        "width": tw,
+        "height": th,
+    }
+
+def nearestresize(image, width, height, dstwidth, dstheight):
+    # Resizes an image to the given size by copying, for each destination
+    # pixel, the source pixel it falls on (nearest neighbor).  Unlike
+    # areaaverage(), this introduces no new colors.
+    if width <= 0 or height <= 0 or dstwidth <= 0 or dstheight <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    ret = [0 for i in range(dstwidth * dstheight * 3)]
+    for dy in range(dstheight):
+        yp = ((dy * height + height // 2) // dstheight) * width * 3
+        dp = dy * dstwidth * 3
+        for dx in range(dstwidth):
+            sp = yp + ((dx * width + width // 2) // dstwidth) * 3
+            ret[dp + dx * 3] = image[sp]
+            ret[dp + dx * 3 + 1] = image[sp + 1]
+            ret[dp + dx * 3 + 2] = image[sp + 2]
+    return ret
+
+def _besttiledim(screendim, mindim, maxdim):
+    best = None
+    for t in range(mindim, maxdim + 1):
+        # Part of the screen not covered by whole tiles; when the screen
+        # is smaller than the tile, the tile is simply cropped
+        leftover = screendim % t if t <= screendim else t - screendim
+        if best is None or leftover <= best[0]:
+            best = [leftover, t]
+    return best[1]
+
+def besttilesize(screenwidth, screenheight, minsize, maxsize):
+    # Finds the width and height, each from 'minsize' through 'maxsize',
+    # of a tile that fits a screen of the given size most evenly, so that
+    # the tiled image isn't cut off partway through a tile at the screen's
+    # right or bottom edge.  'minsize' and 'maxsize' can each be an integer,
+    # or a 2-item array of a minimum/maximum width and a minimum/maximum height.
+    # If several sizes fit equally well, the largest is chosen.
+    # Returns a 2-item array of the tile's width and height.
+    mins = minsize if isinstance(minsize, list) else [minsize, minsize]
+    maxs = maxsize if isinstance(maxsize, list) else [maxsize, maxsize]
+    if screenwidth <= 0 or screenheight <= 0:
+        raise ValueError
+    if mins[0] <= 0 or mins[1] <= 0 or maxs[0] < mins[0] or maxs[1] < mins[1]:
+        raise ValueError
+    return [
+        _besttiledim(screenwidth, mins[0], maxs[0]),
+        _besttiledim(screenheight, mins[1], maxs[1]),
+    ]
+
+def smartcrop(image, width, height, aspect):
+    # Crops an image to the given aspect ratio (a 2-item array of a width
+    # and height, such as [16, 9]), keeping its most detailed region, that is,
+    # the region where neighboring pixels differ the most (such as where there
+    # are edges and texture), rather than simply its middle.
+    # Returns a dictionary with "image", "width", and "height" keys, and "x" and
+    # "y" keys giving where the cropped region starts in the original image.
+    if width <= 0 or height <= 0 or len(aspect) != 2:
+        raise ValueError
+    if aspect[0] <= 0 or aspect[1] <= 0:
+        raise ValueError
+    if width * aspect[1] > height * aspect[0]:
+        cw = max(1, (height * aspect[0] + aspect[1] // 2) // aspect[1])
+        ch = height
+    else:
+        cw = width
+        ch = max(1, (width * aspect[1] + aspect[0] // 2) // aspect[0])
+    # Amount of detail in each column and row
+    lum = [
+        (image[i] * 2126 + image[i + 1] * 7152 + image[i + 2] * 722) // 10000
+        for i in range(0, width * height * 3, 3)
+    ]
+    cols = [0 for i in range(width)]
+    rows = [0 for i in range(height)]
+    for y in range(height):
+        for x in range(width):
+            v = lum[y * width + x]
+            d = 0
+            if x + 1 < width:
+                d += abs(v - lum[y * width + x + 1])
+            if y + 1 < height:
+                d += abs(v - lum[(y + 1) * width + x])
+            cols[x] += d
+            rows[y] += d
+    # Slide the crop along the axis being cropped, keeping the
+    # position with the most detail (the middle one in case of ties)
+    sums = cols if cw < width else rows
+    span = cw if cw < width else ch
+    total = sum(sums[0:span])
+    best = [total, -abs(len(sums) - span)]
+    pos = 0
+    for i in range(1, len(sums) - span + 1):
+        total += sums[i + span - 1] - sums[i - 1]
+        key = [total, -abs(len(sums) - span - 2 * i)]
+        if key > best:
+            best = key
+            pos = i
+    x0 = pos if cw < width else 0
+    y0 = pos if cw == width else 0
+    return {
+        "image": wraparoundcrop(image, width, height, x0, y0, cw, ch),
+        "width": cw,
+        "height": ch,
+        "x": x0,
+        "y": y0,
+    }
+
+def wraparoundcrop(image, width, height, x0, y0, cropwidth, cropheight):
+    # Copies a rectangle of the given size from an image, starting at
+    # the given position; parts of the rectangle beyond the image's edges
+    # wrap around to the other side, as though the image were tiled.
+    if width <= 0 or height <= 0 or cropwidth <= 0 or cropheight <= 0:
+        raise ValueError
+    ret = [0 for i in range(cropwidth * cropheight * 3)]
+    for y in range(cropheight):
+        yp = ((y0 + y) % height) * width * 3
+        dp = y * cropwidth * 3
+        for x in range(cropwidth):
+            sp = yp + ((x0 + x) % width) * 3
+            ret[dp + x * 3] = image[sp]
+            ret[dp + x * 3 + 1] = image[sp + 1]
+            ret[dp + x * 3 + 2] = image[sp + 2]
+    return ret
+
+def _warpsample(image, width, height, x, y, addressmode):
+    # Gets the color at integer position (x, y) of an image, or None if
+    # that position is outside the image and 'addressmode' is "none"
+    if addressmode == "wrap":
+        x %= width
+        y %= height
+    elif addressmode == "clamp":
+        x = min(max(x, 0), width - 1)
+        y = min(max(y, 0), height - 1)
+    elif x < 0 or y < 0 or x >= width or y >= height:
+        return None
+    pos = (y * width + x) * 3
+    return image[pos : pos + 3]
+
+def imagewarp(
+    dstimage,
+    dstwidth,
+    dstheight,
+    srcimage,
+    srcwidth,
+    srcheight,
+    matrix,
+    sampling="nearest",
+    addressmode="wrap",
+):
+    # Draws a transformed copy of an image on another image, such as a
+    # rotated, scaled, or sheared copy, or a copy seen in perspective.
+    # 'matrix' gives where each point of the source image lands on the
+    # destination image.  It is a list of six numbers [a, b, c, d, e, f] for an
+    # affine transform, where point (x, y) lands on (a*x+b*y+c, d*x+e*y+f),
+    # or nine numbers [a, b, c, d, e, f, g, h, i] for a projective
+    # transform, where point (x, y) lands on ((a*x+b*y+c)/w, (d*x+e*y+f)/w)
+    # with w = g*x+h*y+i.  The matrix must be invertible.
+    # 'sampling' is "nearest" (nearest pixel) or "bilinear" (blend of the
+    # four nearest pixels).
+    # 'addressmode' says what to do with destination pixels that fall outside
+    # the source image: "wrap" (default) treats the source image as tiled,
+    # "clamp" repeats the source image's edge pixels, and "none" leaves those
+    # destination pixels unchanged.
+    # Returns 'dstimage'.
+    if len(matrix) == 6:
+        m = list(matrix) + [0, 0, 1]
+    elif len(matrix) == 9:
+        m = list(matrix)
+    else:
+        raise ValueError
+    if sampling != "nearest" and sampling != "bilinear":
+        raise ValueError
+    if addressmode != "wrap" and addressmode != "clamp" and addressmode != "none":
+        raise ValueError
+    if srcwidth <= 0 or srcheight <= 0 or dstwidth <= 0 or dstheight <= 0:
+        raise ValueError
+    # Invert the matrix, to find where each destination pixel comes from
+    a, b, c, d, e, f, g, h, i = m
+    det = a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g)
+    if det == 0:
+        raise ValueError
+    inv = [
+        (e * i - f * h) / det,
+        (c * h - b * i) / det,
+        (b * f - c * e) / det,
+        (f * g - d * i) / det,
+        (a * i - c * g) / det,
+        (c * d - a * f) / det,
+        (d * h - e * g) / det,
+        (b * g - a * h) / det,
+        (a * e - b * d) / det,
+    ]
+    for y in range(dstheight):
+        for x in range(dstwidth):
+            # Sample at the center of each destination pixel
+            px = x + 0.5
+            py = y + 0.5
+            w = inv[6] * px + inv[7] * py + inv[8]
+            if w == 0:
+                continue
+            sx = (inv[0] * px + inv[1] * py + inv[2]) / w - 0.5
+            sy = (inv[3] * px + inv[4] * py + inv[5]) / w - 0.5
+            if sampling == "nearest":
+                color = _warpsample(
+                    srcimage, srcwidth, srcheight, round(sx), round(sy), addressmode
+                )
+            else:
+                x0 = math.floor(sx)
+                y0 = math.floor(sy)
+                fx = sx - x0
+                fy = sy - y0
+                color = [0, 0, 0]
+                weight = 0
+                for yy in range(2):
+                    for xx in range(2):
+                        wt = (fx if xx else 1 - fx) * (fy if yy else 1 - fy)
+                        sc = _warpsample(
+                            srcimage, srcwidth, srcheight, x0 + xx, y0 + yy, addressmode
+                        )
+                        if sc and wt > 0:
+                            color = [color[k] + sc[k] * wt for k in range(3)]
+                            weight += wt
+                if weight > 0:
+                    color = [min(255, int(round(v / weight))) for v in color]
+                else:
+                    color = None
+            if color:
+                pos = (y * dstwidth + x) * 3
+                dstimage[pos] = color[0]
+                dstimage[pos + 1] = color[1]
+                dstimage[pos + 2] = color[2]
+    return dstimage
+
+# display simulation
+
+def correctaspect(image, width, height, par):
+    # Resizes an image meant for a display with non-square pixels so that it
+    # keeps its proportions on a display with square pixels.
+    # 'par' is the pixel aspect ratio, the ratio of a pixel's width to its
+    # height, given as a 2-item array of integers; for example, [5, 6] for
+    # 320x200 graphics on a 4:3 screen.  The image is stretched, never shrunk:
+    # vertically if pixels are taller than wide, horizontally otherwise.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if len(par) != 2 or par[0] <= 0 or par[1] <= 0:
+        raise ValueError
+    if par[0] < par[1]:
+        newwidth = width
+        newheight = (height * par[1] + par[0] // 2) // par[0]
+    else:
+        newwidth = (width * par[0] + par[1] // 2) // par[1]
+        newheight = height
+    if newwidth == width and newheight == height:
+        return {"image": [x for x in image], "width": width, "height": height}
+    return {
+        "image": areaaverage(image, width, height, newwidth, newheight),
+        "width": newwidth,
+        "height": newheight,
+    }
+
+def _cgapalette(palettenumber, intense, background):
+    # Colors of a CGA 320x200 graphics mode palette
+    cga = cgacolors()
+    if palettenumber == 0:
+        indices = [2, 4, 6]
+    elif palettenumber == 1:
+        indices = [3, 5, 7]
+    else:
+        raise ValueError
+    if intense:
+        # High-intensity versions of the colors
+        indices = [i + 8 for i in indices]
+    return [background if background else cga[0]] + [cga[i] for i in indices]
+
+def simulatedisplay(
+    image,
+    width,
+    height,
+    display,
+    cgapalette=1,
+    intense=True,
+    background=None,
+    monocolor=None,
+    dither=True,
+    aspect=False,
+):
+    # Simulates how a full-screen image would look on a classic PC display,
+    # by resizing the image to that display's resolution (see areaaverage())
+    # and reducing it to the colors that display can show.
+    # 'display' is one of the following:
+    # "cga": CGA 320x200 graphics with four colors: a background color and the
+    # three colors of palette 'cgapalette' (0 for green, red, and brown;
+    # 1 for cyan, magenta, and light gray), in high intensity if 'intense'
+    # is True.  'background' is the background color (default is black) and
+    # should be one of the colors in cgacolors().
+    # "ega": EGA 640x350 graphics with the 16 colors in cgacolors().
+    # "hercules": Hercules 720x348 monochrome graphics, in black and
+    # 'monocolor' (default is the green of a green phosphor monitor).
+    # 'dither' means to dither the image to the display's colors (see
+    # patternDither()); if False, each pixel is given the nearest color.
+    # 'aspect' means to stretch the result to a 4:3 shape with square pixels
+    # (see correctaspect()), since those displays' pixels weren't square.
+    # Returns a dictionary with "image", "width", and "height" keys, and
+    # a "pixelaspect" key giving the pixel aspect ratio (see correctaspect())
+    # of the result.
+    if display == "cga":
+        dw, dh = 320, 200
+        palette = _cgapalette(cgapalette, intense, background)
+    elif display == "ega":
+        dw, dh = 640, 350
+        palette = cgacolors()
+    elif display == "hercules":
+        dw, dh = 720, 348
+        palette = [[0, 0, 0], monocolor if monocolor else [51, 255, 51]]
+    else:
+        raise ValueError
+    ret = areaaverage(image, width, height, dw, dh)
+    if dither:
+        patternDither(ret, dw, dh, palette)
+    else:
+        for i in range(0, dw * dh * 3, 3):
+            c = palette[_nearest_rgb3(palette, ret[i], ret[i + 1], ret[i + 2])]
+            ret[i] = c[0]
+            ret[i + 1] = c[1]
+            ret[i + 2] = c[2]
+    # Ratio of a pixel's width to its height on a 4:3 screen
+    g = math.gcd(4 * dh, 3 * dw)
+    par = [4 * dh // g, 3 * dw // g]
+    if aspect:
+        ret = correctaspect(ret, dw, dh, par)
+        return {
+            "image": ret["image"],
+            "width": ret["width"],
+            "height": ret["height"],
+            "pixelaspect": [1, 1],
+        }
+    return {"image": ret, "width": dw, "height": dh, "pixelaspect": par}
+
+def boxblur(image, width, height, radius=1):
+    # Blurs an image by averaging each pixel with the pixels within
+    # 'radius' pixels of it horizontally and vertically (a box blur).
+    # The blur wraps around the image's edges, so a tileable image stays
+    # tileable.  Returns a new image.
+    if radius <= 0 or int(radius) != radius or width <= 0 or height <= 0:
+        raise ValueError
+    size = radius * 2 + 1
+    # Separable box blur: horizontal, then vertical
+    tmp = [0 for i in range(width * height * 3)]
+    for y in range(height):
+        for x in range(width):
+            pos = (y * width + x) * 3
+            for i in range(3):
+                t = 0
+                for k in range(-radius, radius + 1):
+                    t += image[(y * width + (x + k) % width) * 3 + i]
+                tmp[pos + i] = t
+    ret = [0 for i in range(width * height * 3)]
+    for y in range(height):
+        for x in range(width):
+            pos = (y * width + x) * 3
+            for i in range(3):
+                t = 0
+                for k in range(-radius, radius + 1):
+                    t += tmp[(((y + k) % height) * width + x) * 3 + i]
+                ret[pos + i] = (t + size * size // 2) // (size * size)
+    return ret
+
+# CRT effects.  Each of the following methods changes an image in place to
+# look more like it would on a cathode-ray tube (CRT) monitor, and returns
+# the image; they can be combined with crtfilter().
+
+def crtbarrel(image, width, height, amount=0.1, bgcolor=None):
+    # Bulges the image outward as on a curved CRT screen.  'amount' is how
+    # far the corners are pulled in, as a fraction of the image's size.
+    # Areas left uncovered at the edges are filled with 'bgcolor' (default is
+    # black).
+    if width <= 0 or height <= 0 or amount < 0:
+        raise ValueError
+    bg = bgcolor if bgcolor else [0, 0, 0]
+    src = [x for x in image]
+    for y in range(height):
+        ny = (y + 0.5) * 2 / height - 1
+        for x in range(width):
+            nx = (x + 0.5) * 2 / width - 1
+            # Sample farther from the center the farther the pixel is from it
+            f = 1 + amount * (nx * nx + ny * ny)
+            sx = (nx * f + 1) * width / 2 - 0.5
+            sy = (ny * f + 1) * height / 2 - 0.5
+            pos = (y * width + x) * 3
+            if sx < -0.5 or sy < -0.5 or sx > width - 0.5 or sy > height - 0.5:
+                c = bg
+            else:
+                x0 = math.floor(sx)
+                y0 = math.floor(sy)
+                fx = sx - x0
+                fy = sy - y0
+                c = [0, 0, 0]
+                for yy in range(2):
+                    for xx in range(2):
+                        wt = (fx if xx else 1 - fx) * (fy if yy else 1 - fy)
+                        sc = _warpsample(src, width, height, x0 + xx, y0 + yy, "clamp")
+                        c = [c[k] + sc[k] * wt for k in range(3)]
+                c = [min(255, int(round(v))) for v in c]
+            image[pos] = c[0]
+            image[pos + 1] = c[1]
+            image[pos + 2] = c[2]
+    return image
+
+def crtmask(image, width, height, kind="aperture", strength=0.5):
+    # Darkens parts of each pixel's color to imitate the phosphor mask of
+    # a color CRT.  'kind' is "aperture" (vertical red, green, and blue
+    # stripes, as in an aperture grille), "shadow" (red, green, and blue
+    # dots in staggered rows, as in a shadow mask), or "slot" (stripes broken
+    # into slots every few rows).  'strength' is from 0 (no effect) through 1
+    # (the other two components are fully darkened).
+    if kind != "aperture" and kind != "shadow" and kind != "slot":
+        raise ValueError
+    if strength < 0 or strength > 1:
+        raise ValueError
+    keep = int(round((1 - strength) * 256))
+    for y in range(height):
+        for x in range(width):
+            pos = (y * width + x) * 3
+            if kind == "shadow":
+                lit = (x + (y % 2) * 2) % 3
+            elif kind == "slot":
+                lit = x % 3 if (y + (x // 3) * 2) % 4 != 0 else -1
+            else:
+                lit = x % 3
+            for i in range(3):
+                if i != lit:
+                    image[pos + i] = (image[pos + i] * keep) >> 8
+    return image
+
+def crtbloom(image, width, height, radius=2, strength=0.5, threshold=160):
+    # Adds a glow around bright areas, as phosphors glow beyond their
+    # boundaries.  Components brighter than 'threshold' are blurred over
+    # 'radius' pixels and added back to the image at the given 'strength'.
+    # The blur wraps around the image's edges.
+    if radius <= 0 or int(radius) != radius or strength < 0:
+        raise ValueError
+    bright = boxblur([max(0, v - threshold) for v in image], width, height, radius)
+    for pos in range(width * height * 3):
+        image[pos] = min(255, image[pos] + int(bright[pos] * strength))
+    return image
+
+def crtscanlines(image, width, height, strength=0.5, frame=None):
+    # Darkens every other row, as the gaps between a CRT's scan lines.
+    # 'strength' is from 0 (no effect) through 1 (rows fully darkened).
+    # 'frame' is the frame number when filtering frames of an animation; if
+    # given, odd frames darken the other set of rows instead, imitating the
+    # flicker of an interlaced display.
+    if strength < 0 or strength > 1:
+        raise ValueError
+    keep = int(round((1 - strength) * 256))
+    start = 1 if frame is None else (frame + 1) % 2
+    for y in range(start, height, 2):
+        for pos in range(y * width * 3, (y + 1) * width * 3):
+            image[pos] = (image[pos] * keep) >> 8
+    return image
+
+def crtfilter(image, width, height, passes=None, frame=None):
+    # Applies several CRT effects to an image in turn.  'passes' is a list
+    # whose items are each the name of an effect ("barrel", "mask", "bloom",
+    # or "scanlines") or a 2-item list with the name and a dictionary of
+    # options for that effect (the keyword parameters of crtbarrel(),
+    # crtmask(), crtbloom(), or crtscanlines()).  The default is
+    # ["bloom", "mask", "scanlines", "barrel"].
+    # 'frame' is passed to crtscanlines() when filtering frames of
+    # an animation.  Returns 'image'.
+    effects = {
+        "barrel": crtbarrel,
+        "mask": crtmask,
+        "bloom": crtbloom,
+        "scanlines": crtscanlines,
+    }
+    if passes is None:
+        passes = ["bloom", "mask", "scanlines", "barrel"]
+    for p in passes:
+        name, options = (p, {}) if isinstance(p, str) else (p[0], p[1])
+        if name not in effects:
+            raise ValueError("unknown CRT effect: %s" % (name))
+        if name == "scanlines" and frame is not None and "frame" not in options:
+            options = dict(options, frame=frame)
+        effects[name](image, width, height, **options)
+    return image
+
+# sprite sheets and animation
+
+# An animation is a dictionary with the keys "frames" (a list of images, all
+# the same size), "width" and "height" (the size of each frame), and "delays"
+# (a list giving how long each frame is shown, in milliseconds).
+
+def spritesheetframes(image, width, height, cellwidth, cellheight, count=None):
+    # Splits a sprite sheet, an image holding several images (frames)
+    # of size 'cellwidth' x 'cellheight' in a grid, into a list of those
+    # frames, read from left to right, then top to bottom.  Partial cells at
+    # the right and bottom edges are ignored.  'count' is the number of
+    # frames to return, for sheets whose last cells are unused; default is
+    # all of them.
+    if cellwidth <= 0 or cellheight <= 0 or len(image) != width * height * 3:
+        raise ValueError
+    columns = width // cellwidth
+    rows = height // cellheight
+    if count is None:
+        count = columns * rows
+    if count < 0 or count > columns * rows:
+        raise ValueError
+    ret = []
+    for i in range(count):
+        x0 = (i % columns) * cellwidth
+        y0 = (i // columns) * cellheight
+        ret.append(
+            wraparoundcrop(image, width, height, x0, y0, cellwidth, cellheight)
+        )
+    return ret
+
+def spritesheet(frames, cellwidth, cellheight, columns=None, bgcolor=None):
+    # Arranges images of size 'cellwidth' x 'cellheight' in a grid
+    # with the given number of columns (default is all in one row), the
+    # reverse of spritesheetframes().  Unused cells are filled with 'bgcolor'
+    # (default is black).
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if len(frames) == 0 or cellwidth <= 0 or cellheight <= 0:
+        raise ValueError
+    if columns is None:
+        columns = len(frames)
+    if columns <= 0:
+        raise ValueError
+    rows = (len(frames) + columns - 1) // columns
+    width = columns * cellwidth
+    height = rows * cellheight
+    image = blankimage(width, height, bgcolor if bgcolor else [0, 0, 0])
+    for i in range(len(frames)):
+        if len(frames[i]) != cellwidth * cellheight * 3:
+            raise ValueError
+        imageblit(
+            image,
+            width,
+            height,
+            frames[i],
+            cellwidth,
+            cellheight,
+            (i % columns) * cellwidth,
+            (i // columns) * cellheight,
+        )
+    return {"image": image, "width": width, "height": height}
+
+def composeanimation(frames, width, height, delays=100, sequence=None, pingpong=False):
+    # Builds an animation from a list of frames of size 'width' x 'height'
+    # (such as those returned by spritesheetframes()).
+    # 'delays' is the time each frame is shown, in milliseconds, either
+    # one number for all frames or a list with one entry per frame.
+    # 'sequence' is a list of indices into 'frames' giving the order the
+    # frames are shown in (default is the order given).
+    # 'pingpong' means to play the frames forward, then backward, without
+    # repeating the first and last frames.
+    if len(frames) == 0:
+        raise ValueError
+    for f in frames:
+        if len(f) != width * height * 3:
+            raise ValueError
+    if isinstance(delays, (int, float)):
+        delays = [delays for f in frames]
+    if len(delays) != len(frames):
+        raise ValueError
+    if sequence is None:
+        sequence = list(range(len(frames)))
+    if pingpong and len(sequence) > 2:
+        sequence = sequence + sequence[-2:0:-1]
+    for i in sequence:
+        if i < 0 or i >= len(frames):
+            raise ValueError
+    return {
+        "frames": [frames[i] for i in sequence],
+        "width": width,
+        "height": height,
+        "delays": [delays[i] for i in sequence],
+    }
+
+def animatedpattern(
+    pattern="ants", fgcolor=None, bgcolor=None, dx=1, dy=0, scale=1, delay=100
+):
+    # Generates an animated tile from an 8x8 pattern (see hatchedbox()) that
+    # moves by 'dx' pixels to the right and 'dy' pixels down with each frame,
+    # until it returns to where it started.  'pattern' is a list of eight
+    # byte values (with the most significant bit leftmost) or one of the
+    # following: "ants" (the diagonal "marching ants" pattern once used for
+    # selection outlines), "stripes" (diagonal stripes), or "stars" (dots
+    # that blink on and off in turn; 'dx' and 'dy' are ignored).
+    # Set bits are drawn in 'fgcolor' (default is black) and clear bits in
+    # 'bgcolor' (default is white), and each pattern pixel becomes a
+    # 'scale' x 'scale' block.  Each frame is shown for 'delay' milliseconds.
+    # Returns an animation (see composeanimation()).
+    if fgcolor is None:
+        fgcolor = [0, 0, 0]
+    if bgcolor is None:
+        bgcolor = [255, 255, 255]
+    if scale <= 0 or int(scale) != scale:
+        raise ValueError
+    stars = None
+    if pattern == "ants":
+        pattern = [0x0F, 0x1E, 0x3C, 0x78, 0xF0, 0xE1, 0xC3, 0x87]
+    elif pattern == "stripes":
+        pattern = [0xC0, 0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x81]
+    elif pattern == "stars":
+        # Position of each star and the frame on which it appears
+        stars = [[1, 1, 0], [5, 2, 2], [2, 5, 4], [6, 6, 6]]
+        pattern = [0 for i in range(8)]
+        dx = 0
+        dy = 0
+    elif isinstance(pattern, str) or len(pattern) != 8:
+        raise ValueError
+    if stars:
+        count = 8
+    else:
+        px = 8 // math.gcd(dx % 8, 8)
+        py = 8 // math.gcd(dy % 8, 8)
+        count = px * py // math.gcd(px, py)
+    size = 8 * scale
+    frames = []
+    for t in range(count):
+        bits = [0 for i in range(8)]
+        for y in range(8):
+            row = pattern[(y - dy * t) % 8]
+            shift = (dx * t) % 8
+            bits[y] = ((row >> shift) | (row << (8 - shift))) & 0xFF
+        if stars:
+            for sx, sy, phase in stars:
+                # Each star is lit for three frames out of eight
+                if (t - phase) % 8 < 3:
+                    bits[sy] |= 0x80 >> sx
+        frame = [0 for i in range(size * size * 3)]
+        for y in range(size):
+            for x in range(size):
+                bit = (bits[y // scale] >> (7 - x // scale)) & 1
+                c = fgcolor if bit else bgcolor
+                pos = (y * size + x) * 3
+                frame[pos] = c[0]
+                frame[pos + 1] = c[1]
+                frame[pos + 2] = c[2]
+        frames.append(frame)
+    return composeanimation(frames, size, size, delay)
+
+def mystify(
+    width=320,
+    height=240,
+    frames=120,
+    shapes=2,
+    vertices=4,
+    trails=4,
+    colors=None,
+    bgcolor=None,
+    seed=None,
+    delay=40,
+):
+    # Generates an animation in the style of the "Mystify Your Mind"
+    # screen saver from Windows: closed polylines whose corners bounce
+    # around the screen, each followed by copies of its previous positions.
+    # 'shapes' is the number of polylines, 'vertices' the number of corners
+    # each has, and 'trails' the number of positions drawn for each.
+    # 'colors' is a list of colors to draw the polylines with, in turn;
+    # the default is bright colors from the VGA palette.  'bgcolor' is the
+    # background color (default is black).  'seed' seeds the random number
+    # generator used for the motion.  Each frame is shown for 'delay'
+    # milliseconds.  Returns an animation (see composeanimation()).
+    if width < 2 or height < 2 or frames <= 0 or shapes <= 0 or trails <= 0:
+        raise ValueError
+    if vertices < 2:
+        raise ValueError
+    rnd = random.Random(seed)
+    if colors is None:
+        colors = [[255, 255, 0], [0, 255, 255], [255, 0, 255], [0, 255, 0]]
+    if bgcolor is None:
+        bgcolor = [0, 0, 0]
+    state = []
+    for i in range(shapes):
+        pts = [
+            [
+                rnd.uniform(0, width - 1),
+                rnd.uniform(0, height - 1),
+                rnd.choice([-1, 1]) * rnd.uniform(1, 4),
+                rnd.choice([-1, 1]) * rnd.uniform(1, 4),
+            ]
+            for j in range(vertices)
+        ]
+        state.append({"points": pts, "history": [], "color": colors[i % len(colors)]})
+    ret = []
+    for t in range(frames):
+        image = blankimage(width, height, bgcolor)
+        for shape in state:
+            # Move each corner, bouncing off the screen's edges
+            for p in shape["points"]:
+                for axis, limit in [[0, width - 1], [1, height - 1]]:
+                    p[axis] += p[axis + 2]
+                    if p[axis] < 0 or p[axis] > limit:
+                        p[axis + 2] = -p[axis + 2]
+                        p[axis] = min(max(p[axis], 0), limit)
+            shape["history"].append(
+                [[int(p[0]), int(p[1])] for p in shape["points"]]
+            )
+            shape["history"] = shape["history"][-trails:]
+            for pts in shape["history"]:
+                for j in range(len(pts)):
+                    a = pts[j]
+                    b = pts[(j + 1) % len(pts)]
+                    c = shape["color"]
+                    linedraw(image, width, height, c, a[0], a[1], b[0], b[1], True)
+        ret.append(image)
+    return composeanimation(ret, width, height, delay)
+
+def starfield(
+    width=320, height=240, frames=120, stars=150, speed=0.02, seed=None, delay=40
+):
+    # Generates an animation of flying through a field of stars, as in the
+    # "Starfield Simulation" screen saver from Windows.  'stars' is the
+    # number of stars, and 'speed' is how far the viewer moves with each
+    # frame, as a fraction of the distance to the farthest stars.  'seed'
+    # seeds the random number generator used to place the stars.  Each frame
+    # is shown for 'delay' milliseconds.
+    # Returns an animation (see composeanimation()).
+    if width <= 0 or height <= 0 or frames <= 0 or stars <= 0 or speed <= 0:
+        raise ValueError
+    rnd = random.Random(seed)
+
+    def newstar(z):
+        return [rnd.uniform(-1, 1), rnd.uniform(-1, 1), z]
+
+    field = [newstar(rnd.uniform(0.05, 1)) for i in range(stars)]
+    cx = width / 2
+    cy = height / 2
+    ret = []
+    for t in range(frames):
+        image = blankimage(width, height, [0, 0, 0])
+        for i in range(len(field)):
+            star = field[i]
+            star[2] -= speed
+            if star[2] <= 0.05:
+                star = field[i] = newstar(1.0)
+            x = int(cx + star[0] / star[2] * cx)
+            y = int(cy + star[1] / star[2] * cy)
+            if x < 0 or y < 0 or x >= width or y >= height:
+                # The star has left the screen; replace it with a far star
+                field[i] = newstar(1.0)
+                continue
+            # Nearer stars are brighter and bigger
+            v = int(255 * min(1, 1.2 - star[2]))
+            size = 2 if star[2] < 0.3 else 1
+            rectangle(
+                image, width, height, x, y, x + size, y + size, [v, v, v], False
+            )
+        ret.append(image)
+    return composeanimation(ret, width, height, delay)
+
+def lifestep(cells, width, height):
+    # Advances a pattern in Conway's Game of Life by one generation.
+    # 'cells' is a list with one entry per cell, 1 if alive and 0 if not,
+    # in the same order as the pixels of an image.  The edges wrap around, so
+    # that the pattern stays tileable.  Returns the new list of cells.
+    ret = [0 for i in range(width * height)]
+    for y in range(height):
+        up = ((y - 1) % height) * width
+        row = y * width
+        down = ((y + 1) % height) * width
+        for x in range(width):
+            left = (x - 1) % width
+            right = (x + 1) % width
+            n = (
+                cells[up + left]
+                + cells[up + x]
+                + cells[up + right]
+                + cells[row + left]
+                + cells[row + right]
+                + cells[down + left]
+                + cells[down + x]
+                + cells[down + right]
+            )
+            if n == 3 or (n == 2 and cells[row + x]):
+                ret[row + x] = 1
+    return ret
+
+def lifeanimation(
+    width,
+    height,
+    frames=60,
+    image=None,
+    threshold=128,
+    density=0.35,
+    seed=None,
+    steps=1,
+    fgcolor=None,
+    bgcolor=None,
+    delay=100,
+):
+    # Generates an animation of Conway's Game of Life on a grid of the given
+    # size whose edges wrap around, so that each frame is a tileable image.
+    # If 'image' is given (an image of size 'width' x 'height', such as the
+    # current wallpaper), cells start out alive where its luminance is
+    # below 'threshold'; otherwise, each cell starts out alive with
+    # probability 'density' (a "random soup"), using 'seed' to seed the
+    # random number generator.  'steps' is the number of generations per
+    # frame; a higher number makes the animation faster.  Live cells are
+    # drawn in 'fgcolor' (default is black) and dead ones in 'bgcolor'
+    # (default is white).  Each frame is shown for 'delay' milliseconds.
+    # Returns an animation (see composeanimation()).
+    if width <= 0 or height <= 0 or frames <= 0 or steps <= 0:
+        raise ValueError
+    if fgcolor is None:
+        fgcolor = [0, 0, 0]
+    if bgcolor is None:
+        bgcolor = [255, 255, 255]
+    if image is not None:
+        if len(image) != width * height * 3:
+            raise ValueError
+        cells = [
+            1
+            if (image[i] * 299 + image[i + 1] * 587 + image[i + 2] * 114) // 1000
+            < threshold
+            else 0
+            for i in range(0, width * height * 3, 3)
+        ]
+    else:
+        rnd = random.Random(seed)
+        cells = [1 if rnd.random() < density else 0 for i in range(width * height)]
+    ret = []
+    for t in range(frames):
+        frame = [0 for i in range(width * height * 3)]
+        for i in range(width * height):
+            c = fgcolor if cells[i] else bgcolor
+            frame[i * 3] = c[0]
+            frame[i * 3 + 1] = c[1]
+            frame[i * 3 + 2] = c[2]
+        ret.append(frame)
+        for i in range(steps):
+            cells = lifestep(cells, width, height)
+    return composeanimation(ret, width, height, delay)
+
+def animationframe(animation, time):
+    # Gets the frame of a looping animation shown at the given time,
+    # in milliseconds, from the start of the animation.
+    total = sum(animation["delays"])
+    if total <= 0:
+        return animation["frames"][0]
+    t = time % total
+    for i in range(len(animation["frames"])):
+        if t < animation["delays"][i]:
+            return animation["frames"][i]
+        t -= animation["delays"][i]
+    return animation["frames"][-1]
+
+# icon generation
+
+def _lumvariance(image, width, height):
+    lums = [
+        (image[i] * 2126 + image[i + 1] * 7152 + image[i + 2] * 722) // 10000
+        for i in range(0, width * height * 3, 3)
+    ]
+    mean = sum(lums) / len(lums)
+    return sum((v - mean) ** 2 for v in lums) / len(lums)
+
+def deriveicon(
+    image, width, height, sizes=[16, 32, 48], palette=None, shape="round"
+):
+    # Derives a set of icons matching a wallpaper image.  A square region
+    # of the image (treated as tileable) with the most variation in
+    # gray tones is found, then that region is shrunk to each icon size
+    # and its colors are reduced to those in the given palette.
+    # 'sizes' is a list of icon widths (and heights) in pixels.
+    # 'palette' is the color palette for the icons; default is
+    # classiccolors() (16 colors).  websafecolors() is an example of a
+    # 256-or-fewer-color palette.
+    # 'shape' is the shape of the icon's visible part: "square" for
+    # the whole icon, or "round" for a circle filling the icon.
+    # Returns a list of dictionaries, one for each size, with the keys
+    # "image", "width", "height", and "mask".  "mask" is a list with one
+    # item per pixel, which is 1 if the pixel is transparent and 0 otherwise
+    # (as in the "AND mask" of Windows icons).  The icons can be written to
+    # a file with imageformat.writeico() or imageformat.writeicns().
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if (not sizes) or len(sizes) == 0:
+        raise ValueError
+    if shape != "square" and shape != "round":
+        raise ValueError
+    pal = palette if palette else classiccolors()
+    side = max(1, min(width, height) * 2 // 3)
+    step = max(1, side // 4)
+    best = None
+    bestvar = -1
+    for y in range(0, height, step):
+        for x in range(0, width, step):
+            crop = wraparoundcrop(image, width, height, x, y, side, side)
+            var = _lumvariance(crop, side, side)
+            if var > bestvar:
+                best = crop
+                bestvar = var
+    ret = []
+    for size in sizes:
+        if size <= 0 or size > 256:
+            raise ValueError
+        icon = areaaverage(best, side, side, size, size)
+        for i in range(0, size * size * 3, 3):
+            c = pal[_nearest_rgb3(pal, icon[i], icon[i + 1], icon[i + 2])]
+            icon[i] = c[0]
+            icon[i + 1] = c[1]
+            icon[i + 2] = c[2]
+        mask = [0 for i in range(size * size)]
+        if shape == "round":
+            r2 = size * size / 4.0
+            for y in range(size):
+                for x in range(size):
+                    dx = x + 0.5 - size / 2.0
+                    dy = y + 0.5 - size / 2.0
+                    if dx * dx + dy * dy > r2:
+                        mask[y * size + x] = 1
+        ret.append({"image": icon, "width": size, "height": size, "mask": mask})
+    return ret
+
+# text drawing
+
+# Glyphs for the printable ASCII characters (space through tilde) in a
+# 5x7 pixel font.  Each glyph is five bytes, one for each column from left
+# to right; in each byte, the least significant bit is the top row.
+_font5x7 = bytes.fromhex(
+    "000000000000005f00000007000700147f147f14242a7f2a1223130864623649552250"
+    "0005030000001c2241000041221c0014083e081408083e080800503000000808080808"
+    "006060000020100804023e5149453e00427f400042615149462141454b311814127f10"
+    "27454545393c4a49493001710905033649494936064949291e00363600000056360000"
+    "0814224100141414141400412214080201510906324979413e7e1111117e7f49494936"
+    "3e414141227f4141221c7f494949417f090901013e414151327f0808087f00417f4100"
+    "2040413f017f081422417f404040407f0204027f7f0408107f3e4141413e7f09090906"
+    "3e4151215e7f09192946464949493101017f01013f4040403f1f2040201f7f2018207f"
+    "631408146303047804036151494543007f41410002040810200041417f000402010204"
+    "4040404040000102040020545454787f484444383844444420384444487f3854545418"
+    "087e090102085454543c7f0804047800447d40002040443d007f1028440000417f4000"
+    "7c041804787c0804047838444444387c14141408081414187c7c080404084854545420"
+    "043f4440203c4040207c1c2040201c3c4030403c44281028440c5050503c4464544c44"
+    "000836410000007f000000413608000804081008"
+)
+
+def textwidth(text, scale=1):
+    # Width, in pixels, of the given text as drawn by textdraw()
+    if len(text) == 0:
+        return 0
+    return (len(text) * 6 - 1) * scale
+
+def textheight(scale=1):
+    # Height, in pixels, of a line of text as drawn by textdraw()
+    return 7 * scale
+
+def textdraw(image, width, height, c, x0, y0, text, scale=1, bgcolor=None):
+    # Draws a wraparound line of text with its top left corner at the
+    # given position, in a 5x7 pixel font with one pixel between characters.
+    # 'c' is the text color.
+    # 'scale' is the size of each pixel of the font, in pixels.
+    # 'bgcolor' is the color to draw behind the text, or None (the default)
+    # to draw no background.  Characters other than printable ASCII
+    # characters are drawn as question marks.
+    if scale <= 0 or int(scale) != scale:
+        raise ValueError
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if bgcolor and len(text) > 0:
+        simplebox(
+            image,
+            width,
+            height,
+            bgcolor,
+            x0 % width,
+            y0 % height,
+            x0 % width + textwidth(text, scale),
+            y0 % height + textheight(scale),
+        )
+    for i in range(len(text)):
+        ch = ord(text[i])
+        if ch < 0x20 or ch > 0x7E:
+            ch = 0x3F
+        gp = (ch - 0x20) * 5
+        for col in range(5):
+            bits = _font5x7[gp + col]
+            for row in range(7):
+                if (bits >> row) & 1:
+                    for yy in range(scale):
+                        yp = ((y0 + row * scale + yy) % height) * width * 3
+                        for xx in range(scale):
+                            xp = yp + ((x0 + (i * 6 + col) * scale + xx) % width) * 3
+                            image[xp] = c[0]
+                            image[xp + 1] = c[1]
+                            image[xp + 2] = c[2]
+
+def marquee(text, fgcolor, bgcolor, rise=1, run=2, scale=1):
+    # Generates a tileable image of a line of text repeated diagonally
+    # (as in a "CONFIDENTIAL" watermark), in the font used by textdraw().
+    # The text climbs 'rise' pixels for every 'run' pixels to the right
+    # (rise can be 0 or negative); these are integers rather than an angle
+    # so that the text lines up exactly where the image wraps around.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if run <= 0 or int(run) != run or int(rise) != rise or len(text) == 0:
+        raise ValueError
+    th = textheight(scale)
+    tw = textwidth(text, scale)
+    # Distance between lines of text
+    spacing = th + th // 2 + 1
+    # The text climbs rise*width/run pixels across the image's width, which
+    # must be a whole number of lines for the text to line up where the
+    # image wraps around.
+    unit = run * spacing // math.gcd(abs(rise), run * spacing)
+    width = tw + spacing
+    width = ((width + unit - 1) // unit) * unit
+    height = spacing
+    textimage = blankimage(tw, th, [0, 0, 0])
+    textdraw(textimage, tw, th, [255, 255, 255], 0, 0, text, scale)
+    image = blankimage(width, height, bgcolor)
+    for y in range(th):
+        for x in range(tw):
+            if textimage[(y * tw + x) * 3] != 0:
+                yp = (y - (x * rise) // run) % height
+                pos = (yp * width + x) * 3
+                image[pos] = fgcolor[0]
+                image[pos + 1] = fgcolor[1]
+                image[pos + 2] = fgcolor[2]
+    return {"image": image, "width": width, "height": height}
+
+def stamp(
+    image,
+    width,
+    height,
+    mark,
+    corner="bottomright",
+    opacity=1.0,
+    color=None,
+    scale=1,
+    margin=4,
+    key=None,
+    area=None,
+):
+    # Draws a mark, such as a signature or logo, in a corner of an image,
+    # blended with the image beneath it.
+    # 'mark' is either a text string, drawn as with textdraw() in 'color'
+    # (default is white) at the given 'scale', or a dictionary with "image",
+    # "width", and "height" keys giving an image to draw, in which pixels of
+    # the color 'key' (if not None) are left out.
+    # 'corner' is "topleft", "topright", "bottomleft", or "bottomright".
+    # 'opacity' is from 0 (invisible) through 1 (fully opaque).
+    # 'margin' is the distance from the image's edges, in pixels.
+    # 'area' is the rectangle [x0, y0, x1, y1] whose corner the mark is drawn
+    # in, such as one returned by safearea(); default is the whole image.
+    # Returns 'image'.
+    if opacity < 0 or opacity > 1:
+        raise ValueError
+    area = area if area else [0, 0, width, height]
+    if area[2] <= area[0] or area[3] <= area[1]:
+        raise ValueError
+    if isinstance(mark, str):
+        mw = textwidth(mark, scale)
+        mh = textheight(scale)
+        if mw <= 0:
+            return image
+        # Draw the text in white on black, then use that as a mask
+        markimage = blankimage(mw, mh, [0, 0, 0])
+        textdraw(markimage, mw, mh, [255, 255, 255], 0, 0, mark, scale)
+        c = color if color else [255, 255, 255]
+        mask = [markimage[i * 3] != 0 for i in range(mw * mh)]
+        markimage = []
+        for m in mask:
+            markimage += c
+    else:
+        markimage = mark["image"]
+        mw = mark["width"]
+        mh = mark["height"]
+        mask = [
+            (not key) or markimage[i * 3 : i * 3 + 3] != list(key)[0:3]
+            for i in range(mw * mh)
+        ]
+    if corner == "topleft" or corner == "bottomleft":
+        x0 = area[0] + margin
+    elif corner == "topright" or corner == "bottomright":
+        x0 = area[2] - margin - mw
+    else:
+        raise ValueError
+    y0 = area[1] + margin if corner.startswith("top") else area[3] - margin - mh
+    alpha = int(round(opacity * 256))
+    for y in range(mh):
+        for x in range(mw):
+            if not mask[y * mw + x]:
+                continue
+            dp = (((y0 + y) % height) * width + (x0 + x) % width) * 3
+            sp = (y * mw + x) * 3
+            for i in range(3):
+                image[dp + i] = (
+                    image[dp + i] * (256 - alpha) + markimage[sp + i] * alpha
+                ) >> 8
+    return image
+
+def safearea(monitor):
+    # Finds the part of a monitor's screen where overlays and marks can be
+    # drawn without landing under the taskbar or the desktop icons, for use
+    # as the 'area' parameter of stamp() and of the overlay methods.
+    # 'monitor' is a dictionary (such as one read from a JSON file) with the
+    # following keys:
+    # "width" and "height": Size of the monitor's screen, in pixels.
+    # "x" and "y": Position of the monitor's top left corner on the desktop
+    # (default 0), for desktops spanning more than one monitor; the returned
+    # area is offset by that position.
+    # "taskbar": Side of the screen the taskbar is on ("top", "bottom",
+    # "left", or "right"), or None if this monitor has no taskbar; default
+    # is "bottom".
+    # "taskbarsize": Height (or width for a taskbar at the left or right)
+    # of the taskbar; default is 28.
+    # "iconcolumns": Number of columns of desktop icons, each 76 pixels wide
+    # as in desktoppreview(); default is 1.
+    # "iconside": Side of the screen the icon columns are on ("left" or
+    # "right"); default is "left".
+    # "margin": Extra space to leave at every edge; default is 0.
+    # Returns the area as [x0, y0, x1, y1].  Raises an error if no
+    # area remains.
+    x0 = monitor.get("x", 0)
+    y0 = monitor.get("y", 0)
+    x1 = x0 + monitor["width"]
+    y1 = y0 + monitor["height"]
+    taskbar = monitor.get("taskbar", "bottom")
+    size = monitor.get("taskbarsize", 28)
+    if taskbar == "top":
+        y0 += size
+    elif taskbar == "bottom":
+        y1 -= size
+    elif taskbar == "left":
+        x0 += size
+    elif taskbar == "right":
+        x1 -= size
+    elif taskbar is not None:
+        raise ValueError
+    icons = monitor.get("iconcolumns", 1) * 76
+    if monitor.get("iconside", "left") == "left":
+        x0 += icons
+    elif monitor["iconside"] == "right":
+        x1 -= icons
+    else:
+        raise ValueError
+    margin = monitor.get("margin", 0)
+    ret = [x0 + margin, y0 + margin, x1 - margin, y1 - margin]
+    if ret[2] <= ret[0] or ret[3] <= ret[1]:
+        raise ValueError
+    return ret
+
+def calendarimage(when=None, fgcolor=None, bgcolor=None, scale=1):
+    # Draws a calendar of a month, with the given day highlighted, in the
+    # same font as textdraw().  Weeks start on Sunday.
+    # 'when' is a datetime.date or datetime.datetime giving the day to
+    # highlight; default is today.
+    # 'fgcolor' is the text color (default is white); 'bgcolor', the
+    # background color (default is black).
+    # Returns a dictionary with "image", "width", and "height" keys, suitable
+    # for stamp() (with 'key' set to the background color to leave the
+    # background out).
+    import calendar
+    import datetime
+
+    if scale <= 0 or int(scale) != scale:
+        raise ValueError
+    when = when if when else datetime.date.today()
+    fgcolor = fgcolor if fgcolor else [255, 255, 255]
+    bgcolor = bgcolor if bgcolor else [0, 0, 0]
+    months = ["January", "February", "March", "April", "May", "June", "July"]
+    months += ["August", "September", "October", "November", "December"]
+    weeks = calendar.Calendar(6).monthdayscalendar(when.year, when.month)
+    # Each day takes up three characters, and each line 10 pixels
+    width = 7 * 18 - 6 + 8
+    height = (len(weeks) + 2) * 10 + 5
+    image = blankimage(width, height, bgcolor)
+    title = "%s %d" % (months[when.month - 1], when.year)
+    textdraw(image, width, height, fgcolor, (width - textwidth(title)) // 2, 4, title)
+    textdraw(image, width, height, fgcolor, 4, 14, "Su Mo Tu We Th Fr Sa")
+    for row in range(len(weeks)):
+        for col in range(7):
+            day = weeks[row][col]
+            if day == 0:
+                continue
+            x = 4 + col * 18
+            y = 24 + row * 10
+            text = "%2d" % (day)
+            if day == when.day:
+                # Draw the highlighted day in reverse
+                simplebox(image, width, height, fgcolor, x - 1, y - 1, x + 12, y + 8)
+                textdraw(image, width, height, bgcolor, x, y, text)
+            else:
+                textdraw(image, width, height, fgcolor, x, y, text)
+    if scale > 1:
+        image = nearestresize(image, width, height, width * scale, height * scale)
+    return {"image": image, "width": width * scale, "height": height * scale}
+
+def clocktext(when=None, hour24=False, seconds=False):
+    # Gets the time of day as text, such as "9:41 AM", for drawing with
+    # textdraw() or stamp().
+    # 'when' is a datetime.datetime or datetime.time; default is now.
+    import datetime
+
+    when = when if when else datetime.datetime.now()
+    ret = "%d:%02d" % (
+        when.hour if hour24 else (when.hour + 11) % 12 + 1,
+        when.minute,
+    )
+    if seconds:
+        ret += ":%02d" % (when.second)
+    return ret if hour24 else ret + (" PM" if when.hour >= 12 else " AM")
+
+def datetimeoverlay(
+    image,
+    width,
+    height,
+    mode="calendar",
+    when=None,
+    corner="topright",
+    opacity=1.0,
+    color=None,
+    scale=None,
+    margin=8,
+    area=None,
+):
+    # Draws a calendar of the current month (if 'mode' is "calendar") or
+    # a large clock (if 'mode' is "clock") in a corner of an image; see
+    # calendarimage(), clocktext() and stamp().  An image with this
+    # overlay is valid until the next day or minute, respectively, so a
+    # program that sets the desktop wallpaper can redraw it that often to
+    # serve as a desktop calendar or clock.
+    # 'when' is the date and time to show, as a datetime.datetime; default
+    # is now.  'scale' is the size of each pixel of the font, in pixels;
+    # default is 1 for the calendar and 6 for the clock.  'area' is as
+    # in stamp().
+    # Returns 'image'.
+    import datetime
+
+    when = when if when else datetime.datetime.now()
+    color = color if color else [255, 255, 255]
+    if mode == "calendar":
+        # Use a background color distinct from the text color
+        bg = [0, 0, 0] if color != [0, 0, 0] else [255, 255, 255]
+        cal = calendarimage(when, color, bg, scale if scale else 1)
+        return stamp(
+            image, width, height, cal, corner, opacity, margin=margin, key=bg, area=area
+        )
+    if mode == "clock":
+        text = clocktext(when)
+        scale = scale if scale else 6
+        return stamp(
+            image, width, height, text, corner, opacity, color, scale, margin, area=area
+        )
+    raise ValueError
+
+def progressbar(image, width, height, x0, y0, x1, y1, fraction, scheme=None):
+    # Draws a wraparound Windows 95 style progress bar on an image: a sunken
+    # field filled from the left with blocks in the highlight color.
+    # 'fraction' is the part of the bar to fill, from 0 through 1.
+    if fraction < 0 or fraction > 1:
+        raise ValueError
+    scheme = scheme if scheme else classiccolorscheme()
+    helper = ImageWraparoundDraw(image, width, height)
+    statusfieldbox(helper, x0, y0, x1, y1, *_bevelcolors(scheme))
+    # Blocks are as wide as 2/3 of the bar's inside height, with 2-pixel gaps
+    inner = x1 - x0 - 4
+    bw = max(1, (y1 - y0 - 4) * 2 // 3)
+    filled = int(inner * fraction)
+    for bx in range(0, filled, bw + 2):
+        bx1 = min(bx + bw, inner)
+        helper.rect(x0 + 2 + bx, y0 + 2, x0 + 2 + bx1, y1 - 2, scheme["Hilight"])
+
+def _uptimetext():
+    try:
+        ff = open("/proc/uptime", "r")
+        try:
+            data = ff.read()
+        finally:
+            ff.close()
+        secs = int(float(data.split()[0]))
+    except (OSError, ValueError, IndexError):
+        return "unknown"
+    days = secs // 86400
+    ret = "%d:%02d" % ((secs // 3600) % 24, (secs // 60) % 60)
+    if days > 0:
+        ret = "%d day%s, " % (days, "" if days == 1 else "s") + ret
+    return ret
+
+def sysinfooverlay(image, width, height, layout=None, scheme=None):
+    # Draws a panel of system information widgets in a corner of an image,
+    # in the style of a Windows 95 dialog box.
+    # 'layout' is a dictionary (such as one read from a JSON file), or a
+    # string in JSON format, with the following keys, all optional:
+    # "corner": Corner to draw the panel in, as in stamp(); default is
+    # "topright".
+    # "margin": Distance of the panel from the image's edges; default is 8.
+    # "area": Rectangle to draw the panel in, as in stamp(), such as one
+    # returned by safearea(); default is the whole image.
+    # "opacity": Opacity of the panel, as in stamp(); default is 1.
+    # "width": Width of the panel in pixels; default is 160.
+    # "widgets": List of widgets, drawn from top to bottom, each of which
+    # is a dictionary whose "type" key is "hostname" (the computer's name),
+    # "uptime" (time since the computer started; Linux only), "disk" (space
+    # used on the disk holding "path", default "/", as a progress bar), or
+    # "text" (the given "text").  Optional "label" replaces the widget's
+    # default label.  Default is a hostname, uptime, and disk widget.
+    # Returns 'image'.
+    import shutil
+    import socket
+
+    if isinstance(layout, str):
+        layout = json.loads(layout)
+    layout = layout if layout else {}
+    scheme = scheme if scheme else classiccolorscheme()
+    widgets = layout.get("widgets", None)
+    if widgets is None:
+        widgets = [{"type": "hostname"}, {"type": "uptime"}, {"type": "disk"}]
+    pw = layout.get("width", 160)
+    if pw < 32:
+        raise ValueError
+    # Each widget takes up one line of text, or two for a progress bar
+    lines = []
+    for widget in widgets:
+        kind = widget["type"]
+        if kind == "hostname":
+            lines.append([widget.get("label", "Host") + ": " + socket.gethostname()])
+        elif kind == "uptime":
+            lines.append([widget.get("label", "Uptime") + ": " + _uptimetext()])
+        elif kind == "disk":
+            path = widget.get("path", "/")
+            usage = shutil.disk_usage(path)
+            fraction = usage.used / usage.total if usage.total > 0 else 0
+            label = "%s: %d%%" % (widget.get("label", path), round(fraction * 100))
+            lines.append([label, fraction])
+        elif kind == "text":
+            lines.append([widget.get("label", "") + widget["text"]])
+        else:
+            raise ValueError("unknown widget: %s" % (kind))
+    ph = 8 + sum(24 if len(line) > 1 else 12 for line in lines)
+    panel = blankimage(pw, ph)
+    helper = ImageWraparoundDraw(panel, pw, ph)
+    windowborder(helper, 0, 0, pw, ph, *_bevelcolors(scheme), scheme["ButtonFace"])
+    y = 5
+    for line in lines:
+        # Shorten text that doesn't fit the panel
+        text = line[0][: (pw - 12) // 6]
+        textdraw(panel, pw, ph, scheme["ButtonText"], 6, y, text)
+        y += 12
+        if len(line) > 1:
+            progressbar(panel, pw, ph, 5, y - 2, pw - 5, y + 9, line[1], scheme)
+            y += 12
+    mark = {"image": panel, "width": pw, "height": ph}
+    return stamp(
+        image,
+        width,
+        height,
+        mark,
+        layout.get("corner", "topright"),
+        layout.get("opacity", 1.0),
+        margin=layout.get("margin", 8),
+        area=layout.get("area", None),
+    )
+
+def wraptext(text, maxwidth, scale=1):
+    # Breaks text into lines no wider than 'maxwidth' pixels when drawn with
+    # textdraw() at the given scale, breaking lines between words where
+    # possible.  Line breaks already in the text are kept.
+    # Returns a list of the lines.
+    maxchars = (maxwidth + scale) // (6 * scale)
+    if maxchars <= 0:
+        raise ValueError
+    ret = []
+    for paragraph in text.split("\n"):
+        line = ""
+        for word in paragraph.split():
+            # Break words too long to fit on a line by themselves
+            while len(word) > maxchars:
+                if len(line) > 0:
+                    ret.append(line)
+                    line = ""
+                ret.append(word[0:maxchars])
+                word = word[maxchars:]
+            if len(line) == 0:
+                line = word
+            elif len(line) + 1 + len(word) <= maxchars:
+                line += " " + word
+            else:
+                ret.append(line)
+                line = word
+        ret.append(line)
+    return ret
+
+def randomfortune(f, seed=None):
+    # Chooses a saying at random from a text file.  If the file has lines
+    # consisting only of "%", it is read as a "fortune" file, in which
+    # those lines separate the sayings; otherwise, each line that isn't
+    # blank is a saying.  'seed' seeds the random number generator, so
+    # that the same seed gives the same saying.
+    ff = open(f, "r", encoding="utf-8", errors="replace")
+    lines = ff.read().splitlines()
+    ff.close()
+    if "%" in [line.strip() for line in lines]:
+        sayings = []
+        current = []
+        for line in lines + ["%"]:
+            if line.strip() == "%":
+                if len("".join(current).strip()) > 0:
+                    sayings.append("\n".join(current).strip("\n"))
+                current = []
+            else:
+                current.append(line.rstrip())
+    else:
+        sayings = [line.strip() for line in lines if len(line.strip()) > 0]
+    if len(sayings) == 0:
+        raise ValueError("no sayings in file")
+    return random.Random(seed).choice(sayings)
+
+def textboxoverlay(
+    image,
+    width,
+    height,
+    text,
+    boxwidth=200,
+    corner="bottomright",
+    opacity=1.0,
+    scale=1,
+    margin=8,
+    area=None,
+    scheme=None,
+):
+    # Draws text, word-wrapped to fit (see wraptext()), in a box in a corner
+    # of an image, in the style of a Windows 95 tooltip.  'boxwidth' is the
+    # box's width in pixels; the box is as tall as the text needs.
+    # 'corner', 'opacity', 'margin', and 'area' are as in stamp().
+    # Returns 'image'.
+    scheme = scheme if scheme else classiccolorscheme()
+    lines = wraptext(text, boxwidth - 8 * scale, scale)
+    lineheight = textheight(scale) + 3 * scale
+    boxheight = len(lines) * lineheight + 5 * scale
+    box = blankimage(boxwidth, boxheight, scheme["WindowFrame"])
+    inside = [1, 1, boxwidth - 1, boxheight - 1]
+    simplebox(box, boxwidth, boxheight, scheme["InfoWindow"], *inside)
+    for i in range(len(lines)):
+        y = 3 * scale + i * lineheight
+        textdraw(
+            box, boxwidth, boxheight, scheme["InfoText"], 4 * scale, y, lines[i], scale
+        )
+    mark = {"image": box, "width": boxwidth, "height": boxheight}
+    return stamp(image, width, height, mark, corner, opacity, margin=margin, area=area)
+
+def fortuneoverlay(
+    image,
+    width,
+    height,
+    f,
+    seed=None,
+    boxwidth=200,
+    corner="bottomright",
+    opacity=1.0,
+    margin=8,
+    area=None,
+):
+    # Draws a saying chosen at random from a text file (see randomfortune())
+    # in a box in a corner of an image (see textboxoverlay()).  Using the
+    # same seed as the wallpaper gives each wallpaper its own saying.
+    # Returns 'image'.
+    text = randomfortune(f, seed)
+    return textboxoverlay(
+        image, width, height, text, boxwidth, corner, opacity, margin=margin, area=area
+    )
+
+# desktop preview
+
+def _drawdesktopicon(helper, x0, y0):
+    # Draws a generic 32x32 "window" icon
+    helper.rect(x0 + 2, y0 + 4, x0 + 30, y0 + 28, [0, 0, 0])
+    helper.rect(x0 + 3, y0 + 5, x0 + 29, y0 + 27, [255, 255, 255])
+    helper.rect(x0 + 3, y0 + 5, x0 + 29, y0 + 10, [0, 0, 128])
+    helper.rect(x0 + 25, y0 + 6, x0 + 28, y0 + 9, [192, 192, 192])
+
+def desktoppreview(
+    tile,
+    tilewidth,
+    tileheight,
+    screenwidth=640,
+    screenheight=480,
+    iconcount=6,
+    labelcolor=None,
+    labelbgcolor=None,
+):
+    # Draws a preview of a classic desktop with the given wallpaper tile,
+    # with a column of desktop icons and their labels at the left and a
+    # taskbar at the bottom, to help judge whether icon labels are readable
+    # on the wallpaper.
+    # 'labelcolor' is the color of icon labels; default is white.
+    # 'labelbgcolor' is the color drawn behind icon labels, or None (the default)
+    # to draw no background.
+    # Returns the preview image.
+    if screenwidth < 96 or screenheight < 64 or iconcount < 0:
+        raise ValueError
+    image = wraparoundcrop(tile, tilewidth, tileheight, 0, 0, screenwidth, screenheight)
+    helper = ImageWraparoundDraw(image, screenwidth, screenheight)
+    labelcolor = labelcolor if labelcolor else [255, 255, 255]
+    names = ["My Computer", "Network", "Recycle Bin", "Briefcase", "Documents"]
+    taskbarheight = 28
+    y = 4
+    x = 4
+    for i in range(iconcount):
+        if y + 32 + 14 > screenheight - taskbarheight:
+            # Start a new column of icons
+            y = 4
+            x += 76
+            if x + 76 > screenwidth:
+                break
+        _drawdesktopicon(helper, x + 22, y)
+        name = names[i] if i < len(names) else "Folder %d" % (i - len(names) + 1)
+        tw = textwidth(name)
+        textdraw(
+            image,
+            screenwidth,
+            screenheight,
+            labelcolor,
+            x + 38 - tw // 2,
+            y + 36,
+            name,
+            bgcolor=labelbgcolor,
+        )
+        y += 75
+    _drawtaskbar(image, screenwidth, screenheight, taskbarheight)
+    return image
+
+def _drawtaskbar(
+    image, screenwidth, screenheight, taskbarheight, tasks=None, active=None, clock=None
+):
+    # Draws a taskbar with a Start button, a button for each of the given
+    # task names (with the button for the task at index 'active' pressed),
+    # and a clock
+    hilt = [255, 255, 255]
+    lt = [192, 192, 192]
+    sh = [128, 128, 128]
+    dksh = [0, 0, 0]
+    helper = ImageWraparoundDraw(image, screenwidth, screenheight)
+    ty = screenheight - taskbarheight
+    helper.rect(0, ty, screenwidth, ty + 1, lt)
+    helper.rect(0, ty + 1, screenwidth, ty + 2, hilt)
+    helper.rect(0, ty + 2, screenwidth, screenheight, lt)
+    buttonup(helper, 2, ty + 4, 56, screenheight - 2, hilt, lt, sh, dksh)
+    textdraw(image, screenwidth, screenheight, dksh, 14, ty + 10, "Start")
+    tasks = tasks if tasks else []
+    clock = clock if clock else "12:00 PM"
+    cw = textwidth(clock) + 16
+    statusfieldbox(
+        helper,
+        screenwidth - cw - 2,
+        ty + 4,
+        screenwidth - 2,
+        screenheight - 2,
+        hilt,
+        lt,
+        sh,
+        dksh,
+    )
+    textdraw(
+        image, screenwidth, screenheight, dksh, screenwidth - cw + 6, ty + 10, clock
+    )
+    x = 60
+    bw = min(160, (screenwidth - cw - 66) // max(1, len(tasks)) - 3)
+    for i in range(len(tasks)):
+        button = buttondown if i == active else buttonup
+        button(helper, x, ty + 4, x + bw, screenheight - 2, hilt, lt, sh, dksh)
+        # Shorten the task name to fit the button
+        name = tasks[i][: max(0, (bw - 8) // 6)]
+        dy = 1 if i == active else 0
+        textdraw(image, screenwidth, screenheight, dksh, x + 5, ty + 10 + dy, name)
+        x += bw + 3
+
+def _drawmaskedicon(image, width, height, icon, x0, y0):
+    # Draws an icon from deriveicon(), leaving its transparent pixels unchanged
+    iw = icon["width"]
+    for y in range(icon["height"]):
+        for x in range(iw):
+            if icon["mask"][y * iw + x] == 0:
+                sp = (y * iw + x) * 3
+                dp = (((y0 + y) % height) * width + (x0 + x) % width) * 3
+                image[dp : dp + 3] = icon["image"][sp : sp + 3]
+
+def _fakenotepad(image, width, height, x0, y0, active, wallpaper):
+    lines = [
+        "Dear diary,",
+        "",
+        "Today I changed my",
+        "wallpaper again.  This",
+        "one has the %s style." % wallpaper["style"],
+    ]
+    caption = "Untitled - Notepad"
+    r = drawwindow(
+        image,
+        width,
+        height,
+        x0,
+        y0,
+        x0 + 220,
+        y0 + 150,
+        caption,
+        menu=["File", "Edit", "Search", "Help"],
+        active=active,
+        client=[255, 255, 255],
+    )
+    for i in range(len(lines)):
+        textdraw(image, width, height, [0, 0, 0], r[0] + 2, r[1] + 2 + i * 10, lines[i])
+    return caption
+
+def _fakedisplaydialog(image, width, height, x0, y0, active, wallpaper):
+    scheme = classiccolorscheme()
+    hilt, lt, sh, dksh = _bevelcolors(scheme)
+    helper = ImageWraparoundDraw(image, width, height)
+    caption = "Display Properties"
+    r = drawwindow(
+        image,
+        width,
+        height,
+        x0,
+        y0,
+        x0 + 200,
+        y0 + 170,
+        caption,
+        active=active,
+        buttons=("close",),
+    )
+    groupbox(image, width, height, r[0] + 6, r[1] + 6, r[2] - 6, r[3] - 30, "Wallpaper")
+    # Sunken preview of the wallpaper
+    px = r[0] + 14
+    py = r[1] + 18
+    pw = r[2] - r[0] - 32
+    ph = r[3] - r[1] - 60
+    fieldbox(helper, px, py, px + pw + 4, py + ph + 4, hilt, lt, sh, dksh)
+    preview = wraparoundcrop(
+        wallpaper["image"], wallpaper["width"], wallpaper["height"], 0, 0, pw, ph
+    )
+    imageblit(image, width, height, preview, pw, ph, px + 2, py + 2)
+    for i, label in enumerate(["OK", "Cancel"]):
+        bx1 = r[2] - 6 - (1 - i) * 56
+        by1 = r[3] - 6
+        drawbutton(
+            helper,
+            bx1 - 52,
+            by1 - 18,
+            bx1,
+            by1,
+            hilt,
+            lt,
+            sh,
+            dksh,
+            scheme["ButtonFace"],
+            dksh,
+            isDefault=(i == 0),
+        )
+        tx = bx1 - 26 - textwidth(label) // 2
+        textdraw(image, width, height, scheme["ButtonText"], tx, by1 - 12, label)
+    return caption
+
+def fakedesktop(seed=None, screenwidth=640, screenheight=480):
+    # Draws a made-up screenshot of a classic desktop: a random tiled
+    # wallpaper (see randomwallpaper()), a grid of labeled desktop icons
+    # derived from random patterns (see deriveicon()), one or two open
+    # windows (see drawwindow()), and a taskbar with a clock.
+    # 'seed' is a seed for the random number generator, so that the same
+    # seed gives the same image; if None, a different image is made each time.
+    # The state of the global random number generator is left unchanged.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if screenwidth < 320 or screenheight < 240:
+        raise ValueError
+    rnd = random.Random(seed)
+    state = random.getstate()
+    try:
+        random.seed(rnd.randrange(1 << 32))
+        wallpaper = randomwallpaper(
+            crisp=True, screenwidth=screenwidth, screenheight=screenheight
+        )
+        icons = []
+        for i in range(rnd.randint(3, 6)):
+            pat = randomwallpaper(crisp=True)
+            shape = rnd.choice(["square", "round"])
+            icon = deriveicon(
+                pat["image"], pat["width"], pat["height"], [32], shape=shape
+            )
+            icons.append(icon[0])
+    finally:
+        random.setstate(state)
+    image = wraparoundcrop(
+        wallpaper["image"],
+        wallpaper["width"],
+        wallpaper["height"],
+        0,
+        0,
+        screenwidth,
+        screenheight,
+    )
+    scheme = classiccolorscheme()
+    taskbarheight = 28
+    # Icons, in columns from the top left
+    names = ["My Computer", "Network", "Recycle Bin", "Briefcase", "Documents"]
+    names += ["Paint", "Solitaire", "Wallpapers"]
+    rnd.shuffle(names)
+    x = 4
+    y = 4
+    for i in range(len(icons)):
+        if y + 46 > screenheight - taskbarheight:
+            y = 4
+            x += 76
+        _drawmaskedicon(image, screenwidth, screenheight, icons[i], x + 22, y)
+        tw = textwidth(names[i])
+        textdraw(
+            image,
+            screenwidth,
+            screenheight,
+            scheme["HilightText"],
+            x + 38 - tw // 2,
+            y + 36,
+            names[i],
+            bgcolor=scheme["Background"],
+        )
+        y += 60
+    # Windows, with the last one drawn active
+    kinds = rnd.sample(["notepad", "display"], rnd.randint(1, 2))
+    tasks = []
+    for i in range(len(kinds)):
+        active = i == len(kinds) - 1
+        # Keep windows right of the icons where there is room
+        wx = rnd.randint(min(x + 80, screenwidth - 224), screenwidth - 224)
+        wy = rnd.randint(8, screenheight - taskbarheight - 180)
+        if kinds[i] == "notepad":
+            draw = _fakenotepad
+        else:
+            draw = _fakedisplaydialog
+        tasks.append(
+            draw(image, screenwidth, screenheight, wx, wy, active, wallpaper)
+        )
+    hour = rnd.randint(1, 12)
+    clock = "%d:%02d %s" % (hour, rnd.randint(0, 59), rnd.choice(["AM", "PM"]))
+    _drawtaskbar(
+        image, screenwidth, screenheight, taskbarheight, tasks, len(tasks) - 1, clock
+    )
+    return {"image": image, "width": screenwidth, "height": screenheight}
+
+# random wallpaper generation
+
+def _randomdither(image, palette):
+    grays = getgrays(palette)
+    if len(grays) >= 2 and random.randint(0, 99) < 10:
+        # Convert to the grays in the palette
+        dithertograyimage(image["image"], image["width"], image["height"], grays)
+    else:
+        # Dither away from half-and-half colors
+        halfhalfditherimage(
+            image["image"],
+            image["width"],
+            image["height"],
+            palette,
+        )
+    return image
+
+def _randomhatch(pal, halfhalf=True):
+    # Undithered random hatch image; see randomhatchimage()
+    expandedpal = paletteandhalfhalf(pal) if halfhalf else pal
+    if random.randint(0, 99) < 50:
+        w = random.randint(40, 96)
+        w -= w % 2  # make even
         image = diagcrosshatch(
             w,
             random.randint(0, 16),
@@ -2263,36 +5404,47 @@ def randomhatchimage(palette=None):
             bgcolor=random.choice(expandedpal),
             fgcolor=random.choice(expandedpal),
         )
-        return _randomdither({"image": image, "width": w, "height": w}, pal)
+        return {"image": image, "width": w, "height": w}
     else:
         hhatch = random.randint(0, 7)
         vhatch = random.randint(0, 7)
-        return _randomdither(
-            crosshatch(
-                hhatch + random.randint(4, 32),
-                vhatch + random.randint(4, 32),
-                hhatch,
-                vhatch,
-                bgcolor=random.choice(expandedpal),
-                fgcolor=random.choice(expandedpal),
-            ),
-            pal,
+        return crosshatch(
+            hhatch + random.randint(4, 32),
+            vhatch + random.randint(4, 32),
+            hhatch,
+            vhatch,
+            bgcolor=random.choice(expandedpal),
+            fgcolor=random.choice(expandedpal),
         )
 
-def randomboxesimage(palette=None):
-    # Generates a random boxes image using the given palette
+def randomhatchimage(palette=None):
+    # Generates a random hatch image using the given palette
     # (default is the palette in classiccolors)
     pal = palette if palette else classiccolors()
-    expandedpal = paletteandhalfhalf(pal)
+    return _randomdither(_randomhatch(pal), pal)
+
+def _randomboxes(pal, halfhalf=True):
+    # Undithered random boxes image; see randomboxesimage()
+    expandedpal = paletteandhalfhalf(pal) if halfhalf else pal
     w = random.randint(160, 256)
     w -= w % 2  # make even
     h = random.randint(140, 256)
     h -= h % 2  # make even
-    image = randomboxes(w, h, expandedpal)
-    return _randomdither({"image": image, "width": w, "height": h}, pal)
+    shading = None
+    if random.randint(0, 1) == 0:
+        shading = random.sample(["flat", "raised", "sunken", "shadow", "gradient"], 2)
+    spread = random.randint(0, 2) == 0
+    image = randomboxes(w, h, expandedpal, shading, spread)
+    return {"image": image, "width": w, "height": h}
 
-def randombrushednoiseimage(palette=None):
+def randomboxesimage(palette=None):
+    # Generates a random boxes image using the given palette
+    # (default is the palette in classiccolors)
     pal = palette if palette else classiccolors()
+    return _randomdither(_randomboxes(pal), pal)
+
+def _randombrushednoise():
+    # Undithered random brushed noise image; see randombrushednoiseimage()
     w = random.randint(96, 224)
     w -= w % 8  # make divisible by 8
     h = random.randint(96, 224)
@@ -2304,28 +5456,40 @@ def randombrushednoiseimage(palette=None):
         image = brushednoise2(w, h)
     else:
         image = brushednoise3(w, h)
-    graymap(
-        image,
-        w,
-        h,
-        colorgradient([0, 0, 0], [random.randint(0, 255) for i in range(3)]),
-    )
-    patternDither(image, w, h, pal)
+    if random.randint(0, 3) == 0:
+        colorramp(random.choice(["fire", "ice", "copper"])).apply(image, w, h)
+    else:
+        graymap(
+            image,
+            w,
+            h,
+            colorgradient([0, 0, 0], [random.randint(0, 255) for i in range(3)]),
+        )
     return {"image": image, "width": w, "height": h}
 
-def randomcheckimage(palette=None):
-    # Generates a random checkerboard pattern image using the given palette
-    # (default is the palette in classiccolors)
+def randombrushednoiseimage(palette=None):
     pal = palette if palette else classiccolors()
-    expandedpal = paletteandhalfhalf(pal)
+    image = _randombrushednoise()
+    patternDither(image["image"], image["width"], image["height"], pal)
+    return image
+
+def _randomcheck(pal, halfhalf=True):
+    # Undithered random checkerboard image; see randomcheckimage()
+    expandedpal = paletteandhalfhalf(pal) if halfhalf else pal
     w = random.randint(16, 128)
     w -= w % 2  # make even
     h = random.randint(16, 128)
     h -= h % 2  # make even
     hatch = None if random.randint(0, 1) == 0 else random.choice(expandedpal)
-    image = blankimage(width, height, random.choice(expandedpal))
+    image = blankimage(w, h, random.choice(expandedpal))
     checkerboardoverlay(image, w, h, random.choice(expandedpal), hatch)
-    return _randomdither({"image": image, "width": w, "height": h}, pal)
+    return {"image": image, "width": w, "height": h}
+
+def randomcheckimage(palette=None):
+    # Generates a random checkerboard pattern image using the given palette
+    # (default is the palette in classiccolors)
+    pal = palette if palette else classiccolors()
+    return _randomdither(_randomcheck(pal), pal)
 
 def monochromeFromThreeGrays(image, width, height):
     # Input image uses only three colors: (0,0,0),(128,128,128),(255,255,255)
@@ -2438,6 +5602,797 @@ def vgaVariantsFromThreeGrays(image, width, height):
         "dark": dark,
     }
 
+# symmetry operations
+#
+# Unlike the ImageMagick commands for wallpaper groups given earlier, which
+# make an image larger, the following operation keeps the image's size by
+# replacing parts of the image with mirrored or rotated copies of other parts.
+
+def _imagemirrorx(image, width, height):
+    # Right half becomes a mirror image of the left half
+    for y in range(height):
+        yp = y * width * 3
+        for x in range(width // 2):
+            sp = yp + x * 3
+            dp = yp + (width - 1 - x) * 3
+            image[dp] = image[sp]
+            image[dp + 1] = image[sp + 1]
+            image[dp + 2] = image[sp + 2]
+
+def _imagemirrory(image, width, height, flipx=False):
+    # Bottom half becomes a mirror image of the top half (also
+    # mirrored horizontally if 'flipx' is True)
+    for y in range(height // 2):
+        sy = y * width * 3
+        dy = (height - 1 - y) * width * 3
+        for x in range(width):
+            sp = sy + x * 3
+            dp = dy + ((width - 1 - x) if flipx else x) * 3
+            image[dp] = image[sp]
+            image[dp + 1] = image[sp + 1]
+            image[dp + 2] = image[sp + 2]
+
+def imagesymmetry(image, width, height, group):
+    # Gives an image the symmetry of a wallpaper group, so that the
+    # image, when tiled, forms that group's tiling pattern.  The image's
+    # size stays the same.
+    # 'group' is one of the following:
+    # "pm": The right half becomes a mirror image of the left half.
+    # "pg": The bottom half becomes the top half mirrored horizontally
+    # (a glide reflection).  If the height is odd, the middle row becomes
+    # a mirror image of itself.
+    # "p2": The bottom half becomes the top half turned 180 degrees.
+    # "pmm": As "pm", then the bottom half becomes a mirror image of the top half.
+    # "p4m": As "pmm", but first the top left quarter is made symmetric about
+    # its diagonal.  If the image isn't square, acts as "pmm".
+    # Returns 'image'.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if group == "pm":
+        _imagemirrorx(image, width, height)
+    elif group == "pg":
+        for y in range(height // 2):
+            sy = y * width * 3
+            dy = (y + (height + 1) // 2) * width * 3
+            for x in range(width):
+                sp = sy + x * 3
+                dp = dy + (width - 1 - x) * 3
+                image[dp] = image[sp]
+                image[dp + 1] = image[sp + 1]
+                image[dp + 2] = image[sp + 2]
+        if height % 2 == 1:
+            # The middle row has no counterpart, so its right half
+            # becomes a mirror image of its left half
+            yp = (height // 2) * width * 3
+            for x in range(width // 2):
+                sp = yp + x * 3
+                dp = yp + (width - 1 - x) * 3
+                image[dp] = image[sp]
+                image[dp + 1] = image[sp + 1]
+                image[dp + 2] = image[sp + 2]
+    elif group == "p2":
+        _imagemirrory(image, width, height, True)
+    elif group == "pmm":
+        _imagemirrorx(image, width, height)
+        _imagemirrory(image, width, height)
+    elif group == "p4m":
+        for y in range(height // 2 if width == height else 0):
+            for x in range(y):
+                sp = (x * width + y) * 3
+                dp = (y * width + x) * 3
+                image[dp] = image[sp]
+                image[dp + 1] = image[sp + 1]
+                image[dp + 2] = image[sp + 2]
+        _imagemirrorx(image, width, height)
+        _imagemirrory(image, width, height)
+    else:
+        raise ValueError
+    return image
+
+def quilt(seed=None, palette=None, levels=3):
+    # Generates a random quilt-like image with nested symmetry, using
+    # the given palette (default is the palette in classiccolors).
+    # Starting with a small square, the following is done 'levels' times:
+    # Random boxes and hatched boxes are drawn on the square, the square is
+    # given a randomly chosen symmetry (see imagesymmetry()), and, unless
+    # this is the last time, the square is doubled in size by tiling it
+    # twice across and twice down.
+    # 'seed' is a seed for the random number generator, so that the same
+    # seed gives the same image; if None, a different image is made each time.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if levels <= 0 or int(levels) != levels:
+        raise ValueError
+    rnd = random.Random(seed)
+    pal = palette if palette else classiccolors()
+    expandedpal = paletteandhalfhalf(pal)
+    patterns = [
+        [0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55],
+        [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01],
+        [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80],
+        [0xFF, 0, 0, 0, 0xFF, 0, 0, 0],
+        [0x88, 0x88, 0x88, 0x88, 0x88, 0x88, 0x88, 0x88],
+    ]
+    size = 8
+    image = blankimage(size, size, rnd.choice(expandedpal))
+    for level in range(levels):
+        for i in range(rnd.randint(1, 4)):
+            x0 = rnd.randint(0, size - 1)
+            y0 = rnd.randint(0, size - 1)
+            x1 = x0 + rnd.randint(1, max(1, size // 2))
+            y1 = y0 + rnd.randint(1, max(1, size // 2))
+            color = rnd.choice(expandedpal)
+            if rnd.randint(0, 1) == 0:
+                simplebox(image, size, size, color, x0, y0, x1, y1)
+            else:
+                pattern = rnd.choice(patterns)
+                hatchedbox(image, size, size, color, pattern, x0, y0, x1, y1)
+        imagesymmetry(image, size, size, rnd.choice(["pm", "pg", "p2", "pmm", "p4m"]))
+        if level < levels - 1:
+            ns = size * 2
+            newimage = blankimage(ns, ns)
+            for y in range(2):
+                for x in range(2):
+                    imageblit(newimage, ns, ns, image, size, size, x * size, y * size)
+            image = newimage
+            size = ns
+    halfhalfditherimage(image, size, size, pal)
+    return {"image": image, "width": size, "height": size}
+
+# texture synthesis
+
+def wavefunctioncollapse(
+    image, width, height, outwidth, outheight, n=3, seed=None, tries=10
+):
+    # Generates a tileable image of size 'outwidth' x 'outheight' that
+    # resembles the given image (such as a small tile) without simply
+    # repeating it, using the "overlapping" wave function collapse
+    # algorithm.  Every 'n' x 'n' block of pixels in the output (including
+    # blocks that wrap around its edges) also occurs in the input image
+    # (where blocks likewise wrap around), and blocks that occur more often
+    # in the input are more likely to be chosen.  'seed' seeds the random
+    # number generator.  Raises an error if no such image could be found
+    # after 'tries' attempts.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if width <= 0 or height <= 0 or outwidth <= 0 or outheight <= 0 or n <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    rnd = random.Random(seed)
+    # Colors of the input as single integers
+    keys = [
+        image[i] | (image[i + 1] << 8) | (image[i + 2] << 16)
+        for i in range(0, width * height * 3, 3)
+    ]
+    patterns = []
+    weights = []
+    index = {}
+    for y in range(height):
+        for x in range(width):
+            pat = tuple(
+                keys[((y + j) % height) * width + (x + i) % width]
+                for j in range(n)
+                for i in range(n)
+            )
+            if pat in index:
+                weights[index[pat]] += 1
+            else:
+                index[pat] = len(patterns)
+                patterns.append(pat)
+                weights.append(1)
+    count = len(patterns)
+    dirs = [[1, 0], [-1, 0], [0, 1], [0, -1]]
+
+    def agrees(p, q, dx, dy):
+        # Whether pattern q, placed (dx, dy) pixels from pattern p,
+        # overlaps it with the same colors
+        for j in range(max(0, dy), min(n, n + dy)):
+            for i in range(max(0, dx), min(n, n + dx)):
+                if p[j * n + i] != q[(j - dy) * n + (i - dx)]:
+                    return False
+        return True
+
+    # compat[d][p] is a bit mask of the patterns that can be the neighbor
+    # of pattern p in direction d
+    compat = []
+    for dx, dy in dirs:
+        masks = []
+        for p in range(count):
+            mask = 0
+            for q in range(count):
+                if agrees(patterns[p], patterns[q], dx, dy):
+                    mask |= 1 << q
+            masks.append(mask)
+        compat.append(masks)
+    cells = outwidth * outheight
+    full = (1 << count) - 1
+    for attempt in range(tries):
+        wave = [full for i in range(cells)]
+        ok = True
+        while ok:
+            # Find the undecided cell with the fewest possible patterns
+            # (ties are broken at random)
+            best = None
+            bestscore = 0
+            for c in range(cells):
+                k = bin(wave[c]).count("1")
+                if k > 1:
+                    score = k + rnd.random() * 0.5
+                    if best is None or score < bestscore:
+                        best = c
+                        bestscore = score
+            if best is None:
+                break
+            # Choose one of its patterns, weighted by how often each occurs
+            choices = [p for p in range(count) if (wave[best] >> p) & 1]
+            total = sum(weights[p] for p in choices)
+            r = rnd.randrange(total)
+            for p in choices:
+                r -= weights[p]
+                if r < 0:
+                    wave[best] = 1 << p
+                    break
+            # Remove patterns that no longer fit from neighboring cells
+            stack = [best]
+            while stack and ok:
+                c = stack.pop()
+                cx = c % outwidth
+                cy = c // outwidth
+                for d in range(4):
+                    nb = ((cy + dirs[d][1]) % outheight) * outwidth + (
+                        (cx + dirs[d][0]) % outwidth
+                    )
+                    allowed = 0
+                    mask = wave[c]
+                    p = 0
+                    while mask:
+                        if mask & 1:
+                            allowed |= compat[d][p]
+                        mask >>= 1
+                        p += 1
+                    newmask = wave[nb] & allowed
+                    if newmask == 0:
+                        ok = False
+                        break
+                    if newmask != wave[nb]:
+                        wave[nb] = newmask
+                        stack.append(nb)
+        if not ok:
+            continue
+        ret = [0 for i in range(cells * 3)]
+        for c in range(cells):
+            key = patterns[wave[c].bit_length() - 1][0]
+            ret[c * 3] = key & 0xFF
+            ret[c * 3 + 1] = (key >> 8) & 0xFF
+            ret[c * 3 + 2] = (key >> 16) & 0xFF
+        return {"image": ret, "width": outwidth, "height": outheight}
+    raise ValueError("no image found")
+
+# Wang tiles are square tiles whose edges are each given a color; a tiling
+# places tiles so that touching edges have the same color.  Because many
+# different tiles can fit in each place, a large image made of Wang tiles
+# doesn't repeat as obviously as a grid of copies of one tile.
+
+def wangtileset(image, width, height, tilesize, colors=2, seed=None):
+    # Makes a complete set of Wang tiles of size 'tilesize' x 'tilesize' from
+    # a tileable image, with 'colors' edge colors for horizontal edges and
+    # as many for vertical edges, so that there are colors**4 tiles.
+    # Each edge color stands for a region of the image chosen at random
+    # (using 'seed' to seed the random number generator), and each tile
+    # is made of four triangles meeting at its center, each copied from the
+    # region for the color of the edge the triangle touches; two tiles
+    # sharing an edge thus show a seamless diamond from the image there.
+    # Returns a list of dictionaries, each with the keys "image" and "edges"
+    # (the colors of the tile's top, right, bottom, and left edges, in
+    # that order).
+    if width <= 0 or height <= 0 or tilesize <= 0 or colors <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    rnd = random.Random(seed)
+    hregions = [[rnd.randrange(width), rnd.randrange(height)] for i in range(colors)]
+    vregions = [[rnd.randrange(width), rnd.randrange(height)] for i in range(colors)]
+    t = tilesize
+    ret = []
+    for n in range(colors):
+        for e in range(colors):
+            for so in range(colors):
+                for w in range(colors):
+                    tile = [0 for i in range(t * t * 3)]
+                    for y in range(t):
+                        for x in range(t):
+                            # Distance from the tile's center, doubled
+                            dx = x + x + 1 - t
+                            dy = y + y + 1 - t
+                            if abs(dy) >= abs(dx):
+                                if dy < 0:
+                                    sx = hregions[n][0] + x
+                                    sy = hregions[n][1] + y
+                                else:
+                                    sx = hregions[so][0] + x
+                                    sy = hregions[so][1] + y - t
+                            elif dx < 0:
+                                sx = vregions[w][0] + x
+                                sy = vregions[w][1] + y
+                            else:
+                                sx = vregions[e][0] + x - t
+                                sy = vregions[e][1] + y
+                            sp = ((sy % height) * width + (sx % width)) * 3
+                            dp = (y * t + x) * 3
+                            tile[dp] = image[sp]
+                            tile[dp + 1] = image[sp + 1]
+                            tile[dp + 2] = image[sp + 2]
+                    ret.append({"image": tile, "edges": [n, e, so, w]})
+    return ret
+
+def _wangwraps(tile, edge1, edge2):
+    return tile["edges"][edge1] == tile["edges"][edge2]
+
+def wangtiling(tiles, columns, rows, seed=None):
+    # Chooses Wang tiles from the given list (see wangtileset()) for a grid
+    # with the given number of columns and rows, so that touching edges have
+    # the same color, including edges that touch when the grid wraps around.
+    # Tiles are chosen at random among those that fit, using 'seed' to seed
+    # the random number generator.  Raises an error if the tiles can't
+    # fill the grid this way.
+    # Returns a list of indices into 'tiles', row by row.
+    if columns <= 0 or rows <= 0 or len(tiles) == 0:
+        raise ValueError
+    rnd = random.Random(seed)
+    for attempt in range(100):
+        ret = []
+        for y in range(rows):
+            for x in range(columns):
+                # Edge colors required by tiles already placed, including
+                # those that wrap around to the first row or column
+                need = [None, None, None, None]
+                if y > 0:
+                    need[0] = tiles[ret[(y - 1) * columns + x]]["edges"][2]
+                if x > 0:
+                    need[3] = tiles[ret[-1]]["edges"][1]
+                if y == rows - 1 and y > 0:
+                    need[2] = tiles[ret[x]]["edges"][0]
+                if x == columns - 1 and x > 0:
+                    need[1] = tiles[ret[y * columns]]["edges"][3]
+                fits = [
+                    i
+                    for i in range(len(tiles))
+                    if all(
+                        need[k] is None or need[k] == tiles[i]["edges"][k]
+                        for k in range(4)
+                    )
+                ]
+                # With a single row or column, a tile wraps around to touch
+                # itself, so its opposite edges must match
+                if rows == 1:
+                    fits = [i for i in fits if _wangwraps(tiles[i], 0, 2)]
+                if columns == 1:
+                    fits = [i for i in fits if _wangwraps(tiles[i], 1, 3)]
+                if len(fits) == 0:
+                    break
+                ret.append(rnd.choice(fits))
+            if len(ret) != (y + 1) * columns:
+                break
+        if len(ret) == columns * rows:
+            return ret
+    raise ValueError("no tiling found")
+
+def wangtileimage(image, width, height, tilesize, columns, rows, colors=2, seed=None):
+    # Generates a tileable image, 'columns' x 'rows' Wang tiles in size, from
+    # a tileable image, using wangtileset() and wangtiling().
+    # Returns a dictionary with "image", "width", and "height" keys.
+    rnd = random.Random(seed)
+    tiles = wangtileset(image, width, height, tilesize, colors, rnd.random())
+    tiling = wangtiling(tiles, columns, rows, rnd.random())
+    w = columns * tilesize
+    h = rows * tilesize
+    ret = blankimage(w, h)
+    for i in range(len(tiling)):
+        imageblit(
+            ret,
+            w,
+            h,
+            tiles[tiling[i]]["image"],
+            tilesize,
+            tilesize,
+            (i % columns) * tilesize,
+            (i // columns) * tilesize,
+        )
+    return {"image": ret, "width": w, "height": h}
+
+# pattern descriptions
+#
+# A pattern description is a dictionary (such as one read from a JSON file)
+# that says how to draw a wallpaper pattern, so that the pattern can be
+# stored as data and drawn at any size.  It has the following keys:
+# "background": Background color, a 3-item array of the red, green, and blue
+# components in that order; default is white.
+# "layers": List of operations, carried out in order, each of which is a
+# dictionary whose "type" key is one of the following:
+# - "box": Box from ("x0", "y0") to ("x1", "y1") in "color".  Optional
+# "color2" dithers the box's inside with that color (see borderedbox()),
+# and optional "border" draws a 1-pixel border in that color.
+# - "hatch": Box as in "box", filled with the 8x8 "pattern" in "color"
+# (see hatchedbox()).
+# - "line": Line from ("x0", "y0") to ("x1", "y1") in "color".
+# - "circle": Circle centered at ("cx", "cy") with radius "r" in "color".
+# - "diagstripe": Diagonal stripe with thickness "size" in "color"; if
+# "reverse" is true, the stripe runs from top right to bottom left.
+# - "symmetry": Applies a wallpaper group's symmetry, given as "group";
+# see imagesymmetry().
+# - "colorize": Converts the image to gray, then maps black to "color1" and
+# white to "color2" (see graymap() and colorgradient()).
+# - "dither": Reduces the image's colors to those in "palette", either the
+# name of a palette ("vga", "cga", "ega", "websafe", "2color") or a list of
+# colors.  Optional "method" is "pattern" (default; see patternDither()),
+# "gray" (see dithertograyimage()) or "halfhalf" (see halfhalfditherimage()).
+# Positions and sizes are given as fractions of the image width (for x
+# coordinates, radii, and stripe sizes) or height (for y coordinates),
+# so that 0.5 means the middle of the image.  Shapes wrap around
+# the image's edges.
+
+def _descpalette(p):
+    if isinstance(p, list):
+        return p
+    palettes = {
+        "vga": classiccolors,
+        "cga": cgacolors,
+        "ega": egacolors,
+        "websafe": websafecolors,
+        "2color": lambda: [[0, 0, 0], [255, 255, 255]],
+    }
+    if p not in palettes:
+        raise ValueError("unknown palette: %s" % (p))
+    return palettes[p]()
+
+def renderpattern(desc, width, height):
+    # Draws an image of the given size from a pattern description (see above).
+    # 'desc' is either a dictionary or a string in JSON format.
+    # Returns the image.
+    if isinstance(desc, str):
+        desc = json.loads(desc)
+    if width <= 0 or height <= 0:
+        raise ValueError
+    image = blankimage(width, height, desc.get("background", None))
+
+    def px(layer, key):
+        return int(round(layer[key] * width))
+
+    def py(layer, key):
+        return int(round(layer[key] * height))
+
+    for layer in desc.get("layers", []):
+        kind = layer["type"]
+        if kind == "box" or kind == "hatch":
+            x0 = min(px(layer, "x0"), px(layer, "x1"))
+            x1 = max(px(layer, "x0"), px(layer, "x1"))
+            y0 = min(py(layer, "y0"), py(layer, "y1"))
+            y1 = max(py(layer, "y0"), py(layer, "y1"))
+            # Move the box so that its top left corner is within the image
+            ox = x0 % width - x0
+            oy = y0 % height - y0
+            if kind == "hatch":
+                hatchedbox(
+                    image,
+                    width,
+                    height,
+                    layer["color"],
+                    layer["pattern"],
+                    x0 + ox,
+                    y0 + oy,
+                    x1 + ox,
+                    y1 + oy,
+                )
+            else:
+                borderedbox(
+                    image,
+                    width,
+                    height,
+                    layer.get("border", None),
+                    layer["color"],
+                    layer.get("color2", layer["color"]),
+                    x0 + ox,
+                    y0 + oy,
+                    x1 + ox,
+                    y1 + oy,
+                )
+        elif kind == "line":
+            linedraw(
+                image,
+                width,
+                height,
+                layer["color"],
+                px(layer, "x0"),
+                py(layer, "y0"),
+                px(layer, "x1"),
+                py(layer, "y1"),
+                drawEndPoint=True,
+            )
+        elif kind == "circle":
+            circledraw(
+                image,
+                width,
+                height,
+                layer["color"],
+                px(layer, "cx"),
+                py(layer, "cy"),
+                px(layer, "r"),
+            )
+        elif kind == "diagstripe":
+            _drawdiagstripe(
+                image,
+                width,
+                height,
+                min(max(width, height), px(layer, "size")),
+                layer.get("reverse", False),
+                fgcolor=layer["color"],
+            )
+        elif kind == "symmetry":
+            imagesymmetry(image, width, height, layer["group"])
+        elif kind == "colorize":
+            graymap(
+                image,
+                width,
+                height,
+                colorgradient(layer["color1"], layer["color2"]),
+            )
+        elif kind == "dither":
+            pal = _descpalette(layer["palette"])
+            method = layer.get("method", "pattern")
+            if method == "pattern":
+                patternDither(image, width, height, pal)
+            elif method == "gray":
+                dithertograyimage(image, width, height, getgrays(pal))
+            elif method == "halfhalf":
+                # Colors not yet in the palette or its half-and-half
+                # mixtures are mapped to the nearest such color first
+                expanded = paletteandhalfhalf(pal)
+                for i in range(0, width * height * 3, 3):
+                    c = expanded[_nearest_rgb3(expanded, *image[i : i + 3])]
+                    image[i] = c[0]
+                    image[i + 1] = c[1]
+                    image[i + 2] = c[2]
+                halfhalfditherimage(image, width, height, pal)
+            else:
+                raise ValueError("unknown dither method: %s" % (method))
+        else:
+            raise ValueError("unknown layer type: %s" % (kind))
+    return image
+
+def randompatterndesc(palette=None, halfhalf=True):
+    # Generates a random pattern description (see above) using
+    # the given palette (default is the palette in classiccolors).
+    # 'halfhalf' means the description can also use colors halfway between two
+    # of the palette's colors, which its last layer dithers away; if False,
+    # only the palette's colors are used.
+    # The result can be saved with json.dumps() and drawn with renderpattern().
+    pal = palette if palette else classiccolors()
+    expandedpal = paletteandhalfhalf(pal) if halfhalf else pal
+    layers = []
+    for i in range(random.randint(3, 12)):
+        r = random.randint(0, 3)
+        x0 = random.randint(0, 15) / 16
+        y0 = random.randint(0, 15) / 16
+        x1 = x0 + random.randint(1, 8) / 16
+        y1 = y0 + random.randint(1, 8) / 16
+        color = random.choice(expandedpal)
+        if r == 0:
+            layers.append(
+                {"type": "box", "x0": x0, "y0": y0, "x1": x1, "y1": y1, "color": color}
+            )
+        elif r == 1:
+            layers.append(
+                {
+                    "type": "hatch",
+                    "x0": x0,
+                    "y0": y0,
+                    "x1": x1,
+                    "y1": y1,
+                    "color": color,
+                    "pattern": random.choice(
+                        [
+                            [0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55],
+                            [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01],
+                            [0xFF, 0, 0, 0, 0xFF, 0, 0, 0],
+                            [0x88, 0x88, 0x88, 0x88, 0x88, 0x88, 0x88, 0x88],
+                        ]
+                    ),
+                }
+            )
+        elif r == 2:
+            layers.append(
+                {"type": "line", "x0": x0, "y0": y0, "x1": x1, "y1": y1, "color": color}
+            )
+        else:
+            layers.append(
+                {
+                    "type": "circle",
+                    "cx": x0,
+                    "cy": y0,
+                    "r": random.randint(1, 6) / 16,
+                    "color": color,
+                }
+            )
+    if random.randint(0, 1) == 0:
+        layers.append(
+            {
+                "type": "symmetry",
+                "group": random.choice(["pm", "pg", "p2", "pmm", "p4m"]),
+            }
+        )
+    layers.append({"type": "dither", "palette": pal, "method": "halfhalf"})
+    return {"background": random.choice(expandedpal), "layers": layers}
+
+def randomwallpaper(
+    palette=None,
+    style=None,
+    symmetry=None,
+    dither=None,
+    crisp=False,
+    screenwidth=None,
+    screenheight=None,
+    timings=None,
+):
+    # Generates a random wallpaper image, where the kind of image, whether it
+    # has symmetry, and how it's dithered are chosen independently of
+    # each other.
+    # 'palette' is a list of colors, the name of a palette (see "dither" under
+    # "pattern descriptions" above), or "random" to choose one of those
+    # palettes at random; default is the palette in websafecolors for the
+    # "brushednoise" style and the palette in classiccolors otherwise.
+    # 'style' is the kind of image: "hatch", "boxes", "brushednoise", "check",
+    # or "pattern" (see randompatterndesc()); if None, chosen at random.
+    # 'symmetry' is True to always give the image the symmetry of a randomly
+    # chosen wallpaper group (see imagesymmetry()) before dithering, False
+    # to never do so, or None (the default) to decide at random.
+    # 'dither' is how to reduce the image to the palette's colors:
+    # "halfhalf" (see halfhalfditherimage()), "gray" (see dithertograyimage();
+    # the palette must have at least two gray tones), "pattern" (see
+    # patternDither()), "floydsteinberg" (see floydSteinbergDither()), or
+    # "none" (leave the image's colors as they are); if None, the default is
+    # "floydsteinberg" for the "brushednoise" style and chosen at random
+    # between "halfhalf" and "gray" otherwise.
+    # 'crisp' means to draw using only the palette's colors in the first
+    # place, so that the image has crisp edges and no dithering is needed
+    # (and 'dither' is ignored).  The "brushednoise" style can't be
+    # drawn this way.
+    # 'screenwidth' and 'screenheight' are the size of the screen the image
+    # will be tiled on; if given, the image is resized by up to a quarter of
+    # its size so that it fits that screen evenly (see besttilesize()).
+    # 'timings', if given, is a dictionary to which the time taken by each
+    # stage ("generate", "resize", "symmetry", and "dither"), in seconds, is
+    # added; see timingreport().
+    # Returns a dictionary with "image", "width", and "height" keys, and
+    # "style", "group" (the wallpaper group used, or None), and "dither" keys
+    # giving the choices made.
+    styles = ["hatch", "boxes", "check", "pattern"]
+    if not crisp:
+        styles.append("brushednoise")
+    if style is None:
+        style = random.choice(styles)
+    if style not in styles:
+        raise ValueError
+    if crisp:
+        dither = "none"
+    if palette == "random":
+        palette = random.choice(["vga", "cga", "ega", "websafe"])
+    if palette:
+        pal = _descpalette(palette)
+    else:
+        # Smooth noise suits a palette with many colors; the other styles use
+        # few colors to begin with
+        pal = websafecolors() if style == "brushednoise" else classiccolors()
+    if dither is None:
+        if style == "brushednoise":
+            dither = "floydsteinberg"
+        elif len(getgrays(pal)) >= 2 and random.randint(0, 99) < 10:
+            dither = "gray"
+        else:
+            dither = "halfhalf"
+    if dither not in ["halfhalf", "gray", "pattern", "floydsteinberg", "none"]:
+        raise ValueError
+    start = time.perf_counter()
+    if style == "hatch":
+        image = _randomhatch(pal, not crisp)
+    elif style == "boxes":
+        image = _randomboxes(pal, not crisp)
+    elif style == "brushednoise":
+        image = _randombrushednoise()
+    elif style == "check":
+        image = _randomcheck(pal, not crisp)
+    else:
+        w = random.randint(64, 192)
+        w -= w % 2  # make even
+        h = random.randint(64, 192)
+        h -= h % 2  # make even
+        desc = randompatterndesc(pal, not crisp)
+        # Symmetry and dithering are done below instead
+        desc["layers"] = [
+            layer
+            for layer in desc["layers"]
+            if layer["type"] != "symmetry" and layer["type"] != "dither"
+        ]
+        image = {"image": renderpattern(desc, w, h), "width": w, "height": h}
+    start = _addtiming(timings, "generate", start)
+    if screenwidth and screenheight:
+        w = image["width"]
+        h = image["height"]
+        size = besttilesize(
+            screenwidth,
+            screenheight,
+            [max(1, w * 3 // 4), max(1, h * 3 // 4)],
+            [w * 5 // 4, h * 5 // 4],
+        )
+        if size != [w, h]:
+            image["image"] = nearestresize(image["image"], w, h, size[0], size[1])
+            image["width"] = size[0]
+            image["height"] = size[1]
+        start = _addtiming(timings, "resize", start)
+    group = None
+    if symmetry or (symmetry is None and random.randint(0, 1) == 0):
+        group = random.choice(["pm", "pg", "p2", "pmm", "p4m"])
+        imagesymmetry(image["image"], image["width"], image["height"], group)
+        start = _addtiming(timings, "symmetry", start)
+    img = image["image"]
+    w = image["width"]
+    h = image["height"]
+    if dither == "halfhalf":
+        if style == "brushednoise":
+            # Reduce the image to half-and-half colors first
+            patternDither(img, w, h, paletteandhalfhalf(pal))
+        halfhalfditherimage(img, w, h, pal)
+    elif dither == "gray":
+        dithertograyimage(img, w, h, getgrays(pal))
+    elif dither == "pattern":
+        patternDither(img, w, h, pal)
+    elif dither == "floydsteinberg":
+        floydSteinbergDither(img, w, h, pal)
+    _addtiming(timings, "dither", start)
+    image["style"] = style
+    image["group"] = group
+    image["dither"] = dither
+    return image
+
+def _addtiming(timings, stage, start):
+    # Adds the time since 'start' to the given stage's total in 'timings',
+    # if any, and returns the current time
+    now = time.perf_counter()
+    if timings is not None:
+        timings[stage] = timings.get(stage, 0) + (now - start)
+    return now
+
+def timingreport(timings):
+    # Returns a text breakdown of the times in a dictionary filled in by
+    # randomwallpaper() or another method taking a 'timings' parameter,
+    # slowest stage first.  Useful for finding which stages take the most
+    # time when generating many wallpapers.
+    total = sum(timings.values())
+    ret = ""
+    for stage in sorted(timings, key=lambda k: -timings[k]):
+        pct = 100 * timings[stage] / total if total > 0 else 0
+        ret += "%-12s %10.4f s %5.1f%%\n" % (stage, timings[stage], pct)
+    return ret + "%-12s %10.4f s\n" % ("total", total)
+
+def paletteindices(image, width, height, palette):
+    # Converts an image that uses only the colors in the given palette (such as
+    # one generated by randomwallpaper() with 'crisp' set to True) to a list
+    # with the index in the palette of each pixel's color, in the same order
+    # as the pixels.  Raises an error if a pixel's color isn't in the palette.
+    if len(image) != width * height * 3:
+        raise ValueError
+    indices = {}
+    for i in range(len(palette)):
+        c = palette[i]
+        key = c[0] | (c[1] << 8) | (c[2] << 16)
+        if key not in indices:
+            indices[key] = i
+    ret = [0 for i in range(width * height)]
+    for i in range(width * height):
+        key = image[i * 3] | (image[i * 3 + 1] << 8) | (image[i * 3 + 2] << 16)
+        if key not in indices:
+            raise ValueError("color not in palette: %06X" % (key))
+        ret[i] = indices[key]
+    return ret
+
 # palette generation
 
 def _writeu16(ff, x):
@@ -2463,26 +6418,28 @@ def _writeutf16(ff, strval):
     ff.write(b)
     _writeu16(ff, 0)
 
+# Named colors of HTML and CSS
+_cssnamedcolors = (
+    "aliceblue,f0f8ff,antiquewhite,faebd7,aqua,00ffff,aquamarine,7fffd4,azure,f0ffff,beige,f5f5dc,bisque,ffe4c4,black,000000,blanchedalmond,ffebcd,blue,0000ff,"
+    + "blueviolet,8a2be2,brown,a52a2a,burlywood,deb887,cadetblue,5f9ea0,chartreuse,7fff00,chocolate,d2691e,coral,ff7f50,cornflowerblue,6495ed,cornsilk,fff8dc,"
+    + "crimson,dc143c,cyan,00ffff,darkblue,00008b,darkcyan,008b8b,darkgoldenrod,b8860b,darkgray,a9a9a9,darkgreen,006400,darkkhaki,bdb76b,darkmagenta,8b008b,"
+    + "darkolivegreen,556b2f,darkorange,ff8c00,darkorchid,9932cc,darkred,8b0000,darksalmon,e9967a,darkseagreen,8fbc8f,darkslateblue,483d8b,darkslategray,2f4f4f,"
+    + "darkturquoise,00ced1,darkviolet,9400d3,deeppink,ff1493,deepskyblue,00bfff,dimgray,696969,dodgerblue,1e90ff,firebrick,b22222,floralwhite,fffaf0,forestgreen,"
+    + "228b22,fuchsia,ff00ff,gainsboro,dcdcdc,ghostwhite,f8f8ff,gold,ffd700,goldenrod,daa520,gray,808080,green,008000,greenyellow,adff2f,honeydew,f0fff0,hotpink,"
+    + "ff69b4,indianred,cd5c5c,indigo,4b0082,ivory,fffff0,khaki,f0e68c,lavender,e6e6fa,lavenderblush,fff0f5,lawngreen,7cfc00,lemonchiffon,fffacd,lightblue,add8e6,"
+    + "lightcoral,f08080,lightcyan,e0ffff,lightgoldenrodyellow,fafad2,lightgray,d3d3d3,lightgreen,90ee90,lightpink,ffb6c1,lightsalmon,ffa07a,lightseagreen,20b2aa,"
+    + "lightskyblue,87cefa,lightslategray,778899,lightsteelblue,b0c4de,lightyellow,ffffe0,lime,00ff00,limegreen,32cd32,linen,faf0e6,magenta,ff00ff,maroon,800000,"
+    + "mediumaquamarine,66cdaa,mediumblue,0000cd,mediumorchid,ba55d3,mediumpurple,9370d8,mediumseagreen,3cb371,mediumslateblue,7b68ee,mediumspringgreen,"
+    + "00fa9a,mediumturquoise,48d1cc,mediumvioletred,c71585,midnightblue,191970,mintcream,f5fffa,mistyrose,ffe4e1,moccasin,ffe4b5,navajowhite,ffdead,navy,"
+    + "000080,oldlace,fdf5e6,olive,808000,olivedrab,6b8e23,orange,ffa500,orangered,ff4500,orchid,da70d6,palegoldenrod,eee8aa,palegreen,98fb98,paleturquoise,"
+    + "afeeee,palevioletred,d87093,papayawhip,ffefd5,peachpuff,ffdab9,peru,cd853f,pink,ffc0cb,plum,dda0dd,powderblue,b0e0e6,purple,800080,rebeccapurple,663399,red,ff0000,rosybrown,"
+    + "bc8f8f,royalblue,4169e1,saddlebrown,8b4513,salmon,fa8072,sandybrown,f4a460,seagreen,2e8b57,seashell,fff5ee,sienna,a0522d,silver,c0c0c0,skyblue,87ceeb,"
+    + "slateblue,6a5acd,slategray,708090,snow,fffafa,springgreen,00ff7f,steelblue,4682b4,tan,d2b48c,teal,008080,thistle,d8bfd8,tomato,ff6347,turquoise,40e0d0,violet,"
+    + "ee82ee,wheat,f5deb3,white,ffffff,whitesmoke,f5f5f5,yellow,ffff00,yellowgreen,9acd32"
+)
+
 def _setup_rgba_to_colorname_hash():
-    ncs = (
-        "aliceblue,f0f8ff,antiquewhite,faebd7,aqua,00ffff,aquamarine,7fffd4,azure,f0ffff,beige,f5f5dc,bisque,ffe4c4,black,000000,blanchedalmond,ffebcd,blue,0000ff,"
-        + "blueviolet,8a2be2,brown,a52a2a,burlywood,deb887,cadetblue,5f9ea0,chartreuse,7fff00,chocolate,d2691e,coral,ff7f50,cornflowerblue,6495ed,cornsilk,fff8dc,"
-        + "crimson,dc143c,cyan,00ffff,darkblue,00008b,darkcyan,008b8b,darkgoldenrod,b8860b,darkgray,a9a9a9,darkgreen,006400,darkkhaki,bdb76b,darkmagenta,8b008b,"
-        + "darkolivegreen,556b2f,darkorange,ff8c00,darkorchid,9932cc,darkred,8b0000,darksalmon,e9967a,darkseagreen,8fbc8f,darkslateblue,483d8b,darkslategray,2f4f4f,"
-        + "darkturquoise,00ced1,darkviolet,9400d3,deeppink,ff1493,deepskyblue,00bfff,dimgray,696969,dodgerblue,1e90ff,firebrick,b22222,floralwhite,fffaf0,forestgreen,"
-        + "228b22,fuchsia,ff00ff,gainsboro,dcdcdc,ghostwhite,f8f8ff,gold,ffd700,goldenrod,daa520,gray,808080,green,008000,greenyellow,adff2f,honeydew,f0fff0,hotpink,"
-        + "ff69b4,indianred,cd5c5c,indigo,4b0082,ivory,fffff0,khaki,f0e68c,lavender,e6e6fa,lavenderblush,fff0f5,lawngreen,7cfc00,lemonchiffon,fffacd,lightblue,add8e6,"
-        + "lightcoral,f08080,lightcyan,e0ffff,lightgoldenrodyellow,fafad2,lightgray,d3d3d3,lightgreen,90ee90,lightpink,ffb6c1,lightsalmon,ffa07a,lightseagreen,20b2aa,"
-        + "lightskyblue,87cefa,lightslategray,778899,lightsteelblue,b0c4de,lightyellow,ffffe0,lime,00ff00,limegreen,32cd32,linen,faf0e6,magenta,ff00ff,maroon,800000,"
-        + "mediumaquamarine,66cdaa,mediumblue,0000cd,mediumorchid,ba55d3,mediumpurple,9370d8,mediumseagreen,3cb371,mediumslateblue,7b68ee,mediumspringgreen,"
-        + "00fa9a,mediumturquoise,48d1cc,mediumvioletred,c71585,midnightblue,191970,mintcream,f5fffa,mistyrose,ffe4e1,moccasin,ffe4b5,navajowhite,ffdead,navy,"
-        + "000080,oldlace,fdf5e6,olive,808000,olivedrab,6b8e23,orange,ffa500,orangered,ff4500,orchid,da70d6,palegoldenrod,eee8aa,palegreen,98fb98,paleturquoise,"
-        + "afeeee,palevioletred,d87093,papayawhip,ffefd5,peachpuff,ffdab9,peru,cd853f,pink,ffc0cb,plum,dda0dd,powderblue,b0e0e6,purple,800080,rebeccapurple,663399,red,ff0000,rosybrown,"
-        + "bc8f8f,royalblue,4169e1,saddlebrown,8b4513,salmon,fa8072,sandybrown,f4a460,seagreen,2e8b57,seashell,fff5ee,sienna,a0522d,silver,c0c0c0,skyblue,87ceeb,"
-        + "slateblue,6a5acd,slategray,708090,snow,fffafa,springgreen,00ff7f,steelblue,4682b4,tan,d2b48c,teal,008080,thistle,d8bfd8,tomato,ff6347,turquoise,40e0d0,violet,"
-        + "ee82ee,wheat,f5deb3,white,ffffff,whitesmoke,f5f5f5,yellow,ffff00,yellowgreen,9acd32"
-    )
-    nc = ncs.split(",")
+    nc = _cssnamedcolors.split(",")
     __color_to_rgba_namedColors = {}
     i = 0
     while i < len(nc):
@@ -2498,6 +6455,87 @@ def _colorname(c):
         return _rgba_to_colorname_hash[cname] + " " + cname
     return cname
 
+def _setup_colorname_to_rgb_hash():
+    nc = _cssnamedcolors.split(",")
+    ret = {}
+    i = 0
+    while i < len(nc):
+        c = int(nc[i + 1], 16)
+        ret[nc[i]] = [(c >> 16) & 0xFF, (c >> 8) & 0xFF, c & 0xFF]
+        i += 2
+    # Alternate spellings
+    for k in [x for x in ret.keys()]:
+        if "gray" in k:
+            ret[k.replace("gray", "grey")] = ret[k]
+    return ret
+
+_colorname_to_rgb_hash = _setup_colorname_to_rgb_hash()
+
+# Named colors of the X Window System that are not HTML and CSS colors,
+# or whose values differ from the HTML and CSS colors of the same name
+_x11namedcolors = {
+    "gray": [190, 190, 190],
+    "grey": [190, 190, 190],
+    "green": [0, 255, 0],
+    "maroon": [176, 48, 96],
+    "purple": [160, 32, 240],
+    "navyblue": [0, 0, 128],
+    "lightgoldenrod": [238, 221, 130],
+    "lightslateblue": [132, 112, 255],
+    "violetred": [208, 32, 144],
+}
+
+def _colorcomponent(s):
+    s = s.strip()
+    if s.endswith("%"):
+        v = float(s[:-1])
+        if v < 0 or v > 100:
+            raise ValueError
+        return int(round(v * 255 / 100))
+    v = int(s)
+    if v < 0 or v > 255:
+        raise ValueError
+    return v
+
+def parsecolor(s, x11=False):
+    # Parses a color and returns a 3-item array of the red, green, and blue
+    # components of the color in that order, each from 0 through 255.
+    # The color can be given in any of the following forms:
+    # - "#RRGGBB" or "#RGB", in hexadecimal, such as "#c0c0c0".
+    # - "rgb(R,G,B)", where each component is from 0 through 255 or is
+    # a percentage, such as "rgb(192,192,192)" or "rgb(75%,75%,75%)".
+    # - A color name of HTML and CSS, such as "navy" or "lightgray", or, if
+    # 'x11' is True, of the X Window System.  Names are case-insensitive
+    # and can contain spaces (such as "Light Gray").  X11 names include
+    # "gray0" through "gray100"; several other names, such as "gray" and
+    # "green", refer to different colors in X11 than in HTML.
+    # Raises ValueError if the color can't be parsed.
+    t = s.strip().lower()
+    if t.startswith("#") and all(ch in "0123456789abcdef" for ch in t[1:]):
+        if len(t) == 4:
+            return [int(t[i] * 2, 16) for i in range(1, 4)]
+        if len(t) == 7:
+            return [int(t[i : i + 2], 16) for i in range(1, 7, 2)]
+        raise ValueError("invalid color: %s" % (s))
+    if t.startswith("rgb(") and t.endswith(")"):
+        parts = t[4:-1].split(",")
+        if len(parts) != 3:
+            raise ValueError("invalid color: %s" % (s))
+        return [_colorcomponent(p) for p in parts]
+    t = t.replace(" ", "")
+    if x11:
+        if t in _x11namedcolors:
+            return [x for x in _x11namedcolors[t]]
+        for prefix in ["gray", "grey"]:
+            if t.startswith(prefix) and t[len(prefix) :].isdigit():
+                v = int(t[len(prefix) :])
+                if v <= 100:
+                    v = int(round(v * 255 / 100))
+                    return [v, v, v]
+    if t in _colorname_to_rgb_hash:
+        return [x for x in _colorname_to_rgb_hash[t]]
+    raise ValueError("unknown color: %s" % (s))
+
 def writepalette(f, palette, name=None, checkIfExists=False):
     if "\n" in name:
         raise ValueError
@@ -2558,6 +6596,1755 @@ def writepalette(f, palette, name=None, checkIfExists=False):
         _writef32(ff, c[2] / 255.0)
         _writeu16(ff, 0)
 
+# color vision deficiency
+
+def _srgbtolinear(c):
+    c /= 255.0
+    return c / 12.92 if c <= 0.04045 else ((c + 0.055) / 1.055) ** 2.4
+
+def _lineartosrgb(c):
+    c = min(1.0, max(0.0, c))
+    c = c * 12.92 if c <= 0.0031308 else 1.055 * (c ** (1 / 2.4)) - 0.055
+    return int(round(c * 255))
+
+# Matrices from Machado, Oliveira, and Fernandes, "A Physiologically-based
+# Model for Simulation of Color Vision Deficiency", 2009 (severity 1.0)
+_cvdmatrices = {
+    "protanopia": [
+        [0.152286, 1.052583, -0.204868],
+        [0.114503, 0.786281, 0.099216],
+        [-0.003882, -0.048116, 1.051998],
+    ],
+    "deuteranopia": [
+        [0.367322, 0.860646, -0.227968],
+        [0.280085, 0.672501, 0.047413],
+        [-0.011820, 0.042940, 0.968881],
+    ],
+    "tritanopia": [
+        [1.255528, -0.076749, -0.178779],
+        [-0.078411, 0.930809, 0.147602],
+        [0.004733, 0.691367, 0.303900],
+    ],
+}
+
+def simulatecvd(image, width, height, kind):
+    # Changes an image to approximate how it looks to people with a color
+    # vision deficiency (color blindness).  'kind' is "protanopia" (no red
+    # cones), "deuteranopia" (no green cones), or "tritanopia" (no blue
+    # cones).  Returns 'image'.
+    if kind not in _cvdmatrices:
+        raise ValueError
+    m = _cvdmatrices[kind]
+    lin = [_srgbtolinear(i) for i in range(256)]
+    cache = {}
+    for pos in range(0, width * height * 3, 3):
+        key = image[pos] | (image[pos + 1] << 8) | (image[pos + 2] << 16)
+        if key not in cache:
+            rgb = [lin[image[pos + i]] for i in range(3)]
+            cache[key] = [
+                _lineartosrgb(m[i][0] * rgb[0] + m[i][1] * rgb[1] + m[i][2] * rgb[2])
+                for i in range(3)
+            ]
+        image[pos : pos + 3] = cache[key]
+    return image
+
+def _relativeluminance(c):
+    return (
+        0.2126 * _srgbtolinear(c[0])
+        + 0.7152 * _srgbtolinear(c[1])
+        + 0.0722 * _srgbtolinear(c[2])
+    )
+
+def contrastratio(color1, color2):
+    # Contrast ratio between two colors as defined in the Web Content
+    # Accessibility Guidelines (WCAG) 2, from 1 (no contrast) through 21
+    # (black and white).  The guidelines call for a ratio of at least 4.5
+    # for normal text.
+    l1 = _relativeluminance(color1)
+    l2 = _relativeluminance(color2)
+    return (max(l1, l2) + 0.05) / (min(l1, l2) + 0.05)
+
+def contrastreport(image, width, height, labelcolor):
+    # Checks how readable text in 'labelcolor' (such as desktop icon labels)
+    # is over an image, both with normal color vision and as simulated by
+    # simulatecvd().  Returns a dictionary whose keys are "normal",
+    # "protanopia", "deuteranopia", and "tritanopia", and whose values are
+    # dictionaries with the following keys: "min" and "mean", the lowest and
+    # average contrast ratio (see contrastratio()) between the label color and
+    # the image's pixels, and "passing", the fraction of pixels with a contrast
+    # ratio of at least 4.5.
+    ret = {}
+    for kind in ["normal", "protanopia", "deuteranopia", "tritanopia"]:
+        img = [x for x in image]
+        label = [x for x in labelcolor[0:3]]
+        if kind != "normal":
+            simulatecvd(img, width, height, kind)
+            simulatecvd(label, 1, 1, kind)
+        ratios = {}
+        low = None
+        total = 0
+        passing = 0
+        for pos in range(0, width * height * 3, 3):
+            key = img[pos] | (img[pos + 1] << 8) | (img[pos + 2] << 16)
+            if key not in ratios:
+                ratios[key] = contrastratio(img[pos : pos + 3], label)
+            r = ratios[key]
+            low = r if low is None else min(low, r)
+            total += r
+            if r >= 4.5:
+                passing += 1
+        count = max(1, width * height)
+        ret[kind] = {"min": low, "mean": total / count, "passing": passing / count}
+    return ret
+
+# seed embedding
+#
+# The following methods hide a short piece of data, such as the seed or
+# pattern description a wallpaper was generated from, in the least significant
+# bit of each color component of the pixels at the image's top left, row by
+# row, so that the wallpaper can later be traced back to how it was made.
+# This changes colors imperceptibly, but the data is lost if the image is
+# later resized, dithered, or saved in a lossy format.  The data starts with
+# the bytes "WP", then a 2-byte length (most significant byte first), then
+# the data itself, then a CRC-32 of the data.
+
+def _seedbits(data):
+    for b in data:
+        for i in range(8):
+            yield (b >> (7 - i)) & 1
+
+def embedseed(image, width, height, data):
+    # Hides 'data' (a byte string or a text string) in an image as described
+    # above.  Raises an error if the image is too small to hold the data.
+    # Returns 'image'.
+    if isinstance(data, str):
+        data = data.encode("utf-8")
+    if len(data) > 0xFFFF:
+        raise ValueError
+    payload = b"WP" + struct.pack(">H", len(data)) + data
+    payload += struct.pack(">L", zlib.crc32(data))
+    if len(payload) * 8 > width * height * 3:
+        raise ValueError("image too small for data")
+    pos = 0
+    for bit in _seedbits(payload):
+        image[pos] = (image[pos] & 0xFE) | bit
+        pos += 1
+    return image
+
+def recoverseed(image, width, height):
+    # Gets the data hidden in an image by embedseed(), as a byte string, or
+    # None if the image has no such data or the data was damaged.
+    def readbytes(start, count):
+        if (start + count) * 8 > width * height * 3:
+            return None
+        ret = []
+        for i in range(count):
+            b = 0
+            for j in range(8):
+                b = (b << 1) | (image[(start + i) * 8 + j] & 1)
+            ret.append(b)
+        return bytes(ret)
+
+    header = readbytes(0, 4)
+    if (not header) or header[0:2] != b"WP":
+        return None
+    length = struct.unpack(">H", header[2:4])[0]
+    rest = readbytes(4, length + 4)
+    if not rest:
+        return None
+    data = rest[0:length]
+    if struct.unpack(">L", rest[length:])[0] != zlib.crc32(data):
+        return None
+    return data
+
+# exporting
+
+def _coverresize(image, width, height, dstwidth, dstheight):
+    # Resizes an image to cover the given size, keeping its proportions, then
+    # crops the middle of the result to that size
+    if width * dstheight >= height * dstwidth:
+        sh = dstheight
+        sw = max(dstwidth, (width * dstheight + height // 2) // height)
+    else:
+        sw = dstwidth
+        sh = max(dstheight, (height * dstwidth + width // 2) // width)
+    scaled = areaaverage(image, width, height, sw, sh)
+    return wraparoundcrop(
+        scaled, sw, sh, (sw - dstwidth) // 2, (sh - dstheight) // 2, dstwidth, dstheight
+    )
+
+def exportset(
+    wallpaper,
+    sizes,
+    directory,
+    format="png",
+    name="wallpaper",
+    width=0,
+    height=0,
+    focus=False,
+    timings=None,
+):
+    # Writes versions of a wallpaper for several screen sizes, such as to
+    # copy the same wallpaper to computers with different screens.
+    # 'wallpaper' is either a pattern description (see renderpattern()),
+    # which is drawn anew at each size, or an image of size 'width' x 'height',
+    # which is resized to cover each size, keeping its proportions, with any
+    # excess cropped equally from both sides, or, if 'focus' is True, cropped
+    # so as to keep the image's most detailed region (see smartcrop()).
+    # 'sizes' is a list of 2-item arrays of the width and height of each
+    # screen.
+    # 'format' is "png" or "ppm".  Files are named with 'name' followed by
+    # the size, as in "wallpaper-1024x768.png", in 'directory'.
+    # 'timings' is as in randomwallpaper(); the stages are "render" (or
+    # "resize") and "encode".
+    # Returns a list of the file names written.
+    if format != "png" and format != "ppm":
+        raise ValueError
+    isdesc = isinstance(wallpaper, dict) or isinstance(wallpaper, str)
+    if (not isdesc) and (width <= 0 or height <= 0):
+        raise ValueError
+    ret = []
+    for size in sizes:
+        w, h = size[0], size[1]
+        start = time.perf_counter()
+        if isdesc:
+            image = renderpattern(wallpaper, w, h)
+            start = _addtiming(timings, "render", start)
+        elif focus:
+            crop = smartcrop(wallpaper, width, height, [w, h])
+            image = _coverresize(crop["image"], crop["width"], crop["height"], w, h)
+            start = _addtiming(timings, "resize", start)
+        else:
+            image = _coverresize(wallpaper, width, height, w, h)
+            start = _addtiming(timings, "resize", start)
+        f = os.path.join(directory, "%s-%dx%d.%s" % (name, w, h, format))
+        if format == "png":
+            writepng(f, image, w, h)
+        else:
+            writeppm(f, image, w, h)
+        _addtiming(timings, "encode", start)
+        ret.append(f)
+    return ret
+
+def lockscreenvariants(image, width, height, dim=0.5, blur=4, brighten=0.15):
+    # Makes two versions of a wallpaper from one image: a "desktop" version
+    # brightened by the fraction 'brighten' (toward white), and a "lock"
+    # version, for a lock screen or login screen, blurred over 'blur' pixels
+    # (see boxblur(); 0 means no blur) and darkened by the fraction 'dim'
+    # (toward black), so that text drawn over it stands out.
+    # Returns a dictionary with "desktop" and "lock" keys.
+    if dim < 0 or dim > 1 or brighten < 0 or brighten > 1 or blur < 0:
+        raise ValueError
+    up = int(round(brighten * 256))
+    desktop = [v + (((255 - v) * up) >> 8) for v in image]
+    lock = boxblur(image, width, height, blur) if blur > 0 else [v for v in image]
+    keep = int(round((1 - dim) * 256))
+    lock = [(v * keep) >> 8 for v in lock]
+    return {"desktop": desktop, "lock": lock}
+
+def writevariants(
+    wallpaper,
+    width,
+    height,
+    directory,
+    name="wallpaper",
+    format="png",
+    dim=0.5,
+    blur=4,
+    brighten=0.15,
+):
+    # Writes the desktop and lock screen versions of a wallpaper (see
+    # lockscreenvariants()) to two files in 'directory', named with 'name'
+    # followed by "-desktop" or "-lock".
+    # 'wallpaper' is either a pattern description (see renderpattern()),
+    # drawn at size 'width' x 'height', or an image of that size.
+    # 'format' is "png" or "ppm".
+    # Returns a list of the file names written.
+    if format != "png" and format != "ppm":
+        raise ValueError
+    if isinstance(wallpaper, dict) or isinstance(wallpaper, str):
+        image = renderpattern(wallpaper, width, height)
+    else:
+        image = wallpaper
+    variants = lockscreenvariants(image, width, height, dim, blur, brighten)
+    ret = []
+    for kind in ["desktop", "lock"]:
+        f = os.path.join(directory, "%s-%s.%s" % (name, kind, format))
+        if format == "png":
+            writepng(f, variants[kind], width, height)
+        else:
+            writeppm(f, variants[kind], width, height)
+        ret.append(f)
+    return ret
+
+def adjustcolors(
+    image, width, height, brightness=0, hue=0, saturation=1, tint=None, tintamount=0
+):
+    # Returns a recolored copy of an image.
+    # 'brightness' is from -1 (black) through 1 (white); 0 leaves brightness
+    # unchanged.  'hue' is the angle, in degrees, to rotate each color's hue
+    # by.  'saturation' multiplies each color's saturation (0 gives grays).
+    # 'tint' is a color to mix into each pixel by the fraction 'tintamount'
+    # (from 0 through 1).
+    import colorsys
+
+    if brightness < -1 or brightness > 1 or saturation < 0:
+        raise ValueError
+    if tintamount < 0 or tintamount > 1:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    cache = {}
+    ret = [0 for i in range(width * height * 3)]
+    for i in range(0, width * height * 3, 3):
+        key = image[i] | (image[i + 1] << 8) | (image[i + 2] << 16)
+        if key not in cache:
+            c = [v / 255.0 for v in image[i : i + 3]]
+            if hue != 0 or saturation != 1:
+                h, s, v = colorsys.rgb_to_hsv(c[0], c[1], c[2])
+                h = (h + hue / 360.0) % 1.0
+                c = colorsys.hsv_to_rgb(h, min(1.0, s * saturation), v)
+            if brightness < 0:
+                c = [v * (1 + brightness) for v in c]
+            elif brightness > 0:
+                c = [v + (1 - v) * brightness for v in c]
+            if tint and tintamount > 0:
+                c = [c[j] + (tint[j] / 255.0 - c[j]) * tintamount for j in range(3)]
+            cache[key] = [min(255, max(0, int(round(v * 255)))) for v in c]
+        ret[i : i + 3] = cache[key]
+    return ret
+
+# Time-of-day schedules
+#
+# A schedule is a dictionary (such as one read from a JSON file) that says
+# how a wallpaper changes over the day.  It has the following keys:
+# "latitude" and "longitude": Location, in degrees (north and east are
+# positive), used to find the times of sunrise and sunset (see suntimes());
+# needed only if a keyframe's time is given relative to sunrise or sunset.
+# "utcoffset": Offset of local time from UTC, in hours; default is the
+# offset of the local time zone.
+# "keyframes": List of dictionaries, each of which gives the look of the
+# wallpaper at a time of day, with the following keys:
+# - "time": Time of day as a string in the form "HH:MM" (24-hour clock), or
+# "sunrise" or "sunset", optionally followed by an offset in minutes (such
+# as "sunset-30" or "sunrise+90").
+# - "brightness", "hue", "saturation", "tint", "tintamount": Color adjustments
+# as in adjustcolors(); defaults are 0, 0, 1, white, and 0.
+# - "recipe": Optional pattern description (see renderpattern()) drawn
+# instead of the wallpaper from this keyframe's time until the next
+# keyframe with a "recipe" key.
+# Between two keyframes, the color adjustments change smoothly from those of
+# the earlier keyframe to those of the later one, wrapping around midnight.
+
+def defaultschedule():
+    # Schedule (see above) that warms the wallpaper in the morning and
+    # evening, and darkens and cools it at night
+    night = {"brightness": -0.5, "saturation": 0.6, "tint": [64, 96, 255]}
+    night["tintamount"] = 0.15
+    return {
+        "keyframes": [
+            dict(night, time="05:00"),
+            {"time": "07:00", "tint": [255, 160, 64], "tintamount": 0.15},
+            {"time": "10:00"},
+            {"time": "17:00"},
+            {"time": "19:30", "tint": [255, 128, 48], "tintamount": 0.2},
+            dict(night, time="22:00"),
+        ]
+    }
+
+def suntimes(latitude, longitude, date, utcoffset=0):
+    # Calculates the times of sunrise and sunset on a day at a place on Earth,
+    # using the sunrise equation with the approximations given by the US
+    # National Oceanic and Atmospheric Administration (accurate to within a
+    # few minutes except near the poles).
+    # 'latitude' and 'longitude' are in degrees; north and east are positive.
+    # 'date' is a datetime.date.  'utcoffset' is the local time's offset from
+    # UTC, in hours.
+    # Returns a list with the local times of sunrise and sunset, each in
+    # minutes since midnight.  During a polar night, both times are at
+    # solar noon; during a polar day, they are 24 hours apart.
+    if latitude < -90 or latitude > 90:
+        raise ValueError
+    day = date.timetuple().tm_yday
+    g = 2 * math.pi / 365 * (day - 1)
+    eqtime = 229.18 * (
+        0.000075
+        + 0.001868 * math.cos(g)
+        - 0.032077 * math.sin(g)
+        - 0.014615 * math.cos(2 * g)
+        - 0.040849 * math.sin(2 * g)
+    )
+    decl = (
+        0.006918
+        - 0.399912 * math.cos(g)
+        + 0.070257 * math.sin(g)
+        - 0.006758 * math.cos(2 * g)
+        + 0.000907 * math.sin(2 * g)
+        - 0.002697 * math.cos(3 * g)
+        + 0.00148 * math.sin(3 * g)
+    )
+    lat = math.radians(latitude)
+    # Hour angle at which the sun's center is 0.833 degree below the horizon
+    cosha = math.cos(math.radians(90.833)) / (math.cos(lat) * math.cos(decl))
+    cosha -= math.tan(lat) * math.tan(decl)
+    ha = math.degrees(math.acos(min(1, max(-1, cosha))))
+    noon = 720 - 4 * longitude - eqtime + utcoffset * 60
+    return [noon - 4 * ha, noon + 4 * ha]
+
+def solarschedule(
+    latitude,
+    longitude,
+    warm=None,
+    cool=None,
+    warmamount=0.2,
+    coolamount=0.15,
+    night=-0.5,
+):
+    # Schedule (see above) that follows the sun at the given location:
+    # the wallpaper is tinted with the 'warm' color (default is orange)
+    # around sunrise and sunset, fades to its own colors for the middle of
+    # the day, and is darkened by the fraction 'night' (see the
+    # 'brightness' parameter of adjustcolors()) and tinted with the 'cool'
+    # color (default is blue) at night.  'warmamount' and 'coolamount' are
+    # how strongly to tint with those colors (from 0 through 1).
+    warm = warm if warm else [255, 144, 48]
+    cool = cool if cool else [64, 96, 255]
+    dark = {"brightness": night, "tint": cool, "tintamount": coolamount}
+    glow = {"tint": warm, "tintamount": warmamount}
+    return {
+        "latitude": latitude,
+        "longitude": longitude,
+        "keyframes": [
+            dict(dark, time="sunrise-60"),
+            dict(glow, time="sunrise", brightness=night / 4),
+            {"time": "sunrise+120"},
+            {"time": "sunset-120"},
+            dict(glow, time="sunset", brightness=night / 4),
+            dict(dark, time="sunset+60"),
+        ],
+    }
+
+def _scheduletime(keyframe, schedule, date, utcoffset):
+    # Gets a keyframe's time of day in minutes since midnight
+    text = keyframe["time"]
+    for event in ["sunrise", "sunset"]:
+        if text.startswith(event):
+            if "latitude" not in schedule or "longitude" not in schedule:
+                raise ValueError
+            sun = suntimes(schedule["latitude"], schedule["longitude"], date, utcoffset)
+            offset = int(text[len(event) :]) if len(text) > len(event) else 0
+            return (sun[0 if event == "sunrise" else 1] + offset) % 1440
+    parts = text.split(":")
+    if len(parts) != 2:
+        raise ValueError
+    hour = int(parts[0])
+    minute = int(parts[1])
+    if hour < 0 or hour > 23 or minute < 0 or minute > 59:
+        raise ValueError
+    return hour * 60 + minute
+
+def scheduleparams(schedule, when=None):
+    # Gets the color adjustments and recipe in effect at a time of day
+    # under a schedule (see above); 'schedule' can also be a string in JSON
+    # format.  'when' is a datetime.datetime or datetime.time; default is now.
+    # If 'when' is a datetime.time, the day is taken to be today.
+    # Returns a dictionary with the keys "brightness", "hue", "saturation",
+    # "tint", and "tintamount" (see adjustcolors()), and "recipe" (the
+    # pattern description in effect, or None).
+    import datetime
+
+    if isinstance(schedule, str):
+        schedule = json.loads(schedule)
+    when = when if when else datetime.datetime.now()
+    date = when.date() if hasattr(when, "date") else datetime.date.today()
+    utcoffset = schedule.get("utcoffset", None)
+    if utcoffset is None:
+        # Offset of the local time zone (or of 'when', if it has a time zone)
+        if isinstance(when, datetime.datetime) and when.tzinfo:
+            utcoffset = when.utcoffset().total_seconds() / 3600
+        else:
+            local = datetime.datetime.combine(date, datetime.time(12)).astimezone()
+            utcoffset = local.utcoffset().total_seconds() / 3600
+    frames = [
+        [_scheduletime(frame, schedule, date, utcoffset), frame]
+        for frame in schedule["keyframes"]
+    ]
+    frames.sort(key=lambda f: f[0])
+    if len(frames) == 0:
+        raise ValueError
+    now = when.hour * 60 + when.minute + when.second / 60.0
+    # Latest keyframe at or before now, wrapping around to the day before
+    prev = len(frames) - 1
+    for i in range(len(frames)):
+        if frames[i][0] <= now:
+            prev = i
+    nxt = (prev + 1) % len(frames)
+    t0 = frames[prev][0]
+    span = (frames[nxt][0] - t0) % 1440
+    t = ((now - t0) % 1440) / span if span > 0 else 0
+    frames = [frame for time, frame in frames]
+    ret = {}
+    defaults = {"brightness": 0, "hue": 0, "saturation": 1, "tintamount": 0}
+    for key in defaults:
+        v0 = frames[prev].get(key, defaults[key])
+        ret[key] = v0 + (frames[nxt].get(key, defaults[key]) - v0) * t
+    white = [255, 255, 255]
+    tint0 = frames[prev].get("tint", white)
+    tint1 = frames[nxt].get("tint", white)
+    ret["tint"] = [int(round(a + (b - a) * t)) for a, b in zip(tint0, tint1)]
+    ret["recipe"] = None
+    # Keyframes with recipes, in order from now going back a day
+    for i in range(len(frames)):
+        frame = frames[(prev - i) % len(frames)]
+        if "recipe" in frame:
+            ret["recipe"] = frame["recipe"]
+            break
+    return ret
+
+def scheduledwallpaper(wallpaper, width, height, schedule=None, when=None):
+    # Draws a wallpaper as it looks at a time of day under a schedule (see
+    # above; default is defaultschedule()).  A program that sets the desktop
+    # wallpaper can call this method every few minutes to change the
+    # wallpaper gradually over the day.
+    # 'wallpaper' is either a pattern description (see renderpattern()),
+    # drawn at size 'width' x 'height', or an image of that size; it is
+    # ignored if a recipe in the schedule is in effect.
+    # 'when' is a datetime.datetime or datetime.time; default is now.
+    # Returns the image.
+    params = scheduleparams(schedule if schedule else defaultschedule(), when)
+    if params["recipe"] is not None:
+        wallpaper = params["recipe"]
+    if isinstance(wallpaper, dict) or isinstance(wallpaper, str):
+        image = renderpattern(wallpaper, width, height)
+    else:
+        image = wallpaper
+    return adjustcolors(
+        image,
+        width,
+        height,
+        params["brightness"],
+        params["hue"],
+        params["saturation"],
+        params["tint"],
+        params["tintamount"],
+    )
+
+# Weather variants
+#
+# The following methods let a program that sets the desktop wallpaper change
+# it with the weather, without any network access of their own: the weather
+# comes from a source the user provides (see weathercondition()).
+
+# Words accepted for each weather condition
+_weatherwords = {
+    "sunny": ["sunny", "clear", "fair", "sun"],
+    "cloudy": ["cloudy", "clouds", "overcast", "fog", "mist", "haze"],
+    "rain": ["rain", "rainy", "drizzle", "showers", "thunderstorm", "storm"],
+    "snow": ["snow", "snowy", "sleet", "hail", "flurries"],
+}
+
+def weathercondition(source):
+    # Gets the current weather condition from a source the user provides.
+    # 'source' is one of the following:
+    # - A function taking no arguments, such as one that asks a weather
+    # service, that returns a dictionary or a string as described below.
+    # - A list of strings, a command and its arguments, that is run and whose
+    # output is a JSON object or a word as described below.
+    # - The name of a file holding a JSON object or a word.
+    # A JSON object or dictionary gives the condition in its "condition" key.
+    # The condition is a word such as "clear", "overcast", "drizzle", or
+    # "sleet" (case doesn't matter).
+    # Returns "sunny", "cloudy", "rain", or "snow".  Raises an error if the
+    # condition isn't recognized.
+    if callable(source):
+        data = source()
+    elif isinstance(source, list):
+        import subprocess
+
+        data = subprocess.run(
+            source, capture_output=True, text=True, check=True, timeout=60
+        ).stdout
+    else:
+        ff = open(source, "r")
+        data = ff.read()
+        ff.close()
+    if isinstance(data, str):
+        data = data.strip()
+        data = json.loads(data) if data.startswith("{") else {"condition": data}
+    if not isinstance(data, dict) or "condition" not in data:
+        raise ValueError("no weather condition given")
+    word = str(data["condition"]).strip().lower()
+    for condition in _weatherwords:
+        if word in _weatherwords[condition]:
+            return condition
+    raise ValueError("unknown weather condition: %s" % (word))
+
+def weatherwallpaper(variants, width, height, condition):
+    # Chooses the wallpaper for a weather condition (see weathercondition())
+    # among several variants.
+    # 'variants' is a dictionary whose keys are "sunny", "cloudy", "rain",
+    # "snow", or "default" (used for conditions without their own key), and
+    # whose values are pattern descriptions (see renderpattern()), drawn at
+    # size 'width' x 'height', or images of that size.
+    # Returns the image.
+    wallpaper = variants.get(condition, variants.get("default", None))
+    if wallpaper is None:
+        raise ValueError
+    if isinstance(wallpaper, dict) or isinstance(wallpaper, str):
+        return renderpattern(wallpaper, width, height)
+    return [x for x in wallpaper]
+
+def weathereffect(image, width, height, condition, frames=16, amount=0.35, seed=None):
+    # Generates a looping animation of a wallpaper image with subtle falling
+    # rain streaks (if 'condition' is "rain") or snowflakes (if "snow")
+    # blended over it, wrapping around the image's edges so that the
+    # animation still tiles.  For other conditions, the animation has only
+    # the image itself.
+    # 'amount' is how strongly the rain or snow is blended in (from 0
+    # through 1).  'seed' seeds the random number generator used to place
+    # the raindrops or snowflakes.
+    # Returns an animation (see composeanimation()).
+    if width <= 0 or height <= 0 or frames <= 0:
+        raise ValueError
+    if amount < 0 or amount > 1:
+        raise ValueError
+    if condition != "rain" and condition != "snow":
+        return composeanimation([[x for x in image]], width, height, 1000)
+    rnd = random.Random(seed)
+    rain = condition == "rain"
+    count = max(1, width * height // (400 if rain else 250))
+    # Each drop falls a whole number of image heights over the animation,
+    # so that the animation loops
+    drops = [
+        [rnd.randrange(width), rnd.randrange(height), rnd.randint(1, 2)]
+        for i in range(count)
+    ]
+    color = [200, 210, 230] if rain else [255, 255, 255]
+    ret = []
+    for t in range(frames):
+        frame = [x for x in image]
+        for x0, y0, speed in drops:
+            y = y0 + speed * height * t // frames
+            if rain:
+                # A short streak slanting down and to the left
+                points = [[x0 - i // 3, y + i] for i in range(6)]
+            else:
+                # A flake drifting from side to side as it falls
+                sway = int(round(2 * math.sin(2 * math.pi * (t / frames + x0 / 7))))
+                points = [[x0 + sway, y]]
+                if speed == 2:
+                    points.append([x0 + sway + 1, y])
+            for x, y in points:
+                p = ((y % height) * width + x % width) * 3
+                c = _mixcolor(frame[p : p + 3], color, amount)
+                frame[p : p + 3] = c
+        ret.append(frame)
+    return composeanimation(ret, width, height, 60 if rain else 120)
+
+# Output profiles for classic boot and startup screens.  Each gives the size
+# of the stored image, the size it's shown at on screen (the stored image is
+# stretched to that size), the palette, and the file format.
+_bootlogoprofiles = {
+    # Windows 95/98 startup logo (LOGO.SYS), shown stretched to 640x480
+    "win9x": [320, 400, 640, 480, "websafe", "bmprle8"],
+    # OS/2 boot logo
+    "os2": [640, 480, 640, 480, "vga", "os2bmp"],
+    # 16-color 640x480 Windows bitmap, as used for Windows NT and 2000
+    # boot screens
+    "vga16": [640, 480, 640, 480, "vga", "bmp"],
+}
+
+def writebootlogo(f, image, width, height, profile, raiseIfExists=False):
+    # Writes an image to a file meeting the constraints of a classic boot
+    # or startup screen.  The image is resized to cover the screen, keeping
+    # its proportions (see exportset()), dithered to the profile's palette
+    # (see floydSteinbergDither()), and written in the profile's format.
+    # 'profile' is one of the following: "win9x" (320x400, 256 colors,
+    # RLE8-compressed BMP, as for Windows 95 and 98's LOGO.SYS), "os2"
+    # (640x480, 16 colors, OS/2 1.x BMP), or "vga16" (640x480, 16 colors,
+    # Windows BMP).
+    # Uses the writers in imageformat.py.
+    import imageformat
+
+    if profile not in _bootlogoprofiles:
+        raise ValueError
+    w, h, screenw, screenh, pal, fmt = _bootlogoprofiles[profile]
+    img = _coverresize(image, width, height, screenw, screenh)
+    if [w, h] != [screenw, screenh]:
+        img = areaaverage(img, screenw, screenh, w, h)
+    palette = websafecolors() if pal == "websafe" else classiccolors()
+    floydSteinbergDither(img, w, h, palette)
+    if fmt == "bmprle8":
+        imageformat.writebmprle8(f, img, w, h, palette, raiseIfExists)
+    elif fmt == "os2bmp":
+        imageformat.writeos2bmp(f, img, w, h, raiseIfExists)
+    else:
+        imageformat.writebmp(f, img, w, h, raiseIfExists, True)
+
+# Windows theme files
+#
+# A theme file (.theme) is an INI-style text file introduced with the Desktop
+# Themes feature of Microsoft Plus! for Windows 95.  The sections of interest
+# here are "[Control Panel\Desktop]", which gives the wallpaper and the 8x8
+# desktop pattern, and "[Control Panel\Colors]", which gives the color scheme.
+# (A .themepack file is a cabinet archive containing a .theme file; the
+# .theme file must be extracted from it before it can be read here.)
+
+def _readini(f):
+    # Reads an INI-style file into a dictionary of sections, each of which
+    # is a dictionary of keys and values.  Section names and keys are
+    # converted to lower case, since Windows treats them case-insensitively.
+    ff = open(f, "rb")
+    data = ff.read()
+    ff.close()
+    if data[:3] == b"\xef\xbb\xbf":
+        text = str(data[3:], "utf-8")
+    elif data[:2] == b"\xff\xfe":
+        text = str(data[2:], "utf-16le")
+    else:
+        text = str(data, "cp1252", errors="replace")
+    sections = {}
+    section = None
+    for line in text.splitlines():
+        line = line.strip()
+        if len(line) == 0 or line[0] == ";":
+            continue
+        if line[0] == "[" and line[-1] == "]":
+            name = line[1:-1].strip().lower()
+            if name not in sections:
+                sections[name] = {}
+            section = sections[name]
+        elif section is not None and "=" in line:
+            k, v = line.split("=", 1)
+            section[k.strip().lower()] = v.strip()
+    return sections
+
+def _themecolor(value):
+    # Parses a color in the form "R G B", as used in theme files
+    parts = value.split()
+    if len(parts) != 3:
+        raise ValueError
+    ret = [int(p) for p in parts]
+    for c in ret:
+        if c < 0 or c > 255:
+            raise ValueError
+    return ret
+
+def _themepattern(value):
+    # Parses a desktop pattern in the form of eight integers from 0 through
+    # 255, or "(None)"; the result is in the format used by hatchedbox()
+    # with 'msbfirst' set to True
+    if value.lower() == "(none)" or len(value) == 0:
+        return None
+    parts = value.split()
+    if len(parts) != 8:
+        raise ValueError
+    ret = [int(p) for p in parts]
+    for c in ret:
+        if c < 0 or c > 255:
+            raise ValueError
+    return ret
+
+def readtheme(f):
+    # Reads a Windows theme (.theme) file and returns a dictionary with
+    # the following keys:
+    # "wallpaper": path to the wallpaper image, as written in the file, or
+    # None if no wallpaper is set.  The path may contain environment
+    # variable references such as "%WinDir%".
+    # "tile": True if the wallpaper is tiled.
+    # "stretch": True if the wallpaper is stretched to the screen size.
+    # "pattern": an 8-item array giving the desktop pattern (see hatchedbox()),
+    # or None if no pattern is set.
+    # "colors": dictionary of the color scheme, whose keys are the names
+    # in the "[Control Panel\Colors]" section (such as "ActiveTitle"
+    # or "Background") and whose values are 3-item arrays of the red, green,
+    # and blue components of each color.
+    # "sections": dictionary of all sections in the file (with lower-case
+    # section names and keys), in case other settings are needed.
+    sections = _readini(f)
+    desktop = sections.get("control panel\\desktop", {})
+    wallpaper = desktop.get("wallpaper", "")
+    if len(wallpaper) == 0 or wallpaper.lower() == "(none)":
+        wallpaper = None
+    colors = {}
+    for k, v in sections.get("control panel\\colors", {}).items():
+        colors[_themecolorname(k)] = _themecolor(v)
+    return {
+        "wallpaper": wallpaper,
+        "tile": desktop.get("tilewallpaper", "0") == "1",
+        "stretch": desktop.get("wallpaperstyle", "0") == "2",
+        "pattern": _themepattern(desktop.get("pattern", "")),
+        "colors": colors,
+        "sections": sections,
+    }
+
+# Names of the colors in the "[Control Panel\Colors]" section of theme
+# files, in the order of their COLOR_* indices in Windows
+_themecolornames = [
+    "Scrollbar",
+    "Background",
+    "ActiveTitle",
+    "InactiveTitle",
+    "Menu",
+    "Window",
+    "WindowFrame",
+    "MenuText",
+    "WindowText",
+    "TitleText",
+    "ActiveBorder",
+    "InactiveBorder",
+    "AppWorkspace",
+    "Hilight",
+    "HilightText",
+    "ButtonFace",
+    "ButtonShadow",
+    "GrayText",
+    "ButtonText",
+    "InactiveTitleText",
+    "ButtonHilight",
+    "ButtonDkShadow",
+    "ButtonLight",
+    "InfoText",
+    "InfoWindow",
+    "ButtonAlternateFace",
+    "HotTrackingColor",
+    "GradientActiveTitle",
+    "GradientInactiveTitle",
+    "MenuHilight",
+    "MenuBar",
+]
+
+def _themecolorname(name):
+    # Restores the usual capitalization of a color name read from a theme file
+    for n in _themecolornames:
+        if n.lower() == name.lower():
+            return n
+    return name
+
+def writetheme(
+    f,
+    wallpaper=None,
+    tile=True,
+    stretch=False,
+    pattern=None,
+    colors=None,
+    raiseIfExists=False,
+):
+    # Writes a Windows theme (.theme) file.
+    # 'wallpaper' is the path to the wallpaper image (usually a BMP file), or
+    # None for no wallpaper.
+    # 'tile' and 'stretch' give how the wallpaper is displayed; if both are
+    # False, the wallpaper is centered.
+    # 'pattern' is an 8-item array giving the desktop pattern (see
+    # hatchedbox()), or None for no pattern.
+    # 'colors' is a dictionary giving the color scheme (see readtheme()),
+    # or None to leave the color scheme unchanged.
+    if pattern and len(pattern) != 8:
+        raise ValueError
+    if wallpaper and ("\n" in wallpaper or "\r" in wallpaper):
+        raise ValueError
+    lines = ["; Windows theme file", "", "[Control Panel\\Desktop]"]
+    lines.append("Wallpaper=%s" % (wallpaper if wallpaper else "(None)"))
+    lines.append("TileWallpaper=%d" % (1 if tile else 0))
+    lines.append("WallpaperStyle=%d" % (2 if stretch else 0))
+    if pattern:
+        lines.append("Pattern=%s" % (" ".join(str(p & 0xFF) for p in pattern)))
+    else:
+        lines.append("Pattern=(None)")
+    if colors:
+        lines += ["", "[Control Panel\\Colors]"]
+        for k in _themecolornames:
+            if k in colors:
+                c = colors[k]
+                lines.append("%s=%d %d %d" % (k, c[0], c[1], c[2]))
+    # Expected by theme software to identify the file as a theme file
+    lines += ["", "[MasterThemeSelector]", "MTSM=DABJDKT", ""]
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(bytes("\r\n".join(lines), "cp1252"))
+    ff.close()
+
+# Color schemes
+#
+# A color scheme is a dictionary whose keys are the color names given in
+# _themecolornames (such as "ButtonFace") and whose values are 3-item arrays
+# of the red, green, and blue components of each color, as returned by
+# readtheme().
+
+def classiccolorscheme():
+    # The "Windows Standard" color scheme of Windows 95 and later
+    return {
+        "Scrollbar": [192, 192, 192],
+        "Background": [0, 128, 128],
+        "ActiveTitle": [0, 0, 128],
+        "InactiveTitle": [128, 128, 128],
+        "Menu": [192, 192, 192],
+        "Window": [255, 255, 255],
+        "WindowFrame": [0, 0, 0],
+        "MenuText": [0, 0, 0],
+        "WindowText": [0, 0, 0],
+        "TitleText": [255, 255, 255],
+        "ActiveBorder": [192, 192, 192],
+        "InactiveBorder": [192, 192, 192],
+        "AppWorkspace": [128, 128, 128],
+        "Hilight": [0, 0, 128],
+        "HilightText": [255, 255, 255],
+        "ButtonFace": [192, 192, 192],
+        "ButtonShadow": [128, 128, 128],
+        "GrayText": [128, 128, 128],
+        "ButtonText": [0, 0, 0],
+        "InactiveTitleText": [192, 192, 192],
+        "ButtonHilight": [255, 255, 255],
+        "ButtonDkShadow": [0, 0, 0],
+        "ButtonLight": [192, 192, 192],
+        "InfoText": [0, 0, 0],
+        "InfoWindow": [255, 255, 225],
+        "ButtonAlternateFace": [180, 180, 180],
+        "HotTrackingColor": [0, 0, 128],
+        "GradientActiveTitle": [16, 132, 208],
+        "GradientInactiveTitle": [181, 181, 181],
+        "MenuHilight": [0, 0, 128],
+        "MenuBar": [192, 192, 192],
+    }
+
+def _lum(c):
+    return (c[0] * 2126 + c[1] * 7152 + c[2] * 722) // 10000
+
+def _chroma(c):
+    return max(c[0], c[1], c[2]) - min(c[0], c[1], c[2])
+
+def _mixcolor(c1, c2, t):
+    # Mixes two colors; 't' is the weight (from 0 through 1) of the second color
+    return [int(a + (b - a) * t + 0.5) for a, b in zip(c1, c2)]
+
+def _textcolor(bg, dark=[0, 0, 0], light=[255, 255, 255]):
+    # Chooses the dark or light color, whichever contrasts more with 'bg'
+    return dark if _lum(bg) >= 128 else light
+
+def _colorhistogram(image, width, height):
+    # Returns a list of [count, color] pairs for the colors in the image,
+    # from most to least frequent
+    counts = {}
+    for i in range(0, width * height * 3, 3):
+        c = image[i] | (image[i + 1] << 8) | (image[i + 2] << 16)
+        counts[c] = counts.get(c, 0) + 1
+    ret = [[v, [k & 0xFF, (k >> 8) & 0xFF, (k >> 16) & 0xFF]] for k, v in counts.items()]
+    ret.sort(key=lambda x: -x[0])
+    return ret
+
+def averagecolor(image, width, height):
+    # Gets the average color of an image's pixels.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    count = width * height
+    return [
+        (sum(image[i : count * 3 : 3]) + count // 2) // count for i in range(3)
+    ]
+
+def dominantcolors(image, width, height, k=5):
+    # Finds up to 'k' colors that best represent an image, by grouping
+    # similar colors together (k-means clustering) and taking each group's
+    # average color.  The same image always gives the same result.
+    # Returns a list of [color, fraction] pairs, where 'fraction' is the
+    # fraction of the image's pixels in that color's group, from most to least
+    # common.
+    if width <= 0 or height <= 0 or k <= 0:
+        raise ValueError
+    hist = _colorhistogram(image, width, height)
+    # Start with the most frequent color, then repeatedly add the color
+    # farthest from the colors chosen so far
+    centers = [hist[0][1]]
+    while len(centers) < min(k, len(hist)):
+        best = None
+        for count, c in hist:
+            d = min(sum((c[i] - m[i]) ** 2 for i in range(3)) for m in centers)
+            if best is None or d > best[0]:
+                best = [d, c]
+        if best[0] == 0:
+            break
+        centers.append(best[1])
+    for iteration in range(20):
+        sums = [[0, 0, 0, 0] for m in centers]
+        for count, c in hist:
+            j = _nearest_rgb(centers, c)
+            for i in range(3):
+                sums[j][i] += c[i] * count
+            sums[j][3] += count
+        newcenters = [
+            [(t[i] + t[3] // 2) // t[3] for i in range(3)] if t[3] > 0 else m
+            for t, m in zip(sums, centers)
+        ]
+        if newcenters == centers:
+            break
+        centers = newcenters
+    total = width * height
+    ret = [[centers[j], sums[j][3] / total] for j in range(len(centers)) if sums[j][3]]
+    ret.sort(key=lambda x: -x[1])
+    return ret
+
+def accentcolor(image, width, height):
+    # Gets an accent color for an image: its most colorful color among its
+    # most frequent ones, such as for tinting a taskbar or title bar to
+    # match a wallpaper.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    hist = _colorhistogram(image, width, height)
+    # Weigh each color's chroma by how often it occurs
+    accent = None
+    best = -1
+    for count, c in hist[:32]:
+        score = _chroma(c) * math.sqrt(count)
+        if score > best:
+            best = score
+            accent = c
+    return accent
+
+def photomosaic(
+    image, width, height, tiles, columns, rows, cellwidth=16, cellheight=16
+):
+    # Generates a photo mosaic: the given image divided into a grid with the
+    # given number of columns and rows, where each cell is replaced with
+    # the tile whose average color best matches that part of the image.
+    # 'tiles' is a list of dictionaries, each with the keys "image",
+    # "width", and "height" (such as those read with
+    # imageformat.readimagedirectory()); each tile is resized to cover
+    # 'cellwidth' x 'cellheight' pixels.  Where possible, a cell doesn't get
+    # the same tile as the cell above it or to its left.
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if columns <= 0 or rows <= 0 or cellwidth <= 0 or cellheight <= 0:
+        raise ValueError
+    if len(tiles) == 0:
+        raise ValueError
+    thumbs = []
+    for t in tiles:
+        thumb = _coverresize(t["image"], t["width"], t["height"], cellwidth, cellheight)
+        thumbs.append([thumb, averagecolor(thumb, cellwidth, cellheight)])
+    # Average color of each cell of the image
+    cells = areaaverage(image, width, height, columns, rows)
+    w = columns * cellwidth
+    h = rows * cellheight
+    ret = blankimage(w, h)
+    chosen = []
+    for y in range(rows):
+        for x in range(columns):
+            pos = (y * columns + x) * 3
+            c = cells[pos : pos + 3]
+            ranked = sorted(
+                range(len(thumbs)),
+                key=lambda i: (thumbs[i][1][0] - c[0]) ** 2
+                + (thumbs[i][1][1] - c[1]) ** 2
+                + (thumbs[i][1][2] - c[2]) ** 2,
+            )
+            neighbors = []
+            if x > 0:
+                neighbors.append(chosen[-1])
+            if y > 0:
+                neighbors.append(chosen[(y - 1) * columns + x])
+            best = ranked[0]
+            for i in ranked[0:3]:
+                if i not in neighbors:
+                    best = i
+                    break
+            chosen.append(best)
+            imageblit(
+                ret,
+                w,
+                h,
+                thumbs[best][0],
+                cellwidth,
+                cellheight,
+                x * cellwidth,
+                y * cellheight,
+            )
+    return {"image": ret, "width": w, "height": h}
+
+def colorschemefromimage(image, width, height, palette=None):
+    # Generates a color scheme that matches the given wallpaper image,
+    # in the manner of the color schemes of the Desktop Themes for
+    # Windows 95.  The desktop color is the image's most frequent color,
+    # the title bar and selection colors are taken from the image's most
+    # colorful frequent color, and the 3-D object colors are light gray
+    # tinted toward the desktop color.
+    # 'palette' is an optional list of colors; if given, each color in
+    # the scheme is replaced with the nearest color in that palette
+    # (such as classiccolors(), for a scheme suitable for 16-color displays).
+    if width <= 0 or height <= 0:
+        raise ValueError
+    desktop = _colorhistogram(image, width, height)[0][1]
+    accent = accentcolor(image, width, height)
+    if _chroma(accent) < 32:
+        accent = [0, 0, 128]  # image is nearly gray; use the classic title color
+    elif _lum(accent) > 160:
+        accent = _mixcolor(accent, [0, 0, 0], 0.5)  # darken for title text
+    face = _mixcolor([192, 192, 192], desktop, 0.2)
+    scheme = classiccolorscheme()
+    scheme["Background"] = desktop
+    scheme["ActiveTitle"] = accent
+    scheme["Hilight"] = accent
+    scheme["HotTrackingColor"] = accent
+    scheme["MenuHilight"] = accent
+    scheme["GradientActiveTitle"] = _mixcolor(accent, [255, 255, 255], 0.4)
+    scheme["TitleText"] = _textcolor(accent)
+    scheme["HilightText"] = _textcolor(accent)
+    scheme["InactiveTitle"] = _mixcolor(face, [0, 0, 0], 0.33)
+    scheme["GradientInactiveTitle"] = _mixcolor(face, [255, 255, 255], 0.2)
+    scheme["InactiveTitleText"] = face
+    for k in [
+        "Scrollbar",
+        "Menu",
+        "ActiveBorder",
+        "InactiveBorder",
+        "ButtonFace",
+        "ButtonLight",
+        "MenuBar",
+    ]:
+        scheme[k] = face
+    scheme["ButtonHilight"] = _mixcolor(face, [255, 255, 255], 0.75)
+    scheme["ButtonShadow"] = _mixcolor(face, [0, 0, 0], 0.33)
+    scheme["GrayText"] = scheme["ButtonShadow"]
+    scheme["ButtonDkShadow"] = _mixcolor(face, [0, 0, 0], 0.9)
+    scheme["ButtonAlternateFace"] = _mixcolor(face, [0, 0, 0], 0.06)
+    scheme["AppWorkspace"] = _mixcolor(desktop, [128, 128, 128], 0.5)
+    for k in ["MenuText", "WindowText", "ButtonText"]:
+        scheme[k] = _textcolor(face)
+    if palette:
+        for k in scheme:
+            scheme[k] = palette[_nearest_rgb(palette, scheme[k])]
+    return scheme
+
+def writecolorscheme(f, colors, raiseIfExists=False):
+    # Writes a color scheme as a Windows registry (.reg) file that,
+    # when imported, sets the current user's colors.  See also
+    # writetheme(), which can also store a color scheme.
+    lines = [
+        "Windows Registry Editor Version 5.00",
+        "",
+        "[HKEY_CURRENT_USER\\Control Panel\\Colors]",
+    ]
+    for k in _themecolornames:
+        if k in colors:
+            c = colors[k]
+            lines.append('"%s"="%d %d %d"' % (k, c[0], c[1], c[2]))
+    lines.append("")
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    # Version 5 registry files are in UTF-16
+    ff.write(b"\xff\xfe" + bytes("\r\n".join(lines), "utf-16le"))
+    ff.close()
+
+# Terminal color schemes
+#
+# A terminal color scheme is a dictionary with the keys "background",
+# "foreground", and "cursor", each a color, and "colors", a list of the
+# 16 ANSI colors in order (black, red, green, yellow, blue, magenta, cyan,
+# white, then the bright versions of each).
+
+def _hue(c):
+    # Hue of a color, in degrees from 0 through 360
+    mx = max(c)
+    mn = min(c)
+    if mx == mn:
+        return 0
+    if mx == c[0]:
+        h = (c[1] - c[2]) / (mx - mn)
+    elif mx == c[1]:
+        h = 2 + (c[2] - c[0]) / (mx - mn)
+    else:
+        h = 4 + (c[0] - c[1]) / (mx - mn)
+    return (h * 60) % 360
+
+def terminalscheme(image, width, height):
+    # Generates a terminal color scheme (see above) that matches
+    # the given wallpaper image.  The background is a dark version of the
+    # image's main color, and each ANSI color is the standard color tinted
+    # toward the image's color of the most similar hue, if any.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    dominant = [c for c, f in dominantcolors(image, width, height, 8)]
+    main = dominant[0]
+    background = _mixcolor(main, [0, 0, 0], 0.8)
+    foreground = _mixcolor(main, [255, 255, 255], 0.85)
+    # Standard colors in hue order: red, yellow, green, cyan, blue, magenta
+    standard = {
+        1: [205, 49, 49],
+        3: [205, 205, 49],
+        2: [49, 205, 49],
+        6: [49, 205, 205],
+        4: [49, 90, 225],
+        5: [205, 49, 205],
+    }
+    colors = [None for i in range(16)]
+    colors[0] = _mixcolor(background, [0, 0, 0], 0.5)
+    colors[7] = _mixcolor(foreground, [192, 192, 192], 0.5)
+    colors[8] = _mixcolor(background, [128, 128, 128], 0.5)
+    colors[15] = _mixcolor(foreground, [255, 255, 255], 0.5)
+    for index, ref in standard.items():
+        c = ref
+        best = None
+        for d in dominant:
+            if _chroma(d) < 48:
+                continue
+            diff = abs(_hue(d) - _hue(ref))
+            diff = min(diff, 360 - diff)
+            if diff <= 30 and (best is None or diff < best[0]):
+                best = [diff, d]
+        if best:
+            c = _mixcolor(ref, best[1], 0.5)
+        colors[index] = c
+        colors[index + 8] = _mixcolor(c, [255, 255, 255], 0.35)
+    return {
+        "background": background,
+        "foreground": foreground,
+        "cursor": foreground,
+        "colors": colors,
+    }
+
+def writexresources(f, scheme, raiseIfExists=False):
+    # Writes a terminal color scheme as X resources, for use with xterm and
+    # other terminals for the X Window System (for example, by loading the file
+    # with "xrdb -merge").
+    def rgb(c):
+        return "#%02x%02x%02x" % (c[0], c[1], c[2])
+
+    lines = [
+        "*background: " + rgb(scheme["background"]),
+        "*foreground: " + rgb(scheme["foreground"]),
+        "*cursorColor: " + rgb(scheme["cursor"]),
+    ]
+    for i in range(16):
+        lines.append("*color%d: %s" % (i, rgb(scheme["colors"][i])))
+    ff = open(f, "x" if raiseIfExists else "w")
+    ff.write("\n".join(lines) + "\n")
+    ff.close()
+
+_ansicolornames = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "purple",
+    "cyan",
+    "white",
+]
+
+def writewindowsterminal(f, scheme, name="Wallpaper", raiseIfExists=False):
+    # Writes a terminal color scheme as JSON in the form of an entry of
+    # the "schemes" list in Windows Terminal's settings.
+    def rgb(c):
+        return "#%02X%02X%02X" % (c[0], c[1], c[2])
+
+    obj = {
+        "name": name,
+        "background": rgb(scheme["background"]),
+        "foreground": rgb(scheme["foreground"]),
+        "cursorColor": rgb(scheme["cursor"]),
+        "selectionBackground": rgb(scheme["colors"][8]),
+    }
+    for i in range(16):
+        n = _ansicolornames[i % 8]
+        key = n if i < 8 else "bright" + n[0].upper() + n[1:]
+        obj[key] = rgb(scheme["colors"][i])
+    ff = open(f, "x" if raiseIfExists else "w")
+    ff.write(json.dumps(obj, indent=4) + "\n")
+    ff.close()
+
+def writeitermcolors(f, scheme, raiseIfExists=False):
+    # Writes a terminal color scheme as an iTerm2 color preset (.itermcolors),
+    # an XML property list.
+    def entry(key, c):
+        ret = "\t<key>%s</key>\n\t<dict>\n" % (key)
+        for comp, v in zip(["Blue", "Green", "Red"], [c[2], c[1], c[0]]):
+            ret += "\t\t<key>%s Component</key>\n" % (comp)
+            ret += "\t\t<real>%.6f</real>\n" % (v / 255.0)
+        ret += "\t\t<key>Color Space</key>\n\t\t<string>sRGB</string>\n"
+        return ret + "\t</dict>\n"
+
+    text = (
+        '<?xml version="1.0" encoding="UTF-8"?>\n'
+        + '<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN"'
+        + ' "http://www.apple.com/DTDs/PropertyList-1.0.dtd">\n'
+        + '<plist version="1.0">\n<dict>\n'
+    )
+    for i in range(16):
+        text += entry("Ansi %d Color" % (i), scheme["colors"][i])
+    text += entry("Background Color", scheme["background"])
+    text += entry("Foreground Color", scheme["foreground"])
+    text += entry("Cursor Color", scheme["cursor"])
+    text += entry("Selection Color", scheme["colors"][8])
+    text += "</dict>\n</plist>\n"
+    ff = open(f, "x" if raiseIfExists else "w")
+    ff.write(text)
+    ff.close()
+
+def _ansi256colors():
+    # Colors of the 256-color palette of xterm and similar terminals
+    # The first 16 are the ANSI colors, taken from the CGA palette, whose
+    # order differs in swapping red and blue
+    cga = cgacolors()
+    ret = [cga[(i & 1) * 4 + (i & 2) + ((i >> 2) & 1) + (i & 8)] for i in range(16)]
+    levels = [0, 95, 135, 175, 215, 255]
+    for r in range(6):
+        for g in range(6):
+            for b in range(6):
+                ret.append([levels[r], levels[g], levels[b]])
+    for i in range(24):
+        ret.append([8 + i * 10, 8 + i * 10, 8 + i * 10])
+    return ret
+
+def toansi(image, width, height, columns=80, mode="blocks", colors=16, scheme=None):
+    # Converts an image to text art for a terminal, such as to show a
+    # wallpaper in a message of the day, using ANSI escape codes for color.
+    # 'columns' is the width of the art in characters; the number of lines is
+    # chosen to keep the image's proportions, assuming characters twice as
+    # tall as they are wide.  'mode' is "blocks" (each character is an
+    # upper half block showing two pixels, one above the other) or "ascii"
+    # (each character is an ASCII character, on the terminal's background,
+    # that is denser for brighter parts of the image).  'colors' is 16
+    # (the 16 ANSI colors) or 256 (xterm's 256-color palette).  'scheme' is a
+    # terminal color scheme (see terminalscheme()) giving the colors the
+    # terminal uses for the 16 ANSI colors; default is the CGA colors.
+    # Returns the text as a string.
+    if width <= 0 or height <= 0 or columns <= 0:
+        raise ValueError
+    if mode not in ["blocks", "ascii"] or colors not in [16, 256]:
+        raise ValueError
+    palette = _ansi256colors()
+    if scheme:
+        palette[0:16] = scheme["colors"]
+    if colors == 16:
+        palette = palette[0:16]
+    rows = max(1, (height * columns + width) // (width * 2))
+    pixrows = rows * 2 if mode == "blocks" else rows
+    small = areaaverage(image, width, height, columns, pixrows)
+    cache = {}
+
+    def nearest(pos):
+        c = small[pos : pos + 3]
+        key = c[0] | (c[1] << 8) | (c[2] << 16)
+        if key not in cache:
+            cache[key] = min(
+                range(len(palette)),
+                key=lambda i: (palette[i][0] - c[0]) ** 2
+                + (palette[i][1] - c[1]) ** 2
+                + (palette[i][2] - c[2]) ** 2,
+            )
+        return cache[key]
+
+    def code(index, background):
+        if colors == 256:
+            return ("48;5;%d" if background else "38;5;%d") % (index)
+        base = (40 if background else 30) if index < 8 else (100 if background else 90)
+        return "%d" % (base + (index & 7))
+
+    chars = " .:-=+*#%@"
+    ret = ""
+    for y in range(rows):
+        last = None
+        for x in range(columns):
+            if mode == "blocks":
+                fg = nearest((y * 2 * columns + x) * 3)
+                bg = nearest(((y * 2 + 1) * columns + x) * 3)
+                if last != [fg, bg]:
+                    ret += "\x1b[%s;%sm" % (code(fg, False), code(bg, True))
+                    last = [fg, bg]
+                ret += "\u2580"
+            else:
+                pos = (y * columns + x) * 3
+                lum = (
+                    small[pos] * 299 + small[pos + 1] * 587 + small[pos + 2] * 114
+                ) // 1000
+                fg = nearest(pos)
+                if last != fg:
+                    ret += "\x1b[%sm" % (code(fg, False))
+                    last = fg
+                ret += chars[lum * len(chars) // 256]
+        ret += "\x1b[0m\n"
+    return ret
+
+def writeansi(
+    f,
+    image,
+    width,
+    height,
+    columns=80,
+    mode="blocks",
+    colors=16,
+    raiseIfExists=False,
+    scheme=None,
+):
+    # Writes the text art generated by toansi() to a UTF-8 text file.
+    text = toansi(image, width, height, columns, mode, colors, scheme)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(text.encode("utf-8"))
+    ff.close()
+
+def c64colors():
+    # 16-color palette of the Commodore 64
+    return [
+        [0, 0, 0],
+        [255, 255, 255],
+        [136, 0, 0],
+        [170, 255, 238],
+        [204, 68, 204],
+        [0, 204, 85],
+        [0, 0, 170],
+        [238, 238, 119],
+        [221, 136, 85],
+        [102, 68, 0],
+        [255, 119, 119],
+        [51, 51, 51],
+        [119, 119, 119],
+        [170, 255, 102],
+        [0, 136, 255],
+        [187, 187, 187],
+    ]
+
+def teletextcolors():
+    # The eight colors of teletext, in the order of their color codes
+    return [
+        [(i & 1) * 255, ((i >> 1) & 1) * 255, ((i >> 2) & 1) * 255] for i in range(8)
+    ]
+
+# C64 screen codes for the 16 quadrant block characters, indexed by which
+# quadrants are set (1 = upper left, 2 = upper right, 4 = lower left,
+# 8 = lower right); codes from 0x80 up are reversed characters
+_petsciiquadrants = [
+    0x20,
+    0x7C,
+    0x7E,
+    0xE2,
+    0x7B,
+    0x61,
+    0xFF,
+    0xEC,
+    0x6C,
+    0x7F,
+    0xE1,
+    0xFB,
+    0x62,
+    0xFE,
+    0xFC,
+    0xA0,
+]
+
+def blockmosaic(image, width, height, columns, rows, mode="petscii", background=None):
+    # Converts an image to a mosaic of block graphics characters, such as
+    # those of the Commodore 64 (PETSCII) or teletext, for use on those
+    # systems.  The image is divided into a grid with the given number of
+    # columns and rows, and each cell becomes a character made of blocks
+    # drawn in a foreground color over a background color.
+    # 'mode' is "petscii" (2x2 blocks per character, in the colors of
+    # c64colors()) or "teletext" (2x3 blocks per character, as in teletext
+    # "contiguous" mosaic characters, in the colors of teletextcolors()).
+    # 'background' is the index of the background color in that palette to
+    # use for all cells (as in the Commodore 64's normal text mode, where
+    # the background color is shared), or None to choose one for each cell.
+    # Returns a dictionary with the keys "mode", "columns", "rows",
+    # "chars" (the character codes: C64 screen codes or teletext codes),
+    # "blocks" (for each cell, a number whose bits give the blocks drawn in
+    # the foreground color, from left to right, then top to bottom), and "fg"
+    # and "bg" (the foreground and background color indices), each a list
+    # with one entry per cell, row by row.
+    if columns <= 0 or rows <= 0:
+        raise ValueError
+    if mode == "petscii":
+        palette = c64colors()
+        bh = 2
+    elif mode == "teletext":
+        palette = teletextcolors()
+        bh = 3
+    else:
+        raise ValueError
+    if background is not None and (background < 0 or background >= len(palette)):
+        raise ValueError
+    small = areaaverage(image, width, height, columns * 2, rows * bh)
+    sw = columns * 2
+
+    def dist(c, p):
+        return (c[0] - p[0]) ** 2 + (c[1] - p[1]) ** 2 + (c[2] - p[2]) ** 2
+
+    bgchoices = range(len(palette)) if background is None else [background]
+    ret = {"mode": mode, "columns": columns, "rows": rows}
+    ret["chars"] = []
+    ret["blocks"] = []
+    ret["fg"] = []
+    ret["bg"] = []
+    for cy in range(rows):
+        for cx in range(columns):
+            sub = []
+            for j in range(bh):
+                for i in range(2):
+                    pos = ((cy * bh + j) * sw + cx * 2 + i) * 3
+                    sub.append(small[pos : pos + 3])
+            # Find the pair of colors that best matches the cell's blocks
+            best = None
+            for bg in bgchoices:
+                for fg in range(len(palette)):
+                    err = 0
+                    bits = 0
+                    for k in range(len(sub)):
+                        dfg = dist(sub[k], palette[fg])
+                        dbg = dist(sub[k], palette[bg])
+                        if dfg < dbg:
+                            bits |= 1 << k
+                        err += min(dfg, dbg)
+                    if best is None or err < best[0]:
+                        best = [err, bits, fg, bg]
+            err, bits, fg, bg = best
+            if mode == "petscii":
+                ch = _petsciiquadrants[bits]
+            else:
+                # Teletext mosaic characters skip the 0x20 bit
+                ch = 0x20 | (bits & 0x1F) | ((bits & 0x20) << 1)
+            ret["chars"].append(ch)
+            ret["blocks"].append(bits)
+            ret["fg"].append(fg)
+            ret["bg"].append(bg)
+    return ret
+
+def renderblockmosaic(mosaic):
+    # Draws a mosaic generated by blockmosaic() as it would appear on screen,
+    # with 8x8-pixel characters for "petscii" and 12x18-pixel characters
+    # for "teletext".
+    # Returns a dictionary with "image", "width", and "height" keys.
+    if mosaic["mode"] == "petscii":
+        palette = c64colors()
+        bw, bh, brows = 4, 4, 2
+    else:
+        palette = teletextcolors()
+        bw, bh, brows = 6, 6, 3
+    columns = mosaic["columns"]
+    rows = mosaic["rows"]
+    width = columns * bw * 2
+    height = rows * bh * brows
+    image = blankimage(width, height)
+    for cell in range(columns * rows):
+        x0 = (cell % columns) * bw * 2
+        y0 = (cell // columns) * bh * brows
+        for k in range(2 * brows):
+            on = (mosaic["blocks"][cell] >> k) & 1
+            c = palette[mosaic["fg"][cell] if on else mosaic["bg"][cell]]
+            bx = x0 + (k % 2) * bw
+            by = y0 + (k // 2) * bh
+            rectangle(image, width, height, bx, by, bx + bw, by + bh, c)
+    return {"image": image, "width": width, "height": height}
+
+def writeblockmosaic(f, mosaic, raiseIfExists=False):
+    # Writes the character data of a mosaic generated by blockmosaic(): the
+    # character codes, then the foreground color indices, then the background
+    # color indices, one byte per cell in each part, row by row.  For the
+    # "petscii" mode, the first two parts are the contents of the
+    # Commodore 64's screen memory and color memory.
+    data = bytes(mosaic["chars"]) + bytes(mosaic["fg"]) + bytes(mosaic["bg"])
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(data)
+    ff.close()
+
+# image cache
+
+def _cachedirectory():
+    base = os.environ.get("XDG_CACHE_HOME") or os.path.join(
+        os.path.expanduser("~"), ".cache"
+    )
+    return os.path.join(base, "classic-wallpaper")
+
+def recipehash(recipe):
+    # Gets a hash string identifying a "recipe" for an image, that is,
+    # a dictionary of JSON-compatible values (such as a random seed, a
+    # style name, and an image size) that fully determines the image.
+    import hashlib
+
+    text = json.dumps(recipe, sort_keys=True, separators=(",", ":"))
+    return hashlib.sha256(text.encode("utf-8")).hexdigest()
+
+def cachedpng(recipe, render, directory=None, maxbytes=64 * 1024 * 1024):
+    # Gets the PNG file for the image described by 'recipe' (see recipehash())
+    # from an on-disk cache, as a byte string.  If the cache has no such
+    # file, calls 'render', a function taking no parameters that returns
+    # the file's contents, and stores the result in the cache.  'directory'
+    # is the cache directory; the default is "classic-wallpaper" in the
+    # user's cache directory (such as ~/.cache).  When the cache's files
+    # total more than 'maxbytes' bytes, the least recently used files are
+    # deleted.
+    if directory is None:
+        directory = _cachedirectory()
+    path = os.path.join(directory, recipehash(recipe) + ".png")
+    try:
+        ff = open(path, "rb")
+        data = ff.read()
+        ff.close()
+        os.utime(path)  # mark as recently used
+        return data
+    except OSError:
+        pass
+    data = render()
+    try:
+        os.makedirs(directory, exist_ok=True)
+        # Write to a temporary file first so that a reader never
+        # sees a partly written file
+        temppath = path + ".%d.tmp" % (os.getpid())
+        ff = open(temppath, "wb")
+        ff.write(data)
+        ff.close()
+        os.replace(temppath, path)
+        _prunecache(directory, maxbytes)
+    except OSError as e:
+        # The cache is only an optimization
+        _logger.warning("can't write to image cache %s: %s", directory, e)
+    return data
+
+def _prunecache(directory, maxbytes):
+    files = []
+    total = 0
+    for name in os.listdir(directory):
+        if not name.endswith(".png"):
+            continue
+        st = os.stat(os.path.join(directory, name))
+        files.append([st.st_mtime, st.st_size, name])
+        total += st.st_size
+    files.sort()
+    for mtime, size, name in files:
+        if total <= maxbytes:
+            break
+        os.remove(os.path.join(directory, name))
+        total -= size
+
+# preview server
+
+def serve(
+    port=8000,
+    host="127.0.0.1",
+    cache=True,
+    fortunes=None,
+    weather=None,
+    variants=None,
+):
+    # Runs a small HTTP server that generates wallpapers on request, so that
+    # other devices or HTML-based wallpaper programs can fetch them.  Runs
+    # until interrupted.  Requests take the following form:
+    # /wallpaper.png?seed=S&style=T&w=W&h=H&overlay=O&schedule=default
+    # where all parameters are optional: 'seed' seeds the random number
+    # generator so that the same seed gives the same wallpaper, 'style' is
+    # as in randomwallpaper(), and 'w' and 'h' give the size of the image to
+    # return, which is the generated wallpaper tiled over that size (default
+    # is the size of the wallpaper itself).  'overlay' is "calendar" or
+    # "clock" to draw the current month's calendar or the current time on
+    # the image (see datetimeoverlay()), or "sysinfo" to draw a panel of
+    # system information (see sysinfooverlay()).  'schedule' is "default" to
+    # change the wallpaper's colors with the time of day (see
+    # scheduledwallpaper()).  If 'cache' is True, images for requests with
+    # a seed are kept in the cache described in cachedpng().
+    # 'fortunes' is the name of a text file of sayings, one of which is drawn
+    # on each image (see fortuneoverlay()), or None to draw no sayings.
+    # Raises an error at once if that file can't be read or has no sayings.
+    # 'weather' is a source of the current weather condition (see
+    # weathercondition()), or None to ignore the weather.  If it's given,
+    # the wallpaper for the current condition is chosen from 'variants', a
+    # dictionary as in weatherwallpaper(); the generated wallpaper is used
+    # for conditions not in 'variants' unless it has a "default" key.
+    import datetime
+    import http.server
+    import subprocess
+    import urllib.parse
+
+    class Handler(http.server.BaseHTTPRequestHandler):
+        def log_message(self, format, *args):
+            _logger.info("%s %s", self.address_string(), format % args)
+
+        def do_GET(self):
+            url = urllib.parse.urlparse(self.path)
+            if url.path != "/wallpaper.png":
+                self.send_error(404)
+                return
+            query = urllib.parse.parse_qs(url.query)
+
+            def param(name):
+                return query[name][0] if name in query else None
+
+            def render():
+                if param("seed") is not None:
+                    random.seed(param("seed"))
+                wp = randomwallpaper(style=param("style"))
+                w = int(param("w")) if param("w") else wp["width"]
+                h = int(param("h")) if param("h") else wp["height"]
+                if w <= 0 or h <= 0 or w * h > 4096 * 4096:
+                    raise ValueError
+                image = wraparoundcrop(
+                    wp["image"], wp["width"], wp["height"], 0, 0, w, h
+                )
+                if condition is not None:
+                    choices = dict(variants) if variants else {}
+                    choices.setdefault("default", image)
+                    image = weatherwallpaper(choices, w, h, condition)
+                if param("schedule") == "default":
+                    image = scheduledwallpaper(image, w, h, None, now)
+                elif param("schedule") is not None:
+                    raise ValueError
+                if param("overlay") == "sysinfo":
+                    sysinfooverlay(image, w, h)
+                elif param("overlay") is not None:
+                    datetimeoverlay(image, w, h, param("overlay"), now)
+                if fortunes:
+                    fortuneoverlay(image, w, h, fortunes, param("seed"))
+                return pngbytes(image, w, h)
+
+            now = datetime.datetime.now()
+            condition = None
+            if weather is not None:
+                try:
+                    condition = weathercondition(weather)
+                except (OSError, ValueError, subprocess.SubprocessError) as e:
+                    _logger.error("can't get the weather: %s", e)
+                    self.send_error(500)
+                    return
+            try:
+                if cache and param("seed") is not None:
+                    recipe = {
+                        "seed": param("seed"),
+                        "style": param("style"),
+                        "w": param("w"),
+                        "h": param("h"),
+                        "overlay": param("overlay"),
+                        "schedule": param("schedule"),
+                    }
+                    if fortunes:
+                        # Cache the image only until the file of sayings changes
+                        recipe["fortunes"] = [fortunes, os.path.getmtime(fortunes)]
+                    if condition is not None:
+                        recipe["weather"] = condition
+                    if param("overlay") is not None or param("schedule"):
+                        # Cache the image only until the overlay or colors
+                        # change
+                        recipe["time"] = now.strftime("%Y-%m-%d %H:%M")
+                        if (
+                            param("overlay") == "calendar"
+                            and param("schedule") is None
+                        ):
+                            recipe["time"] = now.strftime("%Y-%m-%d")
+                    data = cachedpng(recipe, render)
+                else:
+                    data = render()
+            except ValueError:
+                self.send_error(400)
+                return
+            except OSError as e:
+                # Such as a file of sayings that can no longer be read
+                _logger.error("%s", e)
+                self.send_error(500)
+                return
+            self.send_response(200)
+            self.send_header("Content-Type", "image/png")
+            self.send_header("Content-Length", str(len(data)))
+            self.end_headers()
+            self.wfile.write(data)
+
+    if fortunes:
+        randomfortune(fortunes)
+    server = http.server.HTTPServer((host, port), Handler)
+    try:
+        server.serve_forever()
+    finally:
+        server.server_close()
+
 if __name__ == "__main__":
     try:
         os.mkdir("palettes")
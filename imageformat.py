@@ -0,0 +1,2104 @@
+# This Python script reads and writes image files in various
+# classic formats, for use with the images generated by desktopwallpaper.py.
+# As in that script, an image is a list of integers giving the red,
+# green, and blue components (each from 0 through 255) of each pixel
+# in turn, starting with the top row from left to right.  Like
+# desktopwallpaper.py, these methods are implemented in pure Python and
+# are intended for relatively small images.
+#
+# This script is released to the public domain; in case that is not possible, the
+# file is also licensed under Creative Commons Zero (CC0).
+#
+
+import logging
+import os
+import struct
+
+# Warnings about recoverable problems in files read, such as truncated
+# data, are reported to this logger.
+_logger = logging.getLogger("imageformat")
+
+def _readfile(f):
+    ff = open(f, "rb")
+    data = ff.read()
+    ff.close()
+    return data
+
+def _u16(data, pos):
+    if pos < 0 or pos + 2 > len(data):
+        raise ValueError
+    return data[pos] | (data[pos + 1] << 8)
+
+def _u32(data, pos):
+    if pos < 0 or pos + 4 > len(data):
+        raise ValueError
+    return (
+        data[pos]
+        | (data[pos + 1] << 8)
+        | (data[pos + 2] << 16)
+        | (data[pos + 3] << 24)
+    )
+
+def _s32(data, pos):
+    v = _u32(data, pos)
+    return v - (1 << 32) if v >= (1 << 31) else v
+
+# Decoding limits
+
+# The readers in this script treat their input as untrusted: a truncated or
+# malformed file causes a ValueError rather than any other kind of error.
+# Each reader also takes a 'limits' parameter, a DecodeLimits object that
+# bounds the size of the images it will decode, so that a tiny file claiming
+# to hold a huge image doesn't use up all memory.  If 'limits' is None, the
+# default limits are used.
+
+class DecodeLimits:
+    def __init__(self, maxwidth=16384, maxheight=16384, maxbytes=1 << 27):
+        # 'maxbytes' is the maximum size of a decoded image's red, green,
+        # and blue components, in bytes
+        self.maxwidth = maxwidth
+        self.maxheight = maxheight
+        self.maxbytes = maxbytes
+
+    def check(self, width, height):
+        # Raises a ValueError if an image of the given size is
+        # outside these limits
+        if width <= 0 or height <= 0:
+            raise ValueError
+        if width > self.maxwidth or height > self.maxheight:
+            raise ValueError("image dimensions exceed limits")
+        if width * height * 3 > self.maxbytes:
+            raise ValueError("image size exceeds limits")
+
+def _limits(limits):
+    return DecodeLimits() if limits is None else limits
+
+def _decoder(func):
+    # Wraps a reader so that errors caused by malformed data, such as
+    # out-of-range indices, are reported as ValueErrors
+    def wrapper(*args, **kwargs):
+        try:
+            return func(*args, **kwargs)
+        except (
+            IndexError,
+            KeyError,
+            OverflowError,
+            ZeroDivisionError,
+            struct.error,
+        ) as e:
+            raise ValueError("malformed image file") from e
+
+    wrapper.__name__ = func.__name__
+    return wrapper
+
+def _applypalette(indices, palette):
+    # Converts a list of color indices to an image using the given palette
+    image = [0 for i in range(len(indices) * 3)]
+    for i in range(len(indices)):
+        if indices[i] >= len(palette):
+            raise ValueError("color index out of range")
+        c = palette[indices[i]]
+        image[i * 3] = c[0]
+        image[i * 3 + 1] = c[1]
+        image[i * 3 + 2] = c[2]
+    return image
+
+# Orientation
+
+# Some formats store an image's rows bottom first, or its pixels right to
+# left.  The readers in this script always return images with the top row
+# first, from left to right, and, for such formats, give the order the file
+# used in the key "orientation", a number as in the Exif Orientation tag:
+# 1 means top row first, left to right; 2, top row first, right to left;
+# 3, bottom row first, right to left; 4, bottom row first, left to right;
+# and 5 through 8 are 1 through 4 with rows and columns swapped.
+
+def orientimage(image, width, height, orientation):
+    # Converts an image stored in the given orientation to one with the top
+    # row first, from left to right.  Returns a dictionary with the keys
+    # "image", "width", and "height" (which are swapped for orientations 5
+    # through 8).
+    if orientation < 1 or orientation > 8:
+        raise ValueError("unsupported orientation")
+    if len(image) != width * height * 3:
+        raise ValueError
+    swap = orientation >= 5
+    dw = height if swap else width
+    dh = width if swap else height
+    ret = [0 for i in range(width * height * 3)]
+    for dy in range(dh):
+        for dx in range(dw):
+            if orientation == 1:
+                sx, sy = dx, dy
+            elif orientation == 2:
+                sx, sy = width - 1 - dx, dy
+            elif orientation == 3:
+                sx, sy = width - 1 - dx, height - 1 - dy
+            elif orientation == 4:
+                sx, sy = dx, height - 1 - dy
+            elif orientation == 5:
+                sx, sy = dy, dx
+            elif orientation == 6:
+                sx, sy = dy, height - 1 - dx
+            elif orientation == 7:
+                sx, sy = width - 1 - dy, height - 1 - dx
+            else:
+                sx, sy = width - 1 - dy, dx
+            sp = (sy * width + sx) * 3
+            dp = (dy * dw + dx) * 3
+            ret[dp] = image[sp]
+            ret[dp + 1] = image[sp + 1]
+            ret[dp + 2] = image[sp + 2]
+    return {"image": ret, "width": dw, "height": dh}
+
+# BMP and OS/2 bitmap files
+
+def _bmprledecode(data, pos, width, height, bitcount):
+    # Decodes RLE8 (bitcount 8) or RLE4 (bitcount 4) bitmap data into a list
+    # of color indices, top row first.  Pixels skipped over by the data
+    # get index 0.
+    indices = [0 for i in range(width * height)]
+    x = 0
+    y = height - 1  # RLE bitmaps are stored bottom row first
+
+    def put(idx):
+        if x < width and y >= 0:
+            indices[y * width + x] = idx
+
+    while True:
+        if pos + 2 > len(data):
+            raise ValueError
+        count = data[pos]
+        value = data[pos + 1]
+        pos += 2
+        if count > 0:
+            # Run of 'count' pixels
+            for i in range(count):
+                if bitcount == 8:
+                    put(value)
+                else:
+                    put((value >> 4) if i % 2 == 0 else (value & 0x0F))
+                x += 1
+        elif value == 0:  # End of line
+            x = 0
+            y -= 1
+        elif value == 1:  # End of bitmap
+            break
+        elif value == 2:  # Delta
+            if pos + 2 > len(data):
+                raise ValueError
+            x += data[pos]
+            y -= data[pos + 1]
+            pos += 2
+        else:
+            # Absolute run of 'value' pixels, padded to a 2-byte boundary
+            nbytes = value if bitcount == 8 else (value + 1) // 2
+            if pos + nbytes > len(data):
+                raise ValueError
+            for i in range(value):
+                if bitcount == 8:
+                    put(data[pos + i])
+                else:
+                    b = data[pos + i // 2]
+                    put((b >> 4) if i % 2 == 0 else (b & 0x0F))
+                x += 1
+            pos += nbytes + (nbytes & 1)
+        if y < 0:
+            break
+    return indices
+
+def _bmpdecode(data, infopos, bitspos, limits=None):
+    # Decodes a device-independent bitmap whose information header starts at
+    # 'infopos' (followed by the color table) and whose pixel data starts at
+    # 'bitspos'.  Supports OS/2 1.x headers (BITMAPCOREHEADER), Windows headers
+    # (BITMAPINFOHEADER and later) and OS/2 2.x headers (BITMAPINFOHEADER2),
+    # with 1, 4, 8, 24, or 32 bits per pixel, either uncompressed or, for 4
+    # and 8 bits per pixel, compressed with run-length encoding (RLE4 or RLE8).
+    hdrsize = _u32(data, infopos)
+    if hdrsize == 12:
+        width = _u16(data, infopos + 4)
+        height = _u16(data, infopos + 6)
+        bitcount = _u16(data, infopos + 10)
+        compression = 0
+        colorsused = 0
+        entrysize = 3
+    elif hdrsize >= 16:
+        width = _s32(data, infopos + 4)
+        height = _s32(data, infopos + 8)
+        bitcount = _u16(data, infopos + 14)
+        compression = _u32(data, infopos + 16) if hdrsize >= 20 else 0
+        colorsused = _u32(data, infopos + 32) if hdrsize >= 36 else 0
+        entrysize = 4
+    else:
+        raise ValueError
+    topdown = height < 0
+    height = abs(height)
+    _limits(limits).check(width, height)
+    if bitcount not in [1, 4, 8, 24, 32]:
+        raise ValueError("unsupported bit count")
+    if compression != 0 and not (
+        (compression == 1 and bitcount == 8) or (compression == 2 and bitcount == 4)
+    ):
+        raise ValueError("unsupported compression")
+    palette = None
+    if bitcount <= 8:
+        numcolors = colorsused if colorsused > 0 else (1 << bitcount)
+        if numcolors > 256:
+            raise ValueError
+        palpos = infopos + hdrsize
+        if palpos + numcolors * entrysize > len(data):
+            raise ValueError
+        palette = [
+            [
+                data[palpos + i * entrysize + 2],
+                data[palpos + i * entrysize + 1],
+                data[palpos + i * entrysize],
+            ]
+            for i in range(numcolors)
+        ]
+    if compression != 0:
+        indices = _bmprledecode(data, bitspos, width, height, bitcount)
+        image = _applypalette(indices, palette)
+        return {
+            "image": image,
+            "width": width,
+            "height": height,
+            "palette": palette,
+            "orientation": 4,
+        }
+    stride = ((width * bitcount + 31) // 32) * 4
+    if bitspos + stride * height > len(data):
+        raise ValueError
+    image = [0 for i in range(width * height * 3)]
+    for y in range(height):
+        rowpos = bitspos + (y if topdown else height - 1 - y) * stride
+        yp = y * width * 3
+        for x in range(width):
+            if bitcount == 24 or bitcount == 32:
+                pos = rowpos + x * (bitcount // 8)
+                c = [data[pos + 2], data[pos + 1], data[pos]]
+            else:
+                bit = x * bitcount
+                idx = (data[rowpos + (bit >> 3)] >> (8 - bitcount - (bit & 7))) & (
+                    (1 << bitcount) - 1
+                )
+                if idx >= len(palette):
+                    raise ValueError
+                c = palette[idx]
+            image[yp + x * 3] = c[0]
+            image[yp + x * 3 + 1] = c[1]
+            image[yp + x * 3 + 2] = c[2]
+    ret = {"image": image, "width": width, "height": height}
+    ret["orientation"] = 1 if topdown else 4
+    if palette:
+        ret["palette"] = palette
+    return ret
+
+@_decoder
+def readbmp(f, limits=None):
+    # Reads a BMP file, in either the Windows or the OS/2 format.
+    # Returns a dictionary with the keys "image", "width", "height", and
+    # "orientation" (1 for a top-down bitmap, otherwise 4; see "Orientation"),
+    # and the key "palette" if the bitmap has a color table.
+    data = _readfile(f)
+    if data[0:2] != b"BM":
+        raise ValueError("not a BMP file")
+    return _bmpdecode(data, 14, _u32(data, 10), limits)
+
+def _imagepalette(image, width, height, maxcolors=256):
+    # Gets the colors used in an image, or None if it uses more than
+    # 'maxcolors' colors
+    colors = {}
+    for i in range(0, width * height * 3, 3):
+        c = image[i] | (image[i + 1] << 8) | (image[i + 2] << 16)
+        if c not in colors:
+            if len(colors) >= maxcolors:
+                return None
+            colors[c] = len(colors)
+    return colors
+
+def _bmpbits(image, width, height, colors):
+    # Generates the pixel data of a bottom-up device-independent bitmap.
+    # 'colors' maps each color to its palette index (as returned by
+    # _imagepalette), or is None for a 24-bit bitmap.
+    if colors is None:
+        bitcount = 24
+    else:
+        bitcount = 1 if len(colors) <= 2 else (4 if len(colors) <= 16 else 8)
+    stride = ((width * bitcount + 31) // 32) * 4
+    bits = bytearray(stride * height)
+    for y in range(height):
+        rowpos = (height - 1 - y) * stride
+        yp = y * width * 3
+        for x in range(width):
+            pos = yp + x * 3
+            if bitcount == 24:
+                bits[rowpos + x * 3] = image[pos + 2]
+                bits[rowpos + x * 3 + 1] = image[pos + 1]
+                bits[rowpos + x * 3 + 2] = image[pos]
+            else:
+                idx = colors[image[pos] | (image[pos + 1] << 8) | (image[pos + 2] << 16)]
+                bit = x * bitcount
+                bits[rowpos + (bit >> 3)] |= idx << (8 - bitcount - (bit & 7))
+    return bitcount, bytes(bits)
+
+def _bmpcoreheader(image, width, height):
+    # Generates an OS/2 1.x bitmap information header (BITMAPCOREHEADER),
+    # color table, and pixel data for an image; uses a color table
+    # if the image has 256 or fewer colors.
+    if width <= 0 or height <= 0 or width > 0xFFFF or height > 0xFFFF:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    colors = _imagepalette(image, width, height)
+    bitcount, bits = _bmpbits(image, width, height, colors)
+    header = struct.pack("<LHHHH", 12, width, height, 1, bitcount)
+    if colors is not None:
+        table = bytearray(3 << bitcount)
+        for c, i in colors.items():
+            table[i * 3] = (c >> 16) & 0xFF
+            table[i * 3 + 1] = (c >> 8) & 0xFF
+            table[i * 3 + 2] = c & 0xFF
+        header += bytes(table)
+    return header, bits
+
+def writebmp(f, image, width, height, raiseIfExists=False, indexed=False):
+    # Writes an image to a Windows BMP file with a BITMAPINFOHEADER, the
+    # format classic versions of Windows expect for desktop wallpapers.
+    # The bitmap has 24 bits per pixel, unless 'indexed' is True and the
+    # image has 256 or fewer colors, in which case it has a color table and
+    # 1, 4, or 8 bits per pixel.
+    header, bits = _bmpinfoheader(image, width, height, not indexed)
+    offset = 14 + len(header)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(b"BM" + struct.pack("<LhhL", offset + len(bits), 0, 0, offset))
+    ff.write(header)
+    ff.write(bits)
+    ff.close()
+
+def _indexedimage(image, width, height, palette=None):
+    # Converts an image to a list of palette indices, one for each pixel,
+    # giving each pixel the closest color in 'palette', a list of up to 256
+    # colors (such as the palette an image was dithered to).  If 'palette'
+    # is None, the image's own colors are used if it has 256 or fewer, and
+    # otherwise the image is reduced to a 6x6x6 color cube.
+    # Returns the palette and the list of indices.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    if palette is None:
+        colors = _imagepalette(image, width, height)
+        if colors is not None:
+            palette = [[0, 0, 0] for i in range(len(colors))]
+            for c, i in colors.items():
+                palette[i] = [c & 0xFF, (c >> 8) & 0xFF, c >> 16]
+        else:
+            palette = [
+                [r * 51, g * 51, b * 51]
+                for r in range(6)
+                for g in range(6)
+                for b in range(6)
+            ]
+    if len(palette) == 0 or len(palette) > 256:
+        raise ValueError
+    cache = {}
+    indices = [0 for i in range(width * height)]
+    for i in range(width * height):
+        c = image[i * 3 : i * 3 + 3]
+        key = c[0] | (c[1] << 8) | (c[2] << 16)
+        if key not in cache:
+            cache[key] = min(
+                range(len(palette)),
+                key=lambda k: (palette[k][0] - c[0]) ** 2
+                + (palette[k][1] - c[1]) ** 2
+                + (palette[k][2] - c[2]) ** 2,
+            )
+        indices[i] = cache[key]
+    return palette, indices
+
+def _bmprle8encode(indices, width, height):
+    # Compresses a bottom-up bitmap with 8 bits per pixel using RLE8
+    out = bytearray()
+    for y in range(height - 1, -1, -1):
+        row = indices[y * width : (y + 1) * width]
+        i = 0
+        while i < width:
+            run = 1
+            while i + run < width and run < 255 and row[i + run] == row[i]:
+                run += 1
+            if run >= 2:
+                out += bytes([run, row[i]])
+                i += run
+                continue
+            # Pixels that don't begin a run of two or more
+            j = i
+            while j < width and j - i < 255:
+                if j + 1 < width and row[j] == row[j + 1]:
+                    break
+                j += 1
+            if j - i >= 3:
+                out += bytes([0, j - i]) + bytes(row[i:j])
+                if (j - i) & 1:
+                    out.append(0)  # pad to a 16-bit boundary
+                i = j
+            else:
+                out += bytes([1, row[i]])
+                i += 1
+        out += b"\0\0"  # end of line
+    out += b"\0\1"  # end of bitmap
+    return bytes(out)
+
+def writebmprle8(f, image, width, height, palette=None, raiseIfExists=False):
+    # Writes an image to a Windows BMP file with 8 bits per pixel, compressed
+    # with run-length encoding (RLE8), which classic versions of Windows
+    # accept for wallpapers and which is much smaller than an uncompressed
+    # bitmap for images with large areas of flat color.
+    # 'palette' is as in _indexedimage().
+    palette, indices = _indexedimage(image, width, height, palette)
+    bits = _bmprle8encode(indices, width, height)
+    header = struct.pack(
+        "<LllHHLLllLL",
+        40,
+        width,
+        height,
+        1,
+        8,
+        1,  # BI_RLE8
+        len(bits),
+        2835,  # 72 pixels per inch
+        2835,
+        len(palette),
+        0,
+    )
+    header += b"".join(bytes([c[2], c[1], c[0], 0]) for c in palette)
+    offset = 14 + len(header)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(b"BM" + struct.pack("<LhhL", offset + len(bits), 0, 0, offset))
+    ff.write(header)
+    ff.write(bits)
+    ff.close()
+
+def writeos2bmp(f, image, width, height, raiseIfExists=False):
+    # Writes an image to an OS/2 1.x BMP file (with a BITMAPCOREHEADER), the
+    # format OS/2 Presentation Manager uses for desktop backgrounds.  The
+    # bitmap has a color table if the image has 256 or fewer colors.
+    header, bits = _bmpcoreheader(image, width, height)
+    offset = 14 + len(header)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(b"BM" + struct.pack("<LhhL", offset + len(bits), 0, 0, offset))
+    ff.write(header)
+    ff.write(bits)
+    ff.close()
+
+# OS/2 bitmap arrays
+#
+# A bitmap array ("BA") file, used in OS/2 Presentation Manager, holds several
+# versions of a bitmap, each designed for a display of a given resolution
+# (such as 640x480 for VGA or 1024x768 for XGA).  Each version is preceded by
+# a bitmap array header, which gives the display resolution (0x0 for a
+# device-independent version) and the offset to the next header.
+
+@_decoder
+def readbitmaparrayentries(f, limits=None):
+    # Reads the bitmaps in an OS/2 bitmap array file, or the bitmap in an
+    # ordinary BMP file.  Returns a list of dictionaries, each with the keys
+    # "image", "width", "height", "displaywidth", and "displayheight",
+    # and the key "palette" if the bitmap has a color table.
+    # Bitmaps in the array other than ordinary bitmaps (such as
+    # icons and pointers) are skipped.
+    data = _readfile(f)
+    ret = []
+    if data[0:2] == b"BM":
+        entry = _bmpdecode(data, 14, _u32(data, 10), limits)
+        entry["displaywidth"] = 0
+        entry["displayheight"] = 0
+        return [entry]
+    pos = 0
+    seen = {}
+    while True:
+        if data[pos : pos + 2] != b"BA":
+            raise ValueError
+        if pos in seen:  # cycle in the list of bitmaps
+            raise ValueError
+        seen[pos] = True
+        nextpos = _u32(data, pos + 6)
+        displaywidth = _u16(data, pos + 10)
+        displayheight = _u16(data, pos + 12)
+        if data[pos + 14 : pos + 16] == b"BM":
+            entry = _bmpdecode(data, pos + 28, _u32(data, pos + 24), limits)
+            entry["displaywidth"] = displaywidth
+            entry["displayheight"] = displayheight
+            ret.append(entry)
+        if nextpos == 0:
+            break
+        pos = nextpos
+    return ret
+
+def readbitmaparray(f, displaywidth=0, displayheight=0, limits=None):
+    # Reads the bitmap in an OS/2 bitmap array file (or an ordinary BMP file)
+    # best suited to a display of the given resolution.  The bitmap
+    # designed for that resolution is chosen if there is one; otherwise, the
+    # device-independent bitmap if there is one; otherwise, the bitmap
+    # designed for the closest resolution.  Returns a dictionary as
+    # described in readbitmaparrayentries().
+    entries = readbitmaparrayentries(f, limits)
+    if len(entries) == 0:
+        raise ValueError("no bitmaps")
+    best = None
+    bestscore = 0
+    for e in entries:
+        if e["displaywidth"] == displaywidth and e["displayheight"] == displayheight:
+            return e
+        if e["displaywidth"] == 0 and e["displayheight"] == 0:
+            score = -1
+        else:
+            score = abs(e["displaywidth"] - displaywidth) + abs(
+                e["displayheight"] - displayheight
+            )
+        if best is None or score < bestscore:
+            best = e
+            bestscore = score
+    return best
+
+def writebitmaparray(f, images, raiseIfExists=False):
+    # Writes an OS/2 bitmap array file holding one or more bitmaps.
+    # 'images' is a list of dictionaries, each with the keys "image",
+    # "width", and "height", and optionally "displaywidth" and
+    # "displayheight" giving the display resolution the bitmap is designed
+    # for (default 0x0, meaning device-independent).
+    # Each bitmap is written in the OS/2 1.x format, with a color table if it
+    # uses 256 or fewer colors.
+    if (not images) or len(images) == 0:
+        raise ValueError
+    parts = []
+    for im in images:
+        header, bits = _bmpcoreheader(im["image"], im["width"], im["height"])
+        dw = im.get("displaywidth", 0)
+        dh = im.get("displayheight", 0)
+        if dw < 0 or dh < 0 or dw > 0xFFFF or dh > 0xFFFF:
+            raise ValueError
+        parts.append([header, bits, dw, dh])
+    # Bitmap array headers, file headers and color tables come first,
+    # then the pixel data of each bitmap
+    pos = 0
+    for p in parts:
+        pos += 28 + len(p[0])
+    out = bytearray()
+    for i in range(len(parts)):
+        header, bits, dw, dh = parts[i]
+        nextpos = 0 if i == len(parts) - 1 else len(out) + 28 + len(header)
+        out += b"BA" + struct.pack("<LLHH", 40, nextpos, dw, dh)
+        out += b"BM" + struct.pack("<LhhL", 14 + len(header), 0, 0, pos)
+        out += header
+        pos += len(bits)
+    for p in parts:
+        out += p[1]
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(bytes(out))
+    ff.close()
+
+# GIF files
+
+def _gifsubblocks(data, pos):
+    # Reads a sequence of GIF data sub-blocks starting at 'pos'; returns
+    # their concatenated contents and the position just after them
+    ret = []
+    while True:
+        if pos >= len(data):
+            raise ValueError
+        size = data[pos]
+        pos += 1
+        if size == 0:
+            return b"".join(ret), pos
+        if pos + size > len(data):
+            raise ValueError
+        ret.append(data[pos : pos + size])
+        pos += size
+
+def _giflzwdecode(data, mincodesize, count):
+    # Decodes GIF LZW-compressed data into a list of at most 'count' color
+    # indices
+    if mincodesize < 2 or mincodesize > 8:
+        raise ValueError
+    clear = 1 << mincodesize
+    end = clear + 1
+    ret = []
+    table = None
+    codesize = mincodesize + 1
+    prev = None
+    bitbuf = 0
+    bitcount = 0
+    pos = 0
+    while len(ret) < count:
+        while bitcount < codesize:
+            if pos >= len(data):
+                # data ended early; keep what was decoded
+                _logger.warning("GIF image data is truncated")
+                return ret
+            bitbuf |= data[pos] << bitcount
+            bitcount += 8
+            pos += 1
+        code = bitbuf & ((1 << codesize) - 1)
+        bitbuf >>= codesize
+        bitcount -= codesize
+        if code == clear or table is None:
+            table = [[i] for i in range(clear)] + [None, None]
+            codesize = mincodesize + 1
+            prev = None
+            if code == clear:
+                continue
+        if code == end:
+            break
+        if code < len(table) and table[code] is not None:
+            entry = table[code]
+            if prev is not None:
+                table.append(prev + [entry[0]])
+        elif code == len(table) and prev is not None:
+            entry = prev + [prev[0]]
+            table.append(entry)
+        else:
+            raise ValueError("invalid LZW code")
+        ret += entry
+        prev = entry
+        if len(table) == (1 << codesize) and codesize < 12:
+            codesize += 1
+    return ret[0:count]
+
+@_decoder
+def readgif(f, limits=None):
+    # Reads a GIF file.  For an animated GIF, only the first frame is read.
+    # Returns a dictionary with the keys "image", "width", and "height"
+    # (the size of the GIF's logical screen), "palette" (the color table
+    # used by the first frame), and "transparent" (the index in the palette of
+    # the transparent color, or None if there is none).  Parts of the logical
+    # screen not covered by the first frame, and transparent pixels, are given
+    # the background color.
+    data = _readfile(f)
+    if data[0:6] != b"GIF87a" and data[0:6] != b"GIF89a":
+        raise ValueError("not a GIF file")
+    width = _u16(data, 6)
+    height = _u16(data, 8)
+    flags = data[10]
+    bgindex = data[11]
+    pos = 13
+    globalpalette = None
+    if flags & 0x80:
+        n = 2 << (flags & 7)
+        if pos + n * 3 > len(data):
+            raise ValueError
+        globalpalette = [list(data[pos + i * 3 : pos + i * 3 + 3]) for i in range(n)]
+        pos += n * 3
+    _limits(limits).check(width, height)
+    transparent = None
+    while True:
+        if pos >= len(data):
+            raise ValueError("no image in file")
+        block = data[pos]
+        if block == 0x21:  # Extension
+            if pos + 1 >= len(data):
+                raise ValueError
+            label = data[pos + 1]
+            ext, pos = _gifsubblocks(data, pos + 2)
+            if label == 0xF9 and len(ext) >= 4 and (ext[0] & 1):
+                # Graphic control extension with a transparent color
+                transparent = ext[3]
+        elif block == 0x2C:  # Image descriptor
+            fx = _u16(data, pos + 1)
+            fy = _u16(data, pos + 3)
+            fw = _u16(data, pos + 5)
+            fh = _u16(data, pos + 7)
+            _limits(limits).check(fw, fh)
+            if pos + 9 >= len(data):
+                raise ValueError
+            fflags = data[pos + 9]
+            pos += 10
+            palette = globalpalette
+            if fflags & 0x80:
+                n = 2 << (fflags & 7)
+                if pos + n * 3 > len(data):
+                    raise ValueError
+                palette = [list(data[pos + i * 3 : pos + i * 3 + 3]) for i in range(n)]
+                pos += n * 3
+            if not palette:
+                raise ValueError("no color table")
+            if pos >= len(data):
+                raise ValueError
+            mincodesize = data[pos]
+            lzw, pos = _gifsubblocks(data, pos + 1)
+            indices = _giflzwdecode(lzw, mincodesize, fw * fh)
+            break
+        elif block == 0x3B:  # Trailer
+            raise ValueError("no image in file")
+        else:
+            raise ValueError
+    if globalpalette and bgindex < len(globalpalette) and bgindex != transparent:
+        bg = globalpalette[bgindex]
+    else:
+        bg = [0, 0, 0]
+    image = [bg[i % 3] for i in range(width * height * 3)]
+    # Order in which rows are stored in an interlaced image
+    if fflags & 0x40:
+        rows = (
+            list(range(0, fh, 8))
+            + list(range(4, fh, 8))
+            + list(range(2, fh, 4))
+            + list(range(1, fh, 2))
+        )
+    else:
+        rows = list(range(fh))
+    for i in range(len(indices)):
+        y = fy + rows[i // fw]
+        x = fx + i % fw
+        idx = indices[i]
+        if x >= width or y >= height or idx == transparent:
+            continue
+        if idx >= len(palette):
+            raise ValueError
+        c = palette[idx]
+        pos = (y * width + x) * 3
+        image[pos] = c[0]
+        image[pos + 1] = c[1]
+        image[pos + 2] = c[2]
+    return {
+        "image": image,
+        "width": width,
+        "height": height,
+        "palette": palette,
+        "transparent": transparent,
+    }
+
+def _giflzwencode(indices, mincodesize):
+    # Compresses color indices with GIF's variant of LZW
+    clear = 1 << mincodesize
+    end = clear + 1
+    out = bytearray()
+    bitbuf = 0
+    bitcount = 0
+
+    def emit(code, size):
+        nonlocal bitbuf, bitcount
+        bitbuf |= code << bitcount
+        bitcount += size
+        while bitcount >= 8:
+            out.append(bitbuf & 0xFF)
+            bitbuf >>= 8
+            bitcount -= 8
+
+    codesize = mincodesize + 1
+    table = {}
+    nextcode = end + 1
+    emit(clear, codesize)
+    prefix = None
+    for idx in indices:
+        if prefix is None:
+            prefix = idx
+            continue
+        key = (prefix, idx)
+        if key in table:
+            prefix = table[key]
+            continue
+        emit(prefix, codesize)
+        if nextcode < 4096:
+            table[key] = nextcode
+            nextcode += 1
+            if nextcode > (1 << codesize) and codesize < 12:
+                codesize += 1
+        else:
+            # The code table is full; start over
+            emit(clear, codesize)
+            table = {}
+            nextcode = end + 1
+            codesize = mincodesize + 1
+        prefix = idx
+    if prefix is not None:
+        emit(prefix, codesize)
+    emit(end, codesize)
+    if bitcount > 0:
+        out.append(bitbuf & 0xFF)
+    return bytes(out)
+
+def _gifcolortable(palette):
+    # Gets the size bits (as in a GIF's packed fields) and contents of a
+    # color table holding the given colors
+    sizebits = 0
+    while (2 << sizebits) < len(palette):
+        sizebits += 1
+    table = bytearray(3 * (2 << sizebits))
+    for i in range(len(palette)):
+        table[i * 3 : i * 3 + 3] = bytes(palette[i][0:3])
+    return sizebits, bytes(table)
+
+def _gifimage(indices, x0, y0, width, height, sizebits, localtable=None):
+    # Generates an image descriptor and image data for a GIF file
+    flags = 0
+    if localtable is not None:
+        flags = 0x80 | sizebits
+    out = b"\x2c" + struct.pack("<HHHHB", x0, y0, width, height, flags)
+    if localtable is not None:
+        out += localtable
+    mincodesize = max(2, sizebits + 1)
+    data = _giflzwencode(indices, mincodesize)
+    out += bytes([mincodesize])
+    for i in range(0, len(data), 255):
+        chunk = data[i : i + 255]
+        out += bytes([len(chunk)]) + chunk
+    return out + b"\0"
+
+def writegif(f, image, width, height, palette=None, raiseIfExists=False):
+    # Writes an image to a GIF file.  'palette' is as in _indexedimage():
+    # a list of up to 256 colors, such as the palette an image was
+    # dithered to, or None to use the image's own colors if it has 256
+    # or fewer.
+    if width <= 0 or height <= 0 or width > 0xFFFF or height > 0xFFFF:
+        raise ValueError
+    palette, indices = _indexedimage(image, width, height, palette)
+    sizebits, table = _gifcolortable(palette)
+    # Global color table, with the color resolution set to its size
+    flags = 0x80 | (sizebits << 4) | sizebits
+    out = b"GIF87a" + struct.pack("<HHBBB", width, height, flags, 0, 0) + table
+    out += _gifimage(indices, 0, 0, width, height, sizebits)
+    out += b"\x3b"
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(out)
+    ff.close()
+
+class AnimatedGifWriter:
+    # Writes an animated GIF file one frame at a time.  Call addframe() for
+    # each frame, in order, then close(); can also be used in a 'with'
+    # statement.  'palette' is a list of up to 256 colors used for all
+    # frames, or None to give each frame its own palette (see
+    # _indexedimage()).  'loops' is the number of times to play the
+    # animation, or 0 to loop forever.
+    def __init__(self, f, width, height, palette=None, loops=0, raiseIfExists=False):
+        if width <= 0 or height <= 0 or width > 0xFFFF or height > 0xFFFF:
+            raise ValueError
+        if loops < 0 or loops > 0xFFFF:
+            raise ValueError
+        self.width = width
+        self.height = height
+        self.palette = palette
+        self.frames = 0
+        flags = 0
+        table = b""
+        if palette is not None:
+            if len(palette) == 0 or len(palette) > 256:
+                raise ValueError
+            sizebits, table = _gifcolortable(palette)
+            flags = 0x80 | (sizebits << 4) | sizebits
+        self.fd = open(f, "xb" if raiseIfExists else "wb")
+        self.fd.write(b"GIF89a" + struct.pack("<HHBBB", width, height, flags, 0, 0))
+        self.fd.write(table)
+        # Application extension giving the number of loops
+        ext = b"\x21\xff\x0bNETSCAPE2.0\x03\x01" + struct.pack("<H", loops) + b"\0"
+        self.fd.write(ext)
+
+    def addframe(self, image, delay=100):
+        # Adds a frame, an image of the size given when creating this writer,
+        # shown for 'delay' milliseconds (rounded to hundredths of a second).
+        palette, indices = _indexedimage(image, self.width, self.height, self.palette)
+        sizebits, table = _gifcolortable(palette)
+        cs = max(0, min(0xFFFF, int(round(delay / 10))))
+        # Graphic control extension with the delay
+        self.fd.write(b"\x21\xf9\x04" + struct.pack("<BHB", 0, cs, 0) + b"\0")
+        local = None if self.palette is not None else table
+        w = self.width
+        h = self.height
+        self.fd.write(_gifimage(indices, 0, 0, w, h, sizebits, local))
+        self.frames += 1
+
+    def close(self):
+        if self.fd is None:
+            return
+        self.fd.write(b"\x3b")
+        self.fd.close()
+        self.fd = None
+        if self.frames == 0:
+            raise ValueError("no frames written")
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exctype, exc, tb):
+        if exctype is not None:
+            self.fd.close()
+            self.fd = None
+            return False
+        self.close()
+        return False
+
+def writeanimatedgif(f, animation, palette=None, loops=0, raiseIfExists=False):
+    # Writes an animation to an animated GIF file.  'animation' is a
+    # dictionary with the keys "frames" (a list of images), "width",
+    # "height", and "delays" (how long each frame is shown, in milliseconds),
+    # such as one made with desktopwallpaper.composeanimation().
+    with AnimatedGifWriter(
+        f, animation["width"], animation["height"], palette, loops, raiseIfExists
+    ) as writer:
+        for i in range(len(animation["frames"])):
+            writer.addframe(animation["frames"][i], animation["delays"][i])
+
+# Truevision TGA files
+
+def _tgacolor(data, pos, bits):
+    # Reads a color stored in a TGA file with the given number of bits
+    if bits == 24 or bits == 32:
+        if pos + 3 > len(data):
+            raise ValueError
+        return [data[pos + 2], data[pos + 1], data[pos]]
+    if bits == 15 or bits == 16:
+        v = _u16(data, pos)
+        return [
+            ((v >> 10) & 31) * 255 // 31,
+            ((v >> 5) & 31) * 255 // 31,
+            (v & 31) * 255 // 31,
+        ]
+    if bits == 8:
+        if pos >= len(data):
+            raise ValueError
+        return [data[pos], data[pos], data[pos]]
+    raise ValueError("unsupported bit count")
+
+@_decoder
+def readtga(f, limits=None):
+    # Reads a Truevision TGA file: color-mapped, true-color (15, 16, 24, or
+    # 32 bits per pixel), or grayscale, either uncompressed or run-length
+    # encoded.  Alpha channels are ignored.
+    # Returns a dictionary with the keys "image", "width", "height", and
+    # "orientation" (see "Orientation"), and the key "palette" if the image
+    # is color-mapped.
+    data = _readfile(f)
+    if len(data) < 18:
+        raise ValueError
+    idlength = data[0]
+    maptype = data[1]
+    imagetype = data[2]
+    mapstart = _u16(data, 3)
+    maplength = _u16(data, 5)
+    mapbits = data[7]
+    width = _u16(data, 12)
+    height = _u16(data, 14)
+    bits = data[16]
+    descriptor = data[17]
+    if imagetype not in [1, 2, 3, 9, 10, 11]:
+        raise ValueError("unsupported image type")
+    _limits(limits).check(width, height)
+    pos = 18 + idlength
+    palette = None
+    if maptype == 1:
+        entrysize = (mapbits + 7) // 8
+        palette = [[0, 0, 0] for i in range(mapstart)]
+        for i in range(maplength):
+            palette.append(_tgacolor(data, pos + i * entrysize, mapbits))
+        pos += maplength * entrysize
+    colormapped = imagetype == 1 or imagetype == 9
+    if colormapped and (not palette or bits != 8):
+        raise ValueError
+    if imagetype == 3 or imagetype == 11:
+        if bits != 8:
+            raise ValueError
+    elif not colormapped and bits not in [15, 16, 24, 32]:
+        raise ValueError("unsupported bit count")
+    bpp = (bits + 7) // 8
+    # Raw pixel data, after decompressing if necessary
+    count = width * height
+    if imagetype >= 9:
+        pixels = bytearray()
+        while len(pixels) < count * bpp:
+            if pos >= len(data):
+                raise ValueError
+            header = data[pos]
+            n = (header & 0x7F) + 1
+            pos += 1
+            if header & 0x80:
+                pixels += data[pos : pos + bpp] * n
+                pos += bpp
+            else:
+                pixels += data[pos : pos + n * bpp]
+                pos += n * bpp
+            if pos > len(data):
+                raise ValueError
+        pixels = bytes(pixels[0 : count * bpp])
+    else:
+        pixels = data[pos : pos + count * bpp]
+        if len(pixels) < count * bpp:
+            raise ValueError
+    if colormapped:
+        rgb = _applypalette(pixels, palette)
+    else:
+        rgb = []
+        for i in range(count):
+            rgb += _tgacolor(pixels, i * bpp, bits)
+    # Rows are stored bottom first unless the descriptor says otherwise
+    topdown = (descriptor & 0x20) != 0
+    righttoleft = (descriptor & 0x10) != 0
+    if topdown:
+        orientation = 2 if righttoleft else 1
+    else:
+        orientation = 3 if righttoleft else 4
+    ret = orientimage(rgb, width, height, orientation)
+    ret["orientation"] = orientation
+    if colormapped:
+        ret["palette"] = palette
+    return ret
+
+def _tgarle(row, bpp):
+    # Run-length encodes one row of TGA pixel data; packets don't cross rows
+    px = [row[i : i + bpp] for i in range(0, len(row), bpp)]
+    out = bytearray()
+    i = 0
+    while i < len(px):
+        n = 1
+        while n < 128 and i + n < len(px) and px[i + n] == px[i]:
+            n += 1
+        if n >= 2:
+            out.append(0x80 | (n - 1))
+            out += px[i]
+            i += n
+            continue
+        # Raw packet, up to the next run of two or more equal pixels
+        start = i
+        i += 1
+        while i < len(px) and i - start < 128:
+            if i + 1 < len(px) and px[i] == px[i + 1]:
+                break
+            i += 1
+        out.append(i - start - 1)
+        out += row[start * bpp : i * bpp]
+    return out
+
+def writetga(f, image, width, height, raiseIfExists=False, rle=False):
+    # Writes an image to a 24-bit true-color Truevision TGA file, with
+    # rows stored bottom first as most older paint programs expect.
+    # 'rle' is True to compress the image with run-length encoding
+    # (image type 10) rather than leave it uncompressed (image type 2).
+    if width <= 0 or height <= 0 or width > 0xFFFF or height > 0xFFFF:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(
+        struct.pack(
+            "<BBBHHBHHHHBB", 0, 0, 10 if rle else 2, 0, 0, 0, 0, 0, width, height, 24, 0
+        )
+    )
+    for y in range(height - 1, -1, -1):
+        row = bytearray(width * 3)
+        yp = y * width * 3
+        # TGA stores colors in blue, green, red order
+        row[0::3] = bytes(image[yp + 2 : yp + width * 3 : 3])
+        row[1::3] = bytes(image[yp + 1 : yp + width * 3 : 3])
+        row[2::3] = bytes(image[yp : yp + width * 3 : 3])
+        ff.write(_tgarle(row, 3) if rle else row)
+    # TGA 2.0 footer, with no extension area or developer directory
+    ff.write(struct.pack("<LL", 0, 0) + b"TRUEVISION-XFILE.\x00")
+    ff.close()
+
+# Sun Raster files
+
+def _be32(data, pos):
+    if pos < 0 or pos + 4 > len(data):
+        raise ValueError
+    return struct.unpack(">L", data[pos : pos + 4])[0]
+
+@_decoder
+def readsunraster(f, limits=None):
+    # Reads a Sun Raster file with 1, 8, 24, or 32 bits per pixel, either
+    # uncompressed or run-length encoded.
+    # Returns a dictionary with the keys "image", "width", and "height", and
+    # the key "palette" if the image has a color map.
+    data = _readfile(f)
+    if _be32(data, 0) != 0x59A66A95:
+        raise ValueError("not a Sun Raster file")
+    width = _be32(data, 4)
+    height = _be32(data, 8)
+    depth = _be32(data, 12)
+    rastype = _be32(data, 20)
+    maptype = _be32(data, 24)
+    maplength = _be32(data, 28)
+    _limits(limits).check(width, height)
+    if depth not in [1, 8, 24, 32] or rastype not in [0, 1, 2, 3]:
+        raise ValueError("unsupported Sun Raster file")
+    pos = 32
+    palette = None
+    if maptype == 1 and maplength > 0:
+        # Map is all red components, then all green, then all blue
+        n = maplength // 3
+        if pos + n * 3 > len(data):
+            raise ValueError
+        palette = [
+            [data[pos + i], data[pos + n + i], data[pos + n * 2 + i]] for i in range(n)
+        ]
+    elif maptype != 0 and maptype != 1:
+        raise ValueError("unsupported color map")
+    pos += maplength
+    # Rows are padded to a multiple of 16 bits
+    stride = ((width * depth + 15) // 16) * 2
+    if rastype == 2:
+        raw = bytearray()
+        while len(raw) < stride * height and pos < len(data):
+            b = data[pos]
+            if b != 0x80:
+                raw.append(b)
+                pos += 1
+            elif pos + 1 < len(data) and data[pos + 1] == 0:
+                raw.append(0x80)
+                pos += 2
+            elif pos + 2 < len(data):
+                raw += bytes([data[pos + 2]]) * (data[pos + 1] + 1)
+                pos += 3
+            else:
+                raise ValueError
+        raw = bytes(raw)
+    else:
+        raw = data[pos : pos + stride * height]
+    if len(raw) < stride * height:
+        raise ValueError
+    image = [0 for i in range(width * height * 3)]
+    for y in range(height):
+        rowpos = y * stride
+        for x in range(width):
+            if depth == 1:
+                bit = (raw[rowpos + (x >> 3)] >> (7 - (x & 7))) & 1
+                if palette:
+                    c = palette[bit] if bit < len(palette) else [0, 0, 0]
+                else:
+                    c = [0, 0, 0] if bit else [255, 255, 255]
+            elif depth == 8:
+                v = raw[rowpos + x]
+                if palette:
+                    if v >= len(palette):
+                        raise ValueError
+                    c = palette[v]
+                else:
+                    c = [v, v, v]
+            else:
+                p = rowpos + x * (depth // 8) + (1 if depth == 32 else 0)
+                if rastype == 3:
+                    c = [raw[p], raw[p + 1], raw[p + 2]]
+                else:
+                    c = [raw[p + 2], raw[p + 1], raw[p]]
+            dp = (y * width + x) * 3
+            image[dp] = c[0]
+            image[dp + 1] = c[1]
+            image[dp + 2] = c[2]
+    ret = {"image": image, "width": width, "height": height}
+    if palette:
+        ret["palette"] = palette
+    return ret
+
+def _sunrle(raw):
+    # Compresses Sun Raster image data with byte-oriented run-length encoding
+    out = bytearray()
+    i = 0
+    while i < len(raw):
+        b = raw[i]
+        n = 1
+        while n < 256 and i + n < len(raw) and raw[i + n] == b:
+            n += 1
+        if n >= 3 or (b == 0x80 and n >= 2):
+            out += bytes([0x80, n - 1, b])
+        elif b == 0x80:
+            out += b"\x80\x00"
+        else:
+            out += bytes([b]) * n
+        i += n
+    return bytes(out)
+
+def writesunraster(f, image, width, height, raiseIfExists=False, rle=False):
+    # Writes an image to a Sun Raster file, with 8 bits per pixel and a color
+    # map if the image has 256 or fewer colors, and 24 bits per pixel
+    # otherwise.  'rle' is True to compress the image with run-length
+    # encoding (raster type 2) rather than leave it uncompressed (type 1).
+    if width <= 0 or height <= 0 or width > 0xFFFFFFFF or height > 0xFFFFFFFF:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    colors = _imagepalette(image, width, height)
+    depth = 24 if colors is None else 8
+    # Rows are padded to a multiple of 16 bits
+    stride = ((width * depth + 15) // 16) * 2
+    raw = bytearray(stride * height)
+    for y in range(height):
+        yp = y * width * 3
+        for x in range(width):
+            pos = yp + x * 3
+            if colors is None:
+                # Colors are stored in blue, green, red order
+                raw[y * stride + x * 3] = image[pos + 2]
+                raw[y * stride + x * 3 + 1] = image[pos + 1]
+                raw[y * stride + x * 3 + 2] = image[pos]
+            else:
+                c = image[pos] | (image[pos + 1] << 8) | (image[pos + 2] << 16)
+                raw[y * stride + x] = colors[c]
+    colormap = b""
+    if colors is not None:
+        # Map is all red components, then all green, then all blue
+        cmap = bytearray(len(colors) * 3)
+        for c, i in colors.items():
+            cmap[i] = c & 0xFF
+            cmap[len(colors) + i] = (c >> 8) & 0xFF
+            cmap[len(colors) * 2 + i] = c >> 16
+        colormap = bytes(cmap)
+    data = _sunrle(raw) if rle else bytes(raw)
+    header = struct.pack(
+        ">LLLLLLLL",
+        0x59A66A95,
+        width,
+        height,
+        depth,
+        len(data),
+        2 if rle else 1,
+        1 if colors is not None else 0,
+        len(colormap),
+    )
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(header + colormap + data)
+    ff.close()
+
+# farbfeld files
+
+def writefarbfeld(f, image, width, height, raiseIfExists=False):
+    # Writes an image in the farbfeld format, a simple format with 16 bits
+    # per color component and an alpha channel (here fully opaque), used by
+    # the suckless image tools.  'f' is either a file name or a binary
+    # file object (such as sys.stdout.buffer, to pipe the image into
+    # another program); rows are written as they are converted.
+    if width <= 0 or height <= 0 or width > 0xFFFFFFFF or height > 0xFFFFFFFF:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    isfile = hasattr(f, "write")
+    ff = f if isfile else open(f, "xb" if raiseIfExists else "wb")
+    ff.write(b"farbfeld" + struct.pack(">LL", width, height))
+    for y in range(height):
+        row = bytearray(width * 8)
+        yp = y * width * 3
+        for x in range(width):
+            # Each 8-bit component v becomes the 16-bit value v * 257
+            for i in range(3):
+                row[x * 8 + i * 2] = row[x * 8 + i * 2 + 1] = image[yp + x * 3 + i]
+            row[x * 8 + 6] = row[x * 8 + 7] = 0xFF
+        ff.write(row)
+    if not isfile:
+        ff.close()
+
+# TIFF files
+
+def writetiff(f, image, width, height, raiseIfExists=False):
+    # Writes an image to a baseline TIFF file: uncompressed 24-bit RGB in
+    # strips of about 8 KiB each, which even old scanning and paint software
+    # can read.
+    if width <= 0 or height <= 0 or width > 0xFFFFFFFF or height > 0xFFFFFFFF:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    rowsperstrip = max(1, 8192 // (width * 3))
+    strips = (height + rowsperstrip - 1) // rowsperstrip
+    count = 12  # number of IFD entries
+    # Data pointed to by the IFD follows it, then the image data
+    bitspos = 8 + 2 + count * 12 + 4
+    respos = bitspos + 6
+    offsetspos = respos + 16
+    countspos = offsetspos + (strips * 4 if strips > 1 else 0)
+    datapos = countspos + (strips * 4 if strips > 1 else 0)
+    stripoffsets = []
+    stripcounts = []
+    for i in range(strips):
+        rows = min(rowsperstrip, height - i * rowsperstrip)
+        stripoffsets.append(datapos + i * rowsperstrip * width * 3)
+        stripcounts.append(rows * width * 3)
+    if datapos + width * height * 3 > 0xFFFFFFFF:
+        raise ValueError
+    # A single strip's offset and byte count are stored in the IFD itself
+    offsets = stripoffsets[0] if strips == 1 else offsetspos
+    counts = stripcounts[0] if strips == 1 else countspos
+    entries = [
+        [256, 4, 1, width],  # ImageWidth
+        [257, 4, 1, height],  # ImageLength
+        [258, 3, 3, bitspos],  # BitsPerSample
+        [259, 3, 1, 1],  # Compression: none
+        [262, 3, 1, 2],  # PhotometricInterpretation: RGB
+        [273, 4, strips, offsets],  # StripOffsets
+        [277, 3, 1, 3],  # SamplesPerPixel
+        [278, 4, 1, rowsperstrip],  # RowsPerStrip
+        [279, 4, strips, counts],  # StripByteCounts
+        [282, 5, 1, respos],  # XResolution
+        [283, 5, 1, respos + 8],  # YResolution
+        [296, 3, 1, 2],  # ResolutionUnit: inch
+    ]
+    out = b"II*\0" + struct.pack("<LH", 8, count)
+    for tag, kind, n, value in entries:
+        if kind == 3 and n == 1:
+            out += struct.pack("<HHLHH", tag, kind, n, value, 0)
+        else:
+            out += struct.pack("<HHLL", tag, kind, n, value)
+    out += struct.pack("<L", 0)  # no more IFDs
+    out += struct.pack("<HHH", 8, 8, 8)
+    out += struct.pack("<LLLL", 72, 1, 72, 1)  # 72 pixels per inch
+    if strips > 1:
+        out += struct.pack("<%dL" % (strips), *stripoffsets)
+        out += struct.pack("<%dL" % (strips), *stripcounts)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(out)
+    for y in range(height):
+        ff.write(bytes(image[y * width * 3 : (y + 1) * width * 3]))
+    ff.close()
+
+# X11 pixmap and bitmap files
+
+def _reducedimage(image, width, height, palette):
+    # Dithers a copy of an image to 'palette', or if 'palette' is None and
+    # the image has more than 256 colors, to the 216 "web safe" colors;
+    # otherwise returns the image unchanged
+    import desktopwallpaper
+
+    if palette is None:
+        if _imagepalette(image, width, height) is not None:
+            return image, None
+        palette = desktopwallpaper.websafecolors()
+    image = [x for x in image]
+    desktopwallpaper.floydSteinbergDither(image, width, height, palette)
+    return image, palette
+
+def writexpm(f, image, width, height, palette=None, raiseIfExists=False, name="image"):
+    # Writes an image to an XPM (X PixMap) file, a text format that classic
+    # X window managers and tools such as xsetroot and xpmroot can set as the
+    # desktop background.  Images with more than 256 colors are reduced to
+    # 216 colors with Floyd-Steinberg dithering.
+    # 'palette' is a list of up to 256 colors to dither the image to, or
+    # None to use the image's own colors if it has 256 or fewer.
+    # 'name' is the name of the C array the file declares.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if not name.isidentifier() or not name.isascii():
+        raise ValueError
+    image, palette = _reducedimage(image, width, height, palette)
+    palette, indices = _indexedimage(image, width, height, palette)
+    # Printable ASCII characters other than the quotation mark and backslash
+    chars = [chr(c) for c in range(0x20, 0x7F) if c != 0x22 and c != 0x5C]
+    cpp = 1 if len(palette) <= len(chars) else 2
+    if cpp == 1:
+        codes = chars[0 : len(palette)]
+    else:
+        codes = [chars[i // len(chars)] + chars[i % len(chars)] for i in range(256)]
+    lines = ['"%d %d %d %d"' % (width, height, len(palette), cpp)]
+    for i in range(len(palette)):
+        c = palette[i]
+        lines.append('"%s c #%02X%02X%02X"' % (codes[i], c[0], c[1], c[2]))
+    for y in range(height):
+        row = indices[y * width : (y + 1) * width]
+        lines.append('"' + "".join(codes[i] for i in row) + '"')
+    text = "/* XPM */\nstatic char *%s[] = {\n" % (name)
+    text += ",\n".join(lines) + "\n};\n"
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(text.encode("ascii"))
+    ff.close()
+
+def monochromebits(image, width, height, method="ordered", threshold=128):
+    # Reduces an image to one bit per pixel, for example for writing to an
+    # XBM file.  Returns a list with one item per pixel, which is 1 if the
+    # pixel is dark (drawn in the foreground color) and 0 otherwise.
+    # 'method' is "threshold" (pixels with a gray tone less than 'threshold'
+    # become dark), "ordered" (ordered dithering to black and white, which
+    # keeps tileable images tileable; see desktopwallpaper.dithertograyimage()),
+    # or "floydsteinberg" (error diffusion; see
+    # desktopwallpaper.floydSteinbergDither()).
+    import desktopwallpaper
+
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    gray = desktopwallpaper.graymap([x for x in image], width, height)
+    if method == "ordered":
+        desktopwallpaper.dithertograyimage(gray, width, height, [0, 255])
+    elif method == "floydsteinberg":
+        bw = [[0, 0, 0], [255, 255, 255]]
+        desktopwallpaper.floydSteinbergDither(gray, width, height, bw)
+    elif method != "threshold":
+        raise ValueError
+    return [1 if gray[i * 3] < threshold else 0 for i in range(width * height)]
+
+def writexbm(f, bits, width, height, raiseIfExists=False, name="image"):
+    # Writes a monochrome image to an XBM (X BitMap) file, a C source
+    # format long used for X11 root window patterns (as with
+    # "xsetroot -bitmap").
+    # 'bits' is a list with one item per pixel, which is 1 for the
+    # foreground color and 0 for the background color, such as one returned
+    # by monochromebits().
+    # 'name' is the prefix of the names the file declares.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(bits) != width * height:
+        raise ValueError
+    if not name.isidentifier() or not name.isascii():
+        raise ValueError
+    # Each row is padded to a whole number of bytes, with the leftmost pixel
+    # in the least significant bit
+    stride = (width + 7) // 8
+    data = bytearray(stride * height)
+    for y in range(height):
+        for x in range(width):
+            if bits[y * width + x]:
+                data[y * stride + (x >> 3)] |= 1 << (x & 7)
+    text = "#define %s_width %d\n#define %s_height %d\n" % (name, width, name, height)
+    text += "static unsigned char %s_bits[] = {" % (name)
+    for i in range(len(data)):
+        text += "\n   " if i % 12 == 0 else " "
+        text += "0x%02x" % (data[i]) + ("," if i + 1 < len(data) else "")
+    text += " };\n"
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(text.encode("ascii"))
+    ff.close()
+
+# MacPaint files
+
+def _packbits(data):
+    # Compresses data with the PackBits scheme used on the Macintosh
+    out = bytearray()
+    i = 0
+    n = len(data)
+    while i < n:
+        run = 1
+        while i + run < n and run < 128 and data[i + run] == data[i]:
+            run += 1
+        if run >= 2:
+            out.append(257 - run)
+            out.append(data[i])
+            i += run
+        else:
+            start = i
+            while i < n and i - start < 128:
+                if i + 1 < n and data[i] == data[i + 1]:
+                    break
+                i += 1
+            out.append(i - start - 1)
+            out += bytes(data[start:i])
+    return bytes(out)
+
+def writemacpaint(f, image, width, height, raiseIfExists=False, method="ordered"):
+    # Writes an image to a MacPaint file, a black-and-white image 576 pixels
+    # wide and 720 pixels tall.  The image is reduced to one bit per pixel
+    # (see monochromebits(); 'method' is as given there), then tiled to
+    # fill the page or cropped to fit it.  The file has no MacBinary header.
+    bits = monochromebits(image, width, height, method)
+    # Version 0 header: use MacPaint's default fill patterns
+    out = bytearray(512)
+    for y in range(720):
+        row = bytearray(72)
+        yp = (y % height) * width
+        for x in range(576):
+            if bits[yp + x % width]:
+                row[x >> 3] |= 0x80 >> (x & 7)
+        # Each row is compressed separately
+        out += _packbits(row)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(out)
+    ff.close()
+
+# Icon files
+
+def _iconimage(icon):
+    # Generates the data of one image in an icon or cursor file: a
+    # BITMAPINFOHEADER, color table, color ("XOR") bitmap, and
+    # transparency ("AND") mask.
+    width = icon["width"]
+    height = icon["height"]
+    if width <= 0 or height <= 0 or width > 256 or height > 256:
+        raise ValueError
+    if len(icon["image"]) != width * height * 3:
+        raise ValueError
+    mask = icon.get("mask", None)
+    if mask and len(mask) != width * height:
+        raise ValueError
+    image = [x for x in icon["image"]]
+    if mask:
+        # Transparent pixels must be black in the color bitmap
+        for i in range(width * height):
+            if mask[i]:
+                image[i * 3] = image[i * 3 + 1] = image[i * 3 + 2] = 0
+    colors = _imagepalette(image, width, height)
+    bitcount, bits = _bmpbits(image, width, height, colors)
+    maskstride = ((width + 31) // 32) * 4
+    maskbits = bytearray(maskstride * height)
+    if mask:
+        for y in range(height):
+            rowpos = (height - 1 - y) * maskstride
+            for x in range(width):
+                if mask[y * width + x]:
+                    maskbits[rowpos + (x >> 3)] |= 0x80 >> (x & 7)
+    out = struct.pack(
+        "<LllHHLLllLL",
+        40,
+        width,
+        height * 2,
+        1,
+        bitcount,
+        0,
+        len(bits) + len(maskbits),
+        0,
+        0,
+        0,
+        0,
+    )
+    if colors is not None:
+        table = bytearray(4 << bitcount)
+        for c, i in colors.items():
+            table[i * 4] = (c >> 16) & 0xFF
+            table[i * 4 + 1] = (c >> 8) & 0xFF
+            table[i * 4 + 2] = c & 0xFF
+        out += bytes(table)
+    return (
+        out + bits + bytes(maskbits),
+        bitcount,
+        0 if colors is None or bitcount >= 8 else (1 << bitcount),
+    )
+
+def _icodata(icons, hotspots=None):
+    # Generates the contents of an icon file, or a cursor file if
+    # 'hotspots' is a list of the [x, y] hot spots of each image
+    if (not icons) or len(icons) == 0 or len(icons) > 0xFFFF:
+        raise ValueError
+    if hotspots and len(hotspots) != len(icons):
+        raise ValueError
+    images = [_iconimage(icon) for icon in icons]
+    out = struct.pack("<HHH", 0, 2 if hotspots else 1, len(icons))
+    pos = 6 + 16 * len(icons)
+    for i in range(len(icons)):
+        data, bitcount, numcolors = images[i]
+        out += struct.pack(
+            "<BBBBHHLL",
+            icons[i]["width"] & 0xFF,  # 0 means 256
+            icons[i]["height"] & 0xFF,
+            numcolors,
+            0,
+            hotspots[i][0] if hotspots else 1,
+            hotspots[i][1] if hotspots else bitcount,
+            len(data),
+            pos,
+        )
+        pos += len(data)
+    for data, bitcount, numcolors in images:
+        out += data
+    return out
+
+def writeico(f, icons, raiseIfExists=False):
+    # Writes a Windows icon (.ico) file holding one or more images.
+    # 'icons' is a list of dictionaries, each with the keys "image", "width",
+    # and "height", and optionally "mask", a list with one item per pixel
+    # that is 1 if the pixel is transparent and 0 otherwise (see
+    # desktopwallpaper.deriveicon()).  Each image's width and height must be
+    # 256 or less.  Images with 256 or fewer colors are written with a
+    # color table.
+    data = _icodata(icons)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(data)
+    ff.close()
+
+def _icondecode(data, limits=None):
+    # Decodes one image in an icon or cursor file; see _iconimage()
+    if data[0:8] == b"\x89PNG\r\n\x1a\n":
+        raise ValueError("PNG-compressed icons are not supported")
+    hdrsize = _u32(data, 0)
+    if hdrsize < 40:
+        raise ValueError
+    bitcount = _u16(data, 14)
+    # The header's height covers both the color bitmap and the mask
+    height = _s32(data, 8) // 2
+    width = _s32(data, 4)
+    _limits(limits).check(width, height)
+    colorsused = _u32(data, 32)
+    numcolors = 0
+    if bitcount <= 8:
+        numcolors = colorsused if colorsused > 0 else (1 << bitcount)
+    bitspos = hdrsize + numcolors * 4
+    header = bytearray(data[0:hdrsize])
+    header[8:12] = struct.pack("<l", height)
+    icon = _bmpdecode(bytes(header) + data[hdrsize:], 0, bitspos, limits)
+    stride = ((width * bitcount + 31) // 32) * 4
+    maskpos = bitspos + stride * height
+    maskstride = ((width + 31) // 32) * 4
+    mask = [0 for i in range(width * height)]
+    if maskpos + maskstride * height <= len(data):
+        for y in range(height):
+            rowpos = maskpos + (height - 1 - y) * maskstride
+            for x in range(width):
+                if data[rowpos + (x >> 3)] & (0x80 >> (x & 7)):
+                    mask[y * width + x] = 1
+    else:
+        _logger.warning("icon has no AND mask; treating it as opaque")
+    icon["mask"] = mask
+    return icon
+
+def _icoentries(data, limits=None):
+    # Decodes the images in the contents of an icon or cursor file
+    if len(data) < 6 or _u16(data, 0) != 0 or _u16(data, 2) not in [1, 2]:
+        raise ValueError("not an icon or cursor file")
+    iscursor = _u16(data, 2) == 2
+    ret = []
+    for i in range(_u16(data, 4)):
+        entry = 6 + i * 16
+        size = _u32(data, entry + 8)
+        pos = _u32(data, entry + 12)
+        if pos + size > len(data):
+            raise ValueError
+        icon = _icondecode(data[pos : pos + size], limits)
+        if iscursor:
+            icon["hotspot"] = [_u16(data, entry + 4), _u16(data, entry + 6)]
+        ret.append(icon)
+    return ret
+
+@_decoder
+def readico(f, limits=None):
+    # Reads a Windows icon (.ico) or cursor (.cur) file.  Returns a list of
+    # dictionaries, one for each image in the file, each with the keys
+    # "image", "width", "height", and "mask" (as in writeico()), the key
+    # "palette" if the image has a color table, and, for cursor files, the
+    # key "hotspot", an [x, y] array giving the cursor's hot spot.  Icons
+    # stored in the PNG format are not supported.
+    return _icoentries(_readfile(f), limits)
+
+def _icnspackbits(channel):
+    # Compresses one color channel of an image in an Apple icon file
+    out = bytearray()
+    i = 0
+    n = len(channel)
+    while i < n:
+        run = 1
+        while i + run < n and run < 130 and channel[i + run] == channel[i]:
+            run += 1
+        if run >= 3:
+            out.append(run + 125)
+            out.append(channel[i])
+            i += run
+        else:
+            start = i
+            while i < n and i - start < 128:
+                if (
+                    i + 2 < n
+                    and channel[i] == channel[i + 1]
+                    and channel[i] == channel[i + 2]
+                ):
+                    break
+                i += 1
+            out.append(i - start - 1)
+            out += bytes(channel[start:i])
+    return bytes(out)
+
+def writeicns(f, icons, raiseIfExists=False):
+    # Writes an Apple icon (.icns) file holding one or more images, in
+    # the format used since Mac OS 8.5.  'icons' is as in writeico(), except
+    # that the width and height of each image must be the same and must be
+    # 16, 32, 48, or 128.
+    types = {16: [b"is32", b"s8mk"], 32: [b"il32", b"l8mk"], 48: [b"ih32", b"h8mk"]}
+    types[128] = [b"it32", b"t8mk"]
+    if (not icons) or len(icons) == 0:
+        raise ValueError
+    out = bytearray()
+    for icon in icons:
+        size = icon["width"]
+        if icon["height"] != size or size not in types:
+            raise ValueError
+        image = icon["image"]
+        if len(image) != size * size * 3:
+            raise ValueError
+        mask = icon.get("mask", None)
+        data = b"\0\0\0\0" if size == 128 else b""
+        for ch in range(3):
+            data += _icnspackbits([image[i] for i in range(ch, size * size * 3, 3)])
+        out += types[size][0] + struct.pack(">L", len(data) + 8) + data
+        alpha = bytes(
+            [0 if mask and mask[i] else 255 for i in range(size * size)]
+        )
+        out += types[size][1] + struct.pack(">L", len(alpha) + 8) + alpha
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(b"icns" + struct.pack(">L", len(out) + 8) + bytes(out))
+    ff.close()
+
+def _riffchunk(fourcc, data):
+    # Generates a RIFF chunk, padded to an even size
+    pad = b"\0" if len(data) % 2 == 1 else b""
+    return fourcc + struct.pack("<L", len(data)) + data + pad
+
+def writeani(f, frames, rate=6, raiseIfExists=False):
+    # Writes a Windows animated cursor (.ani) file.
+    # 'frames' is a list of dictionaries, one for each frame, each with
+    # the keys "image", "width", and "height" (usually 32 each), and optionally
+    # "mask" (see writeico()) and "hotspot", an [x, y] array giving the
+    # cursor's hot spot (default [0, 0]).
+    # 'rate' is the time each frame is shown, in jiffies (sixtieths of a
+    # second), or a list with one such time for each frame.
+    if (not frames) or len(frames) == 0:
+        raise ValueError
+    rates = rate if isinstance(rate, list) else None
+    if rates is not None and len(rates) != len(frames):
+        raise ValueError
+    if rates is None and (rate <= 0 or int(rate) != rate):
+        raise ValueError
+    for r in rates if rates else []:
+        if r <= 0 or int(r) != r:
+            raise ValueError
+    anih = struct.pack(
+        "<LLLLLLLLL",
+        36,
+        len(frames),  # number of frames
+        len(frames),  # number of steps
+        0,
+        0,
+        0,
+        0,
+        rates[0] if rates else rate,
+        1,  # frames are stored as cursor files
+    )
+    body = b"ACON" + _riffchunk(b"anih", anih)
+    if rates:
+        body += _riffchunk(b"rate", struct.pack("<%dL" % (len(rates)), *rates))
+    fram = b"fram"
+    for frame in frames:
+        hotspot = frame.get("hotspot", [0, 0])
+        if (
+            hotspot[0] < 0
+            or hotspot[1] < 0
+            or hotspot[0] >= frame["width"]
+            or hotspot[1] >= frame["height"]
+        ):
+            raise ValueError
+        fram += _riffchunk(b"icon", _icodata([frame], [hotspot]))
+    body += _riffchunk(b"LIST", fram)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(_riffchunk(b"RIFF", body))
+    ff.close()
+
+def _riffchunks(data, pos, end):
+    # Reads the chunks of a RIFF file from 'pos' to 'end', as a list of
+    # [fourcc, start of contents, size of contents] arrays
+    ret = []
+    while pos + 8 <= end:
+        size = _u32(data, pos + 4)
+        if pos + 8 + size > end:
+            raise ValueError
+        ret.append([data[pos : pos + 4], pos + 8, size])
+        pos += 8 + size + (size & 1)
+    return ret
+
+@_decoder
+def readani(f, limits=None):
+    # Reads a Windows animated cursor (.ani) file.  Returns a list of
+    # dictionaries, one for each step of the animation in the order shown,
+    # each with the keys of a cursor image as returned by readico() and
+    # the key "rate", the time the step is shown, in jiffies (sixtieths of
+    # a second).
+    data = _readfile(f)
+    if data[0:4] != b"RIFF" or data[8:12] != b"ACON":
+        raise ValueError("not an animated cursor file")
+    end = min(len(data), 8 + _u32(data, 4))
+    anih = None
+    rates = None
+    seq = None
+    frames = []
+    for fourcc, pos, size in _riffchunks(data, 12, end):
+        if fourcc == b"anih" and size >= 36:
+            anih = struct.unpack("<LLLLLLLLL", data[pos : pos + 36])
+        elif fourcc == b"rate":
+            rates = [_u32(data, pos + i * 4) for i in range(size // 4)]
+        elif fourcc == b"seq ":
+            seq = [_u32(data, pos + i * 4) for i in range(size // 4)]
+        elif fourcc == b"LIST" and data[pos : pos + 4] == b"fram":
+            for ffourcc, fpos, fsize in _riffchunks(data, pos + 4, pos + size):
+                if ffourcc == b"icon":
+                    frames.append(_icoentries(data[fpos : fpos + fsize], limits)[0])
+    if not anih:
+        raise ValueError
+    if (anih[8] & 1) == 0:
+        raise ValueError("raw bitmap frames are not supported")
+    steps = anih[2]
+    if not seq:
+        # Without a sequence, each step shows the frame with the same number
+        if steps > len(frames):
+            raise ValueError
+        seq = list(range(steps))
+    ret = []
+    for i in range(min(steps, len(seq))):
+        if seq[i] >= len(frames):
+            raise ValueError
+        frame = dict(frames[seq[i]])
+        frame["rate"] = rates[i] if rates and i < len(rates) else anih[7]
+        ret.append(frame)
+    return ret
+
+def _bmpinfoheader(image, width, height, truecolor=False):
+    # Generates a Windows bitmap information header (BITMAPINFOHEADER),
+    # color table, and pixel data for an image; uses a color table
+    # if the image has 256 or fewer colors, unless 'truecolor' is True.
+    if width <= 0 or height <= 0:
+        raise ValueError
+    if len(image) != width * height * 3:
+        raise ValueError
+    colors = None if truecolor else _imagepalette(image, width, height)
+    bitcount, bits = _bmpbits(image, width, height, colors)
+    header = struct.pack(
+        "<LllHHLLllLL",
+        40,
+        width,
+        height,
+        1,
+        bitcount,
+        0,
+        len(bits),
+        2835,  # 72 pixels per inch
+        2835,
+        0 if colors is None else len(colors),
+        0,
+    )
+    if colors is not None:
+        table = bytearray(4 * len(colors))
+        for c, i in colors.items():
+            table[i * 4] = (c >> 16) & 0xFF
+            table[i * 4 + 1] = (c >> 8) & 0xFF
+            table[i * 4 + 2] = c & 0xFF
+        header += bytes(table)
+    return header, bits
+
+# Reading any supported file
+
+def _firstimage(images):
+    if len(images) == 0:
+        raise ValueError("file has no images")
+    return images[0]
+
+def readimage(f, limits=None):
+    # Reads an image from a file in any format supported by the readers in
+    # this script, determined from the file's contents (or, for TGA files,
+    # which have no signature, from the file name's extension).  For icon and
+    # cursor files, the first image is read; for animated GIF files, the
+    # first frame.  Returns a dictionary with the keys "image", "width", and
+    # "height", and other keys depending on the reader used.
+    ff = open(f, "rb")
+    head = ff.read(6)
+    ff.close()
+    if head[0:2] == b"BM" or head[0:2] == b"BA":
+        return readbitmaparray(f, limits=limits)
+    if head[0:4] == b"GIF8":
+        return readgif(f, limits)
+    if head[0:4] == b"\x59\xa6\x6a\x95":
+        return readsunraster(f, limits)
+    if head[0:4] == b"RIFF":
+        return _firstimage(readani(f, limits))
+    # Checked before icons, since a TGA file's header can start with the
+    # same bytes as an icon or cursor file's
+    if str(f).lower().endswith(".tga"):
+        return readtga(f, limits)
+    if head[0:4] == b"\0\0\1\0" or head[0:4] == b"\0\0\2\0":
+        return _firstimage(readico(f, limits))
+    raise ValueError("unsupported file format")
+
+def readimagedirectory(directory, limits=None):
+    # Reads the images in all files in the given directory that readimage()
+    # supports, in order by file name, skipping other files.
+    # Returns a list of dictionaries as returned by readimage(), each with
+    # the additional key "file", the file's path.
+    ret = []
+    for name in sorted(os.listdir(directory)):
+        path = os.path.join(directory, name)
+        if not os.path.isfile(path):
+            continue
+        try:
+            im = readimage(path, limits)
+        except ValueError as e:
+            _logger.warning("skipping %s: %s", path, e)
+            continue
+        im["file"] = path
+        ret.append(im)
+    return ret
+
+# Windows resources
+
+def _resname(name):
+    # Encodes a resource type or name, which is either an integer
+    # from 1 through 65535 or a string
+    if isinstance(name, int):
+        if name <= 0 or name > 0xFFFF:
+            raise ValueError
+        return struct.pack("<HH", 0xFFFF, name)
+    if len(name) == 0 or name[0] == "#":
+        raise ValueError
+    return bytes(name.upper(), "utf-16le") + b"\0\0"
+
+def _resentry(restype, name, data):
+    # Generates a resource in a 32-bit resource (.res) file
+    header = _resname(restype) + _resname(name)
+    if len(header) % 4 != 0:
+        header += b"\0\0"
+    # data version, memory flags (moveable, pure, discardable), language
+    # (U.S. English), version, characteristics
+    header += struct.pack("<LHHLL", 0, 0x1030, 0x0409, 0, 0)
+    ret = struct.pack("<LL", len(data), len(header) + 8) + header + data
+    return ret + b"\0" * ((4 - len(data) % 4) % 4)
+
+def writeres(f, bitmaps=None, icons=None, raiseIfExists=False):
+    # Writes a 32-bit Windows resource (.res) file, which can be linked
+    # into a Windows program, holding bitmaps and icons.
+    # 'bitmaps' is a list of dictionaries, each with the keys "image",
+    # "width", "height", and "id", where "id" is the resource's identifier
+    # (an integer from 1 through 65535, or a string).
+    # 'icons' is a list of dictionaries, each with the keys "icons", a list
+    # of the icon's images as in writeico(), and "id", the icon's identifier.
+    # The file begins with an empty resource
+    out = struct.pack("<LLHHHH", 0, 32, 0xFFFF, 0, 0xFFFF, 0) + b"\0" * 16
+    for bm in bitmaps if bitmaps else []:
+        header, bits = _bmpinfoheader(bm["image"], bm["width"], bm["height"])
+        out += _resentry(2, bm["id"], header + bits)  # RT_BITMAP
+    iconid = 1
+    for group in icons if icons else []:
+        if (not group["icons"]) or len(group["icons"]) == 0:
+            raise ValueError
+        grp = struct.pack("<HHH", 0, 1, len(group["icons"]))
+        for icon in group["icons"]:
+            data, bitcount, numcolors = _iconimage(icon)
+            out += _resentry(3, iconid, data)  # RT_ICON
+            grp += struct.pack(
+                "<BBBBHHLH",
+                icon["width"] & 0xFF,
+                icon["height"] & 0xFF,
+                numcolors,
+                0,
+                1,
+                bitcount,
+                len(data),
+                iconid,
+            )
+            iconid += 1
+        out += _resentry(14, group["id"], grp)  # RT_GROUP_ICON
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(out)
+    ff.close()
+
+def writerc(f, resources, raiseIfExists=False):
+    # Writes a resource script (.rc) that refers to bitmap and icon files
+    # (such as those written by writebmp() and writeico()), to be
+    # included in a Windows program's resource script.
+    # 'resources' is a list of three-item lists, each giving the resource's
+    # identifier (an integer or a name), its kind ("BITMAP" or "ICON"), and the
+    # path to the file.
+    lines = []
+    for resid, kind, path in resources:
+        if kind != "BITMAP" and kind != "ICON":
+            raise ValueError
+        if '"' in path or "\n" in path:
+            raise ValueError
+        if isinstance(resid, str) and (len(resid) == 0 or (not resid.isidentifier())):
+            raise ValueError
+        lines.append('%s %s "%s"' % (resid, kind, path.replace("\\", "\\\\")))
+    lines.append("")
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(bytes("\r\n".join(lines), "utf-8"))
+    ff.close()
+
+# Video files
+#
+# These methods write a sequence of frames (such as successive frames
+# of an animated wallpaper) as a video.  Each frame is an image of the
+# same width and height.
+
+def _checkframes(frames, width, height):
+    frames = list(frames)
+    if len(frames) == 0 or width <= 0 or height <= 0:
+        raise ValueError
+    for frame in frames:
+        if len(frame) != width * height * 3:
+            raise ValueError
+    return frames
+
+def writey4m(f, frames, width, height, fps=30, raiseIfExists=False):
+    # Writes a sequence of frames as a YUV4MPEG2 (.y4m) video, an
+    # uncompressed format read by many video encoders.
+    # 'frames' is a list (or other iterable) of images.
+    # 'fps' is the number of frames per second.
+    if fps <= 0 or int(fps) != fps:
+        raise ValueError
+    frames = _checkframes(frames, width, height)
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(
+        bytes(
+            "YUV4MPEG2 W%d H%d F%d:1 Ip A1:1 C444\n" % (width, height, fps),
+            "utf-8",
+        )
+    )
+    for frame in frames:
+        ff.write(b"FRAME\n")
+        ys = bytearray(width * height)
+        us = bytearray(width * height)
+        vs = bytearray(width * height)
+        for i in range(width * height):
+            r = frame[i * 3]
+            g = frame[i * 3 + 1]
+            b = frame[i * 3 + 2]
+            # ITU-R BT.601 conversion to studio-range Y'CbCr
+            ys[i] = (16 * 256 + 66 * r + 129 * g + 25 * b + 128) >> 8
+            us[i] = (128 * 256 - 38 * r - 74 * g + 112 * b + 128) >> 8
+            vs[i] = (128 * 256 + 112 * r - 94 * g - 18 * b + 128) >> 8
+        ff.write(bytes(ys))
+        ff.write(bytes(us))
+        ff.write(bytes(vs))
+    ff.close()
+
+def writeavi(f, frames, width, height, fps=30, raiseIfExists=False):
+    # Writes a sequence of frames as an uncompressed 24-bit AVI video.
+    # 'frames' is a list (or other iterable) of images.
+    # 'fps' is the number of frames per second.
+    if fps <= 0 or int(fps) != fps:
+        raise ValueError
+    frames = _checkframes(frames, width, height)
+    framesize = ((width * 3 + 3) // 4) * 4 * height
+    avih = struct.pack(
+        "<LLLLLLLLLLLLLL",
+        1000000 // fps,  # microseconds per frame
+        framesize * fps,  # maximum bytes per second
+        0,
+        0x10,  # has index
+        len(frames),
+        0,
+        1,  # number of streams
+        framesize,
+        width,
+        height,
+        0,
+        0,
+        0,
+        0,
+    )
+    strh = b"vids" + b"DIB " + struct.pack(
+        "<LHHLLLLLLLLhhhh",
+        0,
+        0,
+        0,
+        0,
+        1,  # time scale
+        fps,  # frame rate in units of the time scale
+        0,
+        len(frames),
+        framesize,
+        0xFFFFFFFF,  # default quality
+        framesize,
+        0,
+        0,
+        width,
+        height,
+    )
+    strf = struct.pack(
+        "<LllHHLLllLL", 40, width, height, 1, 24, 0, framesize, 0, 0, 0, 0
+    )
+    strl = b"strl" + _riffchunk(b"strh", strh) + _riffchunk(b"strf", strf)
+    hdrl = b"hdrl" + _riffchunk(b"avih", avih) + _riffchunk(b"LIST", strl)
+    movi = bytearray(b"movi")
+    idx1 = bytearray()
+    for frame in frames:
+        bitcount, bits = _bmpbits(frame, width, height, None)
+        idx1 += b"00db" + struct.pack("<LLL", 0x10, len(movi), len(bits))
+        movi += _riffchunk(b"00db", bits)
+    body = b"AVI " + _riffchunk(b"LIST", hdrl) + _riffchunk(b"LIST", bytes(movi))
+    body += _riffchunk(b"idx1", bytes(idx1))
+    ff = open(f, "xb" if raiseIfExists else "wb")
+    ff.write(_riffchunk(b"RIFF", body))
+    ff.close()
@@ -0,0 +1,57 @@
+import random
+import unittest
+
+import desktopwallpaper as dw
+
+def _colors(image):
+    return set(tuple(image[i : i + 3]) for i in range(0, len(image), 3))
+
+def _gradient(width, height):
+    image = []
+    for y in range(height):
+        for x in range(width):
+            image += [x * 255 // (width - 1), y * 255 // (height - 1), 128]
+    return image
+
+class DitherTest(unittest.TestCase):
+    def assertInPalette(self, image, palette):
+        extra = _colors(image) - set(tuple(c) for c in palette)
+        self.assertEqual(extra, set())
+
+    def test_dither_functions(self):
+        for palette in [dw.classiccolors(), dw.websafecolors(), dw.egacolors()]:
+            image = _gradient(32, 24)
+            dw.floydSteinbergDither(image, 32, 24, palette)
+            self.assertInPalette(image, palette)
+            image = _gradient(32, 24)
+            dw.patternDither(image, 32, 24, palette)
+            self.assertInPalette(image, palette)
+            grays = dw.getgrays(palette)
+            image = _gradient(32, 24)
+            dw.dithertograyimage(image, 32, 24, grays)
+            self.assertInPalette(image, [[g, g, g] for g in grays])
+
+    def test_randomwallpaper(self):
+        # Every way randomwallpaper() reduces an image to a palette gives
+        # only that palette's colors, for each style
+        styles = ["hatch", "boxes", "brushednoise", "check", "pattern"]
+        for dither in ["halfhalf", "gray", "pattern", "floydsteinberg"]:
+            for style in styles:
+                for palette in ["vga", "websafe"]:
+                    random.seed(dither + style + palette)
+                    wp = dw.randomwallpaper(palette, style, dither=dither)
+                    self.assertEqual(wp["dither"], dither)
+                    pal = dw._descpalette(palette)
+                    if dither == "gray":
+                        pal = [[g, g, g] for g in dw.getgrays(pal)]
+                    self.assertInPalette(wp["image"], pal)
+
+    def test_default_dither(self):
+        for style in ["boxes", "brushednoise"]:
+            random.seed(style)
+            wp = dw.randomwallpaper(style=style)
+            pal = dw.websafecolors() if style == "brushednoise" else None
+            self.assertInPalette(wp["image"], pal if pal else dw.classiccolors())
+
+if __name__ == "__main__":
+    unittest.main()
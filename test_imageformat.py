@@ -0,0 +1,67 @@
+import os
+import struct
+import tempfile
+import unittest
+
+import imageformat
+
+def _cursor(color):
+    return {"image": color * (32 * 32), "width": 32, "height": 32}
+
+class ReadAniTest(unittest.TestCase):
+    def setUp(self):
+        self.dir = tempfile.TemporaryDirectory()
+        self.path = os.path.join(self.dir.name, "test.ani")
+        imageformat.writeani(self.path, [_cursor([255, 0, 0]), _cursor([0, 0, 255])])
+        ff = open(self.path, "rb")
+        self.data = ff.read()
+        ff.close()
+
+    def tearDown(self):
+        self.dir.cleanup()
+
+    def _write(self, data):
+        ff = open(self.path, "wb")
+        ff.write(data)
+        ff.close()
+
+    def test_valid(self):
+        self.assertEqual(len(imageformat.readani(self.path)), 2)
+
+    def test_huge_step_count(self):
+        # The number of steps is at offset 8 of the "anih" chunk's contents
+        data = bytearray(self.data)
+        data[28:32] = struct.pack("<L", 0x7FFFFFFF)
+        self._write(bytes(data))
+        self.assertRaises(ValueError, imageformat.readani, self.path)
+
+    def test_truncated(self):
+        for size in range(0, len(self.data), 97):
+            self._write(self.data[:size])
+            self.assertRaises(ValueError, imageformat.readani, self.path)
+
+class ReadImageTest(unittest.TestCase):
+    def test_no_images(self):
+        # A valid icon header with no entries is skipped, not a fatal error
+        d = tempfile.TemporaryDirectory()
+        path = os.path.join(d.name, "empty.ico")
+        ff = open(path, "wb")
+        ff.write(b"\0\0\1\0\0\0")
+        ff.close()
+        self.assertRaises(ValueError, imageformat.readimage, path)
+        self.assertEqual(imageformat.readimagedirectory(d.name), [])
+        d.cleanup()
+
+class AnimatedGifWriterTest(unittest.TestCase):
+    def test_error_before_frames(self):
+        # An error in the 'with' statement isn't hidden by the error for
+        # an animation with no frames
+        d = tempfile.TemporaryDirectory()
+        path = os.path.join(d.name, "test.gif")
+        with self.assertRaises(KeyError):
+            with imageformat.AnimatedGifWriter(path, 8, 8):
+                raise KeyError
+        d.cleanup()
+
+if __name__ == "__main__":
+    unittest.main()